@@ -2,31 +2,31 @@ use diskcache_rs::Client;
 
 #[tokio::main]
 async fn main() {
-    let mut store = Client::new("db", 4);
+    let mut store = Client::new("db", 4).unwrap();
     let keys = ["hey", "hi", "yoo-hoo", "bonjour"].to_vec();
     let values = ["English", "English", "Slang", "French"].to_vec();
 
     // Setting the values
     println!("[Inserting key-value pairs]");
     for (k, v) in keys.clone().into_iter().zip(values) {
-        let _ = store.set(k.to_string(), v.to_string()).await;
+        let _ = store.set_str(k, v).await;
     }
 
     // Getting the values
     println!("[After insert]");
     for k in keys.clone() {
-        let got = store.get(k).await.unwrap();
+        let got = store.get_str(k).await.unwrap();
         println!("For key: {:?}, Got: {:?}", k, got);
     }
 
     // Deleting some values
     for k in &keys[2..] {
-        let removed = store.delete(*k).await;
+        let removed = store.delete(k).await;
         println!("Removed: key: {:?}, resp: {:?}", k, removed);
     }
 
     for k in &keys {
-        let got = store.get(*k).await;
+        let got = store.get_str(k).await;
         println!("[After delete: For key: {:?}, Got: {:?}", k, got);
     }
 
@@ -36,7 +36,7 @@ async fn main() {
 
     println!("[After clear]");
     for k in &keys {
-        let got = store.get(*k).await;
+        let got = store.get_str(k).await;
         println!("For key: {:?}, Got: {:?}", k, got);
     }
     store.close().await;
@@ -0,0 +1,375 @@
+use crate::store::{CompactReport, Entry, Retry, StoreOptions};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use tokio::io;
+
+/// Where a store persists its entries. The store never works on one key from two
+/// actions at once, so implementations only have to keep their own state consistent
+#[async_trait]
+pub(crate) trait StorageBackend: Send + Sync {
+    /// Persists `entry` as the value of `key`, replacing any previous one
+    async fn save(&self, key: &str, entry: &Entry) -> io::Result<()>;
+
+    /// Reads the entry of `key`, expired or not
+    async fn load(&self, key: &str) -> io::Result<Option<Entry>>;
+
+    /// Checks whether an unexpired entry is persisted for `key`
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        Ok(matches!(self.load(key).await?, Some(e) if !e.is_expired()))
+    }
+
+    /// Deletes the entry of `key`, failing with `NotFound` if there is none
+    async fn remove(&self, key: &str) -> io::Result<()>;
+
+    /// Deletes every entry
+    async fn clear(&self) -> io::Result<()>;
+
+    /// Lists the keys of every persisted entry, expired ones included
+    async fn keys(&self) -> Vec<String>;
+
+    /// Reads every persisted entry, skipping those that cannot be read. Called
+    /// only while the store opens, before any other method. It may block, so
+    /// [`Client::reopen`](crate::Client::reopen) opens the store on the blocking pool
+    fn load_all(&self) -> Vec<(String, Entry)>;
+
+    /// Flushes everything persisted so far to disk
+    async fn sync(&self) -> io::Result<()>;
+
+    /// Recreates whatever files of its own were removed from the store directory,
+    /// once the directory itself is back
+    async fn repair(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Reclaims the disk space that writes and deletes left behind. Called while
+    /// no other method runs
+    async fn compact(&self) -> io::Result<CompactReport> {
+        Ok(CompactReport::default())
+    }
+}
+
+/// Keeps each entry in a file of its own, named after its key
+pub(crate) struct FileBackend {
+    store_path: String,
+    /// `store_path` with symlinks resolved, which every key's file must lie under
+    root: PathBuf,
+    options: StoreOptions,
+}
+
+impl FileBackend {
+    /// Fails with `InvalidInput` if the file extension of `options` is empty or
+    /// holds anything but ASCII letters and digits, or if `store_path` cannot be
+    /// resolved, as when it does not exist
+    pub(crate) fn new(store_path: &str, options: &StoreOptions) -> io::Result<FileBackend> {
+        if let Some(extension) = &options.file_extension {
+            if extension.is_empty() || !extension.bytes().all(|b| b.is_ascii_alphanumeric()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{:?} is not a valid file extension", extension),
+                ));
+            }
+        }
+
+        Ok(FileBackend {
+            store_path: store_path.to_string(),
+            root: std::fs::canonicalize(store_path)?,
+            options: options.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl StorageBackend for FileBackend {
+    async fn save(&self, key: &str, entry: &Entry) -> io::Result<()> {
+        crate::fs::save_to_file(&self.root, key, entry, &self.options).await
+    }
+
+    async fn load(&self, key: &str) -> io::Result<Option<Entry>> {
+        crate::fs::get_from_file(&self.root, key, &self.options).await
+    }
+
+    /// Reads no further than the expiry header
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        crate::fs::exists_in_file(&self.root, key, &self.options).await
+    }
+
+    async fn remove(&self, key: &str) -> io::Result<()> {
+        crate::fs::remove_from_file(&self.root, key, &self.options).await
+    }
+
+    async fn clear(&self) -> io::Result<()> {
+        crate::fs::clear_from_file(&self.store_path, &self.options).await
+    }
+
+    async fn keys(&self) -> Vec<String> {
+        crate::fs::keys_from_file(&self.store_path, &self.options).await
+    }
+
+    fn load_all(&self) -> Vec<(String, Entry)> {
+        crate::fs::load_all_from_file(&self.store_path, &self.options)
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        crate::fs::sync_store(&self.store_path, &self.options).await
+    }
+
+    /// Removes the temporary files of writes cut short by a crash, and the shard
+    /// directories that deletes left empty
+    async fn compact(&self) -> io::Result<CompactReport> {
+        crate::fs::compact_store(&self.store_path, &self.options).await
+    }
+}
+
+/// Keeps every entry in memory alone, so that nothing outlives the store. Entries
+/// evicted from the store's own memory by `max_entries` or `max_bytes` stay here
+#[derive(Default)]
+pub(crate) struct MemoryBackend {
+    entries: DashMap<String, Entry>,
+}
+
+#[async_trait]
+impl StorageBackend for MemoryBackend {
+    async fn save(&self, key: &str, entry: &Entry) -> io::Result<()> {
+        self.entries.insert(key.to_string(), entry.clone());
+        Ok(())
+    }
+
+    async fn load(&self, key: &str) -> io::Result<Option<Entry>> {
+        Ok(self.entries.get(key).map(|e| e.clone()))
+    }
+
+    async fn remove(&self, key: &str) -> io::Result<()> {
+        match self.entries.remove(key) {
+            Some(_) => Ok(()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no such key")),
+        }
+    }
+
+    async fn clear(&self) -> io::Result<()> {
+        self.entries.clear();
+        Ok(())
+    }
+
+    async fn keys(&self) -> Vec<String> {
+        self.entries.iter().map(|e| e.key().clone()).collect()
+    }
+
+    fn load_all(&self) -> Vec<(String, Entry)> {
+        Vec::new()
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Retries the saves and removals of another backend that fail with a transient
+/// error, backing off exponentially; see [`Retry`]
+pub(crate) struct RetryingBackend {
+    inner: Box<dyn StorageBackend>,
+    retry: Retry,
+}
+
+impl RetryingBackend {
+    pub(crate) fn new(inner: Box<dyn StorageBackend>, retry: Retry) -> RetryingBackend {
+        RetryingBackend { inner, retry }
+    }
+
+    /// `key` names the key being written, for tracing
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    async fn with_retries<'a, F, Fut>(&'a self, key: &str, mut operation: F) -> io::Result<()>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = io::Result<()>> + 'a,
+    {
+        let mut delay = self.retry.base_delay;
+        for _ in 0..self.retry.max_retries {
+            match operation().await {
+                Err(e) if is_transient(&e) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(key, error = %e, "retrying after a transient error");
+                    tokio::time::sleep(delay).await;
+                    delay = delay.saturating_mul(2);
+                }
+                done => return done,
+            }
+        }
+
+        operation().await
+    }
+}
+
+/// Errors that a network filesystem may raise for a moment under load
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::ResourceBusy
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+    )
+}
+
+#[async_trait]
+impl StorageBackend for RetryingBackend {
+    async fn save(&self, key: &str, entry: &Entry) -> io::Result<()> {
+        self.with_retries(key, || self.inner.save(key, entry)).await
+    }
+
+    async fn load(&self, key: &str) -> io::Result<Option<Entry>> {
+        self.inner.load(key).await
+    }
+
+    async fn exists(&self, key: &str) -> io::Result<bool> {
+        self.inner.exists(key).await
+    }
+
+    async fn remove(&self, key: &str) -> io::Result<()> {
+        self.with_retries(key, || self.inner.remove(key)).await
+    }
+
+    async fn clear(&self) -> io::Result<()> {
+        self.inner.clear().await
+    }
+
+    async fn keys(&self) -> Vec<String> {
+        self.inner.keys().await
+    }
+
+    fn load_all(&self) -> Vec<(String, Entry)> {
+        self.inner.load_all()
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        self.inner.sync().await
+    }
+
+    async fn repair(&self) -> io::Result<()> {
+        self.inner.repair().await
+    }
+
+    async fn compact(&self) -> io::Result<CompactReport> {
+        self.inner.compact().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// Fails its first `failures` saves and removals with `kind`
+    struct FlakyBackend {
+        inner: MemoryBackend,
+        failures: usize,
+        kind: io::ErrorKind,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl FlakyBackend {
+        fn fail(&self) -> io::Result<()> {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.failures {
+                return Err(io::Error::new(self.kind, "flaky"));
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl StorageBackend for FlakyBackend {
+        async fn save(&self, key: &str, entry: &Entry) -> io::Result<()> {
+            self.fail()?;
+            self.inner.save(key, entry).await
+        }
+
+        async fn load(&self, key: &str) -> io::Result<Option<Entry>> {
+            self.inner.load(key).await
+        }
+
+        async fn remove(&self, key: &str) -> io::Result<()> {
+            self.fail()?;
+            self.inner.remove(key).await
+        }
+
+        async fn clear(&self) -> io::Result<()> {
+            self.inner.clear().await
+        }
+
+        async fn keys(&self) -> Vec<String> {
+            self.inner.keys().await
+        }
+
+        fn load_all(&self) -> Vec<(String, Entry)> {
+            Vec::new()
+        }
+
+        async fn sync(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn flaky(
+        failures: usize,
+        kind: io::ErrorKind,
+        max_retries: u32,
+    ) -> (RetryingBackend, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let inner = FlakyBackend {
+            inner: MemoryBackend::default(),
+            failures,
+            kind,
+            calls: calls.clone(),
+        };
+        let retry = Retry {
+            max_retries,
+            base_delay: Duration::from_millis(1),
+        };
+        (RetryingBackend::new(Box::new(inner), retry), calls)
+    }
+
+    fn entry(value: &str) -> Entry {
+        Entry {
+            value: value.as_bytes().into(),
+            expires_at: None,
+            idle_ttl: None,
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn transient_errors_are_retried_until_they_pass() {
+        let (backend, calls) = flaky(2, io::ErrorKind::ResourceBusy, 3);
+        backend.save("hey", &entry("English")).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let loaded = backend.load("hey").await.unwrap().unwrap();
+        assert_eq!(&*loaded.value, b"English");
+
+        // the removal fails twice as well
+        calls.store(0, Ordering::SeqCst);
+        backend.remove("hey").await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(backend.load("hey").await.unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn retries_give_up_after_max_retries() {
+        let (backend, calls) = flaky(5, io::ErrorKind::ResourceBusy, 2);
+        let err = backend.save("hey", &entry("English")).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ResourceBusy);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        assert!(backend.load("hey").await.unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn permanent_errors_fail_fast() {
+        for kind in [io::ErrorKind::PermissionDenied, io::ErrorKind::NotFound] {
+            let (backend, calls) = flaky(1, kind, 3);
+            let err = backend.save("hey", &entry("English")).await.unwrap_err();
+            assert_eq!(err.kind(), kind);
+            assert_eq!(calls.load(Ordering::SeqCst), 1);
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::builder::ClientBuilder;
+use crate::client::Client;
+use crate::error::CacheError;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::io;
+use tokio::runtime::{Handle, Runtime};
+
+/// A [`Client`] for code that is not async. It runs the store on a runtime of its
+/// own and blocks the calling thread until each operation is done.
+///
+/// Blocking inside an async task would stall the runtime running it, so every
+/// operation called from within one fails with [`CacheError::NestedRuntime`], and
+/// building the client there fails too. Use [`Client`] from async code instead
+///
+/// ```no_run
+/// # fn run() -> Result<(), Box<dyn std::error::Error>> {
+/// let client = diskcache_rs::BlockingClient::new("db", 4)?;
+/// client.set_str("hey", "English")?;
+/// assert_eq!(client.get_str("hey")?.as_deref(), Some("English"));
+/// # Ok(())
+/// # }
+/// ```
+pub struct BlockingClient {
+    client: Client,
+    /// Only taken when the client is dropped
+    runtime: Option<Runtime>,
+}
+
+impl BlockingClient {
+    pub fn new(store_path: &str, num_of_workers: usize) -> io::Result<BlockingClient> {
+        ClientBuilder::new()
+            .path(store_path)
+            .workers(num_of_workers)
+            .build_blocking()
+    }
+
+    pub(crate) fn open(builder: ClientBuilder) -> io::Result<BlockingClient> {
+        if Handle::try_current().is_ok() {
+            return Err(io::Error::other(
+                "a blocking client cannot be built from within an async runtime",
+            ));
+        }
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()?;
+        // the workers of the store are spawned on the runtime entered here
+        let client = {
+            let _entered = runtime.enter();
+            builder.build()?
+        };
+
+        Ok(BlockingClient {
+            client,
+            runtime: Some(runtime),
+        })
+    }
+
+    pub fn set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<Option<Arc<[u8]>>, CacheError> {
+        self.block_on(self.client.set(key, value))?
+    }
+
+    pub fn set_str(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Option<String>, CacheError> {
+        self.block_on(self.client.set_str(key, value))?
+    }
+
+    pub fn get(&self, key: impl AsRef<str>) -> Result<Option<Arc<[u8]>>, CacheError> {
+        self.block_on(self.client.get(key))?
+    }
+
+    pub fn get_str(&self, key: impl AsRef<str>) -> Result<Option<String>, CacheError> {
+        self.block_on(self.client.get_str(key))?
+    }
+
+    pub fn delete(&self, key: impl AsRef<str>) -> Result<Option<Arc<[u8]>>, CacheError> {
+        self.block_on(self.client.delete(key))?
+    }
+
+    pub fn clear(&self) -> Result<(), CacheError> {
+        self.block_on(self.client.clear())?
+    }
+
+    /// Finishes the operations already queued, then stops the workers and lets go
+    /// of the store path; see [`Client::close`]
+    pub fn close(mut self) -> Result<(), CacheError> {
+        if Handle::try_current().is_ok() {
+            return Err(CacheError::NestedRuntime);
+        }
+
+        let runtime = self.runtime.as_ref().unwrap();
+        runtime.block_on(self.client.close());
+        Ok(())
+    }
+
+    fn block_on<F: Future>(&self, operation: F) -> Result<F::Output, CacheError> {
+        if Handle::try_current().is_ok() {
+            return Err(CacheError::NestedRuntime);
+        }
+
+        Ok(self.runtime.as_ref().unwrap().block_on(operation))
+    }
+}
+
+/// Stops the runtime without waiting for it, which unlike dropping it is allowed
+/// within an async runtime. The workers are aborted as when a [`Client`] is dropped
+impl Drop for BlockingClient {
+    fn drop(&mut self) {
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    const STORE_PATH: &str = "client_db";
+    const KEYS: [&str; 4] = ["hey", "hi", "yoo-hoo", "bonjour"];
+    const VALUES: [&str; 4] = ["English", "English", "Slang", "French"];
+
+    #[test]
+    #[serial]
+    fn blocking_client_works_without_a_runtime() {
+        let client = BlockingClient::new(STORE_PATH, 2).unwrap();
+        client.clear().unwrap();
+
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            client.set_str(k.to_string(), v.to_string()).unwrap();
+        }
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            assert_eq!(client.get_str(k).unwrap(), Some(v.to_string()));
+        }
+
+        let removed = client.delete(KEYS[0]).unwrap();
+        assert_eq!(removed.as_deref(), Some(VALUES[0].as_bytes()));
+        assert_eq!(client.get(KEYS[0]).unwrap(), None);
+        client.close().unwrap();
+
+        // what was set outlives the client
+        let client = BlockingClient::new(STORE_PATH, 2).unwrap();
+        assert_eq!(
+            client.get_str(KEYS[1]).unwrap(),
+            Some(VALUES[1].to_string())
+        );
+        client.clear().unwrap();
+        assert_eq!(client.get(KEYS[1]).unwrap(), None);
+        client.close().unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn blocking_client_refuses_to_run_inside_a_runtime() {
+        let client = BlockingClient::new(STORE_PATH, 2).unwrap();
+
+        let runtime = Runtime::new().unwrap();
+        let result = runtime.block_on(async { client.get(KEYS[0]) });
+        assert!(matches!(result, Err(CacheError::NestedRuntime)));
+        let built = runtime.block_on(async { BlockingClient::new("other_db", 2) });
+        assert!(built.is_err());
+        assert!(!std::path::Path::new("other_db").exists());
+
+        // a client dropped within a runtime does not take it down
+        runtime.block_on(async move {
+            assert!(matches!(client.close(), Err(CacheError::NestedRuntime)));
+        });
+    }
+}
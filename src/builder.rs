@@ -0,0 +1,274 @@
+use crate::blocking::BlockingClient;
+use crate::client::Client;
+use crate::store::{
+    Compression, Durability, Eviction, EvictionListener, Format, Layout, Loader, LoaderFuture,
+    Retry, ShardHasher, Storage, StoreOptions,
+};
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+use tokio::io;
+
+/// Number of workers when the number of CPU cores cannot be told
+const FALLBACK_NUM_OF_WORKERS: usize = 4;
+const DEFAULT_CHANNEL_CAPACITY: usize = 10;
+
+/// Configures and builds a [`Client`]
+///
+/// ```no_run
+/// # async fn run() -> std::io::Result<()> {
+/// let client = diskcache_rs::ClientBuilder::new()
+///     .path("db")
+///     .workers(4)
+///     .preload(true)
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClientBuilder {
+    store_path: Option<String>,
+    num_of_workers: usize,
+    channel_capacity: usize,
+    options: StoreOptions,
+}
+
+impl ClientBuilder {
+    pub fn new() -> ClientBuilder {
+        ClientBuilder {
+            store_path: None,
+            num_of_workers: std::thread::available_parallelism()
+                .map_or(FALLBACK_NUM_OF_WORKERS, |n| n.get()),
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            options: StoreOptions::default(),
+        }
+    }
+
+    /// Directory the values are persisted in. Required unless the store is in memory
+    pub fn path(mut self, store_path: &str) -> ClientBuilder {
+        self.store_path = Some(store_path.to_string());
+        self
+    }
+
+    /// Number of worker tasks handling operations; must be at least 1. Defaults to
+    /// the number of CPU cores
+    pub fn workers(mut self, num_of_workers: usize) -> ClientBuilder {
+        self.num_of_workers = num_of_workers;
+        self
+    }
+
+    /// Number of operations that can be queued for the workers. Must be at least 1.
+    ///
+    /// Once this many operations are waiting, every further call on the client
+    /// waits for a worker to take one off the queue before it is sent, rather than
+    /// failing. A bigger queue absorbs bursts of operations at the cost of memory
+    pub fn channel_capacity(mut self, channel_capacity: usize) -> ClientBuilder {
+        self.channel_capacity = channel_capacity;
+        self
+    }
+
+    /// See [`StoreOptions::preload`]
+    pub fn preload(mut self, preload: bool) -> ClientBuilder {
+        self.options.preload = preload;
+        self
+    }
+
+    /// See [`StoreOptions::durability`]
+    pub fn durability(mut self, durability: Durability) -> ClientBuilder {
+        self.options.durability = durability;
+        self
+    }
+
+    /// Retries sets and deletes that hit a transient filesystem error up to
+    /// `max_retries` times, backing off from `base_delay`; see [`Retry`]
+    pub fn retry(mut self, max_retries: u32, base_delay: Duration) -> ClientBuilder {
+        self.options.retry = Retry {
+            max_retries,
+            base_delay,
+        };
+        self
+    }
+
+    /// See [`StoreOptions::max_entries`]
+    pub fn max_entries(mut self, max_entries: usize) -> ClientBuilder {
+        self.options.max_entries = Some(max_entries);
+        self
+    }
+
+    /// See [`StoreOptions::max_bytes`]
+    pub fn max_bytes(mut self, max_bytes: usize) -> ClientBuilder {
+        self.options.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// See [`StoreOptions::max_value_bytes`]
+    pub fn max_value_bytes(mut self, max_value_bytes: usize) -> ClientBuilder {
+        self.options.max_value_bytes = Some(max_value_bytes);
+        self
+    }
+
+    /// See [`StoreOptions::eviction`]
+    pub fn eviction(mut self, eviction: Eviction) -> ClientBuilder {
+        self.options.eviction = eviction;
+        self
+    }
+
+    /// See [`StoreOptions::compression`]
+    pub fn compression(mut self, compression: Compression) -> ClientBuilder {
+        self.options.compression = compression;
+        self
+    }
+
+    /// See [`Layout`]
+    pub fn layout(mut self, layout: Layout) -> ClientBuilder {
+        self.options.layout = layout;
+        self
+    }
+
+    /// See [`StoreOptions::shard_hasher`], and [`ShardHasher`] before changing it
+    /// for an existing store
+    pub fn shard_hasher(mut self, shard_hasher: ShardHasher) -> ClientBuilder {
+        self.options.shard_hasher = shard_hasher;
+        self
+    }
+
+    /// See [`StoreOptions::file_extension`]
+    pub fn file_extension(mut self, file_extension: &str) -> ClientBuilder {
+        self.options.file_extension = Some(file_extension.to_string());
+        self
+    }
+
+    /// See [`StoreOptions::dir_mode`]
+    pub fn dir_mode(mut self, mode: u32) -> ClientBuilder {
+        self.options.dir_mode = Some(mode);
+        self
+    }
+
+    /// See [`StoreOptions::file_mode`]
+    pub fn file_mode(mut self, mode: u32) -> ClientBuilder {
+        self.options.file_mode = Some(mode);
+        self
+    }
+
+    /// See [`StoreOptions::format`]
+    pub fn format(mut self, format: Format) -> ClientBuilder {
+        self.options.format = format;
+        self
+    }
+
+    /// See [`StoreOptions::loader`]
+    pub fn loader(
+        mut self,
+        load: impl Fn(&str) -> LoaderFuture + Send + Sync + 'static,
+    ) -> ClientBuilder {
+        self.options.loader = Some(Loader::new(load));
+        self
+    }
+
+    /// See [`StoreOptions::on_evict`]
+    pub fn on_evict(
+        mut self,
+        on_evict: impl Fn(&str, &str) + Send + Sync + 'static,
+    ) -> ClientBuilder {
+        self.options.on_evict = Some(EvictionListener::new(on_evict));
+        self
+    }
+
+    /// See [`StoreOptions::idle_ttl`]
+    pub fn idle_ttl(mut self, idle_ttl: Duration) -> ClientBuilder {
+        self.options.idle_ttl = Some(idle_ttl);
+        self
+    }
+
+    /// See [`StoreOptions::timeout`]
+    pub fn timeout(mut self, timeout: Duration) -> ClientBuilder {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    /// See [`StoreOptions::storage`]
+    pub fn storage(mut self, storage: Storage) -> ClientBuilder {
+        self.options.storage = storage;
+        self
+    }
+
+    /// See [`StoreOptions::write_back`]
+    pub fn write_back(mut self, interval: Duration) -> ClientBuilder {
+        self.options.write_back = Some(interval);
+        self
+    }
+
+    /// See [`StoreOptions::read_only`]
+    pub fn read_only(mut self, read_only: bool) -> ClientBuilder {
+        self.options.read_only = read_only;
+        self
+    }
+
+    /// See [`StoreOptions::coalesce_sets`]
+    pub fn coalesce_sets(mut self, coalesce_sets: bool) -> ClientBuilder {
+        self.options.coalesce_sets = coalesce_sets;
+        self
+    }
+
+    /// Keeps the store in memory alone, touching no file; see [`Storage::Memory`].
+    /// No path is needed then
+    pub fn in_memory(self) -> ClientBuilder {
+        self.storage(Storage::Memory)
+    }
+
+    /// Replaces every store option at once
+    pub fn options(mut self, options: StoreOptions) -> ClientBuilder {
+        self.options = options;
+        self
+    }
+
+    /// Opens the store. Fails if the store directory cannot be created, or with
+    /// `ResourceBusy` if another client, in this process or any other, has the same
+    /// path open. Read-only clients only fail so on a client that writes, and fail
+    /// with `NotFound` instead if there is no store to open
+    pub fn build(self) -> io::Result<Client> {
+        let store_path = match self.store_path {
+            Some(v) => v,
+            None if self.options.storage == Storage::Memory => String::new(),
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "a store path is required",
+                ))
+            }
+        };
+
+        if self.num_of_workers == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "a store needs at least 1 worker",
+            ));
+        }
+
+        if self.channel_capacity == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "channel capacity must be at least 1",
+            ));
+        }
+
+        Client::open(
+            &store_path,
+            self.num_of_workers,
+            self.channel_capacity,
+            self.options,
+        )
+    }
+
+    /// Opens the store behind a [`BlockingClient`], for code that is not async.
+    /// Fails as [`build`](Self::build) does, and also if called from within an
+    /// async runtime
+    pub fn build_blocking(self) -> io::Result<BlockingClient> {
+        BlockingClient::open(self)
+    }
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        ClientBuilder::new()
+    }
+}
@@ -1,289 +1,3002 @@
-use crate::store::{Action, Store};
+use crate::builder::ClientBuilder;
+use crate::error::{CacheError, NotUtf8};
+use crate::store::{
+    Action, CacheEvent, CacheStats, CompactReport, Format, KeyMetadata, KeyValuePairs, Modifier,
+    SetOutcome, Store, StoreOptions, Values,
+};
 use core::option::Option;
-use std::io::{Error, ErrorKind};
+use dashmap::DashMap;
+use futures_util::stream::{self, Stream};
+use std::collections::HashMap;
+use std::future::Future;
+use std::ops::Deref;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::io;
-use tokio::sync::mpsc;
-use tokio::sync::oneshot;
+use tokio::sync::{broadcast, mpsc};
+use tokio::sync::{oneshot, Mutex};
 
+/// Owns the store and its workers. All the cache operations are reached through
+/// the [`ClientHandle`] it dereferences to
 pub struct Client {
-    action_sender: mpsc::Sender<Action>,
+    handle: ClientHandle,
     store: Option<Store>,
+    store_path: String,
+    num_of_workers: usize,
+    channel_capacity: usize,
+    options: StoreOptions,
+}
+
+/// A cheap, cloneable handle for sending operations to a [`Client`]'s store from many tasks.
+///
+/// Its operations are cancel-safe: a future dropped before it sent its action, as
+/// when it loses a `tokio::select!` while the store is busy, leaves the store as it
+/// was, and one dropped after sees its action through to the end, a set writing its
+/// value as if awaited. Either way the store goes on serving others
+#[derive(Clone)]
+pub struct ClientHandle {
+    action_sender: mpsc::Sender<Action>,
+    /// Keys whose value is being computed by `get_or_insert_with`
+    in_flight: Arc<DashMap<String, Arc<Mutex<()>>>>,
+    /// How the typed client encodes values
+    #[cfg_attr(not(feature = "serde"), allow(dead_code))]
+    format: Format,
+    /// How long each operation waits on the store, if not forever
+    timeout: Option<Duration>,
+}
+
+impl Client {
+    /// Opens the store at `store_path`, failing if its directory cannot be created
+    /// or the store is already in use
+    pub fn new(store_path: &str, num_of_workers: usize) -> io::Result<Client> {
+        ClientBuilder::new()
+            .path(store_path)
+            .workers(num_of_workers)
+            .build()
+    }
+
+    /// Creates a client whose store loads every persisted entry into memory up front,
+    /// so that reads of existing keys never have to fall back to disk
+    pub fn new_with_preload(store_path: &str, num_of_workers: usize) -> io::Result<Client> {
+        ClientBuilder::new()
+            .path(store_path)
+            .workers(num_of_workers)
+            .preload(true)
+            .build()
+    }
+
+    pub fn with_options(
+        store_path: &str,
+        num_of_workers: usize,
+        options: StoreOptions,
+    ) -> io::Result<Client> {
+        ClientBuilder::new()
+            .path(store_path)
+            .workers(num_of_workers)
+            .options(options)
+            .build()
+    }
+
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    pub(crate) fn open(
+        store_path: &str,
+        num_of_workers: usize,
+        channel_capacity: usize,
+        options: StoreOptions,
+    ) -> io::Result<Client> {
+        let (handle, store) = start_store(
+            store_path,
+            num_of_workers,
+            channel_capacity,
+            options.clone(),
+        )?;
+        Ok(Client {
+            handle,
+            store: Some(store),
+            store_path: store_path.to_string(),
+            num_of_workers,
+            channel_capacity,
+            options,
+        })
+    }
+
+    /// Returns a handle that can be cloned and shared across tasks
+    pub fn handle(&self) -> ClientHandle {
+        self.handle.clone()
+    }
+
+    /// Path of the store directory, as given to the builder
+    pub fn store_path(&self) -> &str {
+        &self.store_path
+    }
+
+    /// Number of workers the store was built with, running or not; see
+    /// [`workers_alive`](Self::workers_alive)
+    pub fn worker_count(&self) -> usize {
+        self.num_of_workers
+    }
+
+    /// Most operations that can be queued for the workers at once
+    pub fn channel_capacity(&self) -> usize {
+        self.channel_capacity
+    }
+
+    /// Settings the store was built with, including its size limits
+    pub fn options(&self) -> &StoreOptions {
+        &self.options
+    }
+
+    /// Returns a receiver of every change made to the store from now on. A receiver
+    /// that falls too far behind skips the oldest events and gets
+    /// `RecvError::Lagged` instead, rather than holding up the workers.
+    ///
+    /// The receiver is closed once the store is, reopened or not. Fails with
+    /// [`CacheError::StoreClosed`] if the store is closed already
+    pub fn subscribe(&mut self) -> Result<broadcast::Receiver<CacheEvent>, CacheError> {
+        match self.store.as_ref() {
+            Some(store) => Ok(store.subscribe()),
+            None => Err(CacheError::StoreClosed),
+        }
+    }
+
+    /// Counts the workers of the store that are running, or 0 once it is closed.
+    ///
+    /// An operation that panics fails with [`CacheError::ChannelClosed`] without
+    /// taking its worker down, so a count below the number of workers the client
+    /// was built with means the store is in trouble
+    pub fn workers_alive(&self) -> usize {
+        self.store.as_ref().map_or(0, Store::workers_alive)
+    }
+
+    /// Finishes the operations already queued, then stops the workers and lets go
+    /// of the store path, so that another client may open it.
+    /// Operations sent afterwards fail with [`CacheError::StoreClosed`]
+    pub async fn close(&mut self) {
+        let store = self.store.take().unwrap();
+        store.close().await;
+    }
+
+    /// Starts the store again with the settings it was first built with, closing it
+    /// first if it is still open. Values persisted before are read back as usual.
+    ///
+    /// Handles taken before the reopen stay tied to the old store, so take new ones.
+    /// Fails, leaving the client closed, if another store took the path meanwhile
+    pub async fn reopen(&mut self) -> io::Result<()> {
+        if let Some(store) = self.store.take() {
+            store.close().await;
+        }
+
+        let (store_path, options) = (self.store_path.clone(), self.options.clone());
+        let (num_of_workers, channel_capacity) = (self.num_of_workers, self.channel_capacity);
+        // opening reads the whole store through std::fs when it preloads
+        let (handle, store) = tokio::task::spawn_blocking(move || {
+            start_store(&store_path, num_of_workers, channel_capacity, options)
+        })
+        .await
+        .map_err(io::Error::other)??;
+        self.handle = handle;
+        self.store = Some(store);
+        Ok(())
+    }
+
+    /// Checks whether the store directory is there. A store kept in memory never
+    /// creates it
+    pub fn store_exists(&self) -> bool {
+        std::path::Path::new(&self.store_path).is_dir()
+    }
+
+    /// Recreates the store directory if it was removed while the store ran, along
+    /// with the lock keeping other stores off it and, in [`Storage::Log`](crate::Storage::Log),
+    /// the log. What memory still holds is written back to it; values only on disk
+    /// were lost with the directory.
+    ///
+    /// Sets recreate the directories they write to on their own, so this is only
+    /// needed to get the lock and the entries in memory back
+    pub async fn ensure_store(&mut self) -> io::Result<()> {
+        match self.store.as_mut() {
+            Some(store) => store.ensure_store(&self.store_path).await,
+            None => Err(io::Error::other("the store is closed")),
+        }
+    }
+}
+
+fn start_store(
+    store_path: &str,
+    num_of_workers: usize,
+    channel_capacity: usize,
+    options: StoreOptions,
+) -> io::Result<(ClientHandle, Store)> {
+    let (action_sender, action_receiver) = mpsc::channel(channel_capacity);
+    let handle = ClientHandle::new(action_sender, options.format, options.timeout);
+    let store = Store::with_options(action_receiver, num_of_workers, store_path, options)?;
+    Ok((handle, store))
+}
+
+/// Names the store and sums it up, without listing its entries. A closed client
+/// shows `store: None`
+impl std::fmt::Debug for Client {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Client")
+            .field("store_path", &self.store_path)
+            .field("workers", &self.num_of_workers)
+            .field("store", &self.store)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Deref for Client {
+    type Target = ClientHandle;
+
+    fn deref(&self) -> &ClientHandle {
+        &self.handle
+    }
 }
 
-impl Client {
-    pub fn new(store_path: &str, num_of_workers: usize) -> Client {
-        let (action_sender, action_receiver) = mpsc::channel(10);
-        Client {
-            action_sender,
-            store: Some(Store::new(action_receiver, num_of_workers, store_path)),
+impl ClientHandle {
+    fn new(
+        action_sender: mpsc::Sender<Action>,
+        format: Format,
+        timeout: Option<Duration>,
+    ) -> ClientHandle {
+        ClientHandle {
+            action_sender,
+            in_flight: Arc::new(DashMap::new()),
+            format,
+            timeout,
+        }
+    }
+
+    /// Returns a handle to the same store whose operations wait on it no longer
+    /// than `timeout`, or as long as it takes if `None`, whatever the client was
+    /// built with; see [`StoreOptions::timeout`]
+    pub fn with_timeout(&self, timeout: Option<Duration>) -> ClientHandle {
+        ClientHandle {
+            timeout,
+            ..self.clone()
+        }
+    }
+
+    pub async fn set(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<Option<Arc<[u8]>>, CacheError> {
+        self.send_set_action(key.into(), value.into(), None, None)
+            .await
+    }
+
+    /// Sets a value that is treated as missing once `ttl` has elapsed
+    pub async fn set_with_ttl(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+        ttl: Duration,
+    ) -> Result<Option<Arc<[u8]>>, CacheError> {
+        let expires_at = Some(SystemTime::now() + ttl);
+        self.send_set_action(key.into(), value.into(), expires_at, None)
+            .await
+    }
+
+    /// Sets a value that is treated as missing once it has not been read for
+    /// `idle_ttl`. Every get of the key restarts the countdown, and writes the
+    /// new expiry to disk; see [`StoreOptions::idle_ttl`] to make it the default
+    pub async fn set_with_idle_ttl(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+        idle_ttl: Duration,
+    ) -> Result<Option<Arc<[u8]>>, CacheError> {
+        let expires_at = Some(SystemTime::now() + idle_ttl);
+        self.send_set_action(key.into(), value.into(), expires_at, Some(idle_ttl))
+            .await
+    }
+
+    /// Sets a value like [`set`](Self::set), telling whether the key is new. Unlike
+    /// `set`, it reads the value it replaces from disk when the store does not hold
+    /// it in memory, so both `created` and `previous` cover the disk too
+    pub async fn set_detailed(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<SetOutcome, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::SetDetailed {
+            key: key.into(),
+            value: value.into(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Sets a UTF-8 string value, returning the previous value as a string
+    pub async fn set_str(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<String>,
+    ) -> Result<Option<String>, CacheError> {
+        let key = key.into();
+        let prev = self.set(key.clone(), value.into().into_bytes()).await?;
+        into_utf8(&key, prev)
+    }
+
+    /// Sets several values in one round-trip to the store, returning the
+    /// outcome for each entry in the order given
+    pub async fn set_many(
+        &self,
+        entries: Vec<(String, Vec<u8>)>,
+    ) -> Result<Vec<Result<Option<Arc<[u8]>>, CacheError>>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::SetMany { entries, resp: tx };
+        let prevs = self.send_single_record_action(action, rv).await?;
+        Ok(prevs
+            .into_iter()
+            .map(|p| p.map_err(CacheError::from))
+            .collect())
+    }
+
+    /// Sets every key-value pair of `entries` in one round-trip to the store, as
+    /// when loading a `HashMap` into it. Fails with the error of the first pair
+    /// that could not be set, the pairs before and after it being set all the same
+    pub async fn extend(
+        &self,
+        entries: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<(), CacheError> {
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| (key, value.into_bytes()))
+            .collect();
+        for outcome in self.set_many(entries).await? {
+            outcome?;
+        }
+        Ok(())
+    }
+
+    /// Gets the value at `key`. The value is shared with the store's memory rather
+    /// than copied, so repeated reads of a large value cost no allocation
+    pub async fn get(&self, key: impl AsRef<str>) -> Result<Option<Arc<[u8]>>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Get {
+            key: key.as_ref().to_string(),
+            resp: tx,
+        };
+
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Appends the value at `key` to `buf`, returning whether the key was found. A
+    /// miss leaves `buf` as it was.
+    ///
+    /// For callers that need the value in a buffer of their own, which can then be
+    /// reused across calls instead of allocating one per value
+    pub async fn get_into(
+        &self,
+        key: impl AsRef<str>,
+        buf: &mut Vec<u8>,
+    ) -> Result<bool, CacheError> {
+        match self.get(key).await? {
+            Some(value) => {
+                buf.extend_from_slice(&value);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Gets the value at `key` only if it is held in memory, never reading the disk
+    /// or calling the loader. A key evicted to disk reads as `None`, so a hot path
+    /// can rely on this not waiting on IO and fall back to [`get`](Self::get) itself
+    pub async fn get_cached(&self, key: impl AsRef<str>) -> Result<Option<Arc<[u8]>>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::GetCached {
+            key: key.as_ref().to_string(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Returns what the store has seen of `key` since it opened, or `None` if the
+    /// key is missing or has expired. Reading it does not count as an access.
+    /// Only keys held in memory are followed, so one held only on disk, e.g. once
+    /// evicted, has blank metadata
+    pub async fn metadata(&self, key: impl AsRef<str>) -> Result<Option<KeyMetadata>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Meta {
+            key: key.as_ref().to_string(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Gets the value at `key` like [`get`](Self::get), but without counting as a use
+    /// of the key: its place in line for eviction and the hit and miss counters of
+    /// [`stats`](Self::stats) stay as they were, and the loader is never called.
+    /// Meant for inspecting a live cache without skewing it
+    pub async fn peek(&self, key: impl AsRef<str>) -> Result<Option<Arc<[u8]>>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Peek {
+            key: key.as_ref().to_string(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Gets a value as a string, failing with `Deserialization` if it is not valid UTF-8
+    pub async fn get_str(&self, key: impl AsRef<str>) -> Result<Option<String>, CacheError> {
+        let key = key.as_ref();
+        let value = self.get(key).await?;
+        into_utf8(key, value)
+    }
+
+    /// Gets several values in one round-trip to the store, in the order of `keys`
+    pub async fn get_many(&self, keys: Vec<String>) -> Result<Values, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::GetMany { keys, resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Reads the values of `keys` from disk into memory ahead of their use, so that
+    /// the gets that follow are served from memory. Keys that are missing, expired
+    /// or already in memory are skipped. Stops at the first value that cannot be
+    /// read; the keys before it stay in memory
+    pub async fn prefetch(&self, keys: Vec<String>) -> Result<(), CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Prefetch { keys, resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Atomically adds `delta` to the integer stored at `key`, treating a missing
+    /// key as 0, and returns the new total. Fails if the current value is not an integer
+    pub async fn increment(&self, key: impl AsRef<str>, delta: i64) -> Result<i64, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Incr {
+            key: key.as_ref().to_string(),
+            delta,
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Atomically sets `key` to `new` if its current value equals `expected`, where
+    /// `None` means the key must be absent. Returns whether the value was swapped
+    pub async fn compare_and_swap(
+        &self,
+        key: impl AsRef<str>,
+        expected: Option<Vec<u8>>,
+        new: impl Into<Vec<u8>>,
+    ) -> Result<bool, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Cas {
+            key: key.as_ref().to_string(),
+            expected,
+            new: new.into(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Atomically replaces the value of `key` with what `f` makes of it, in a single
+    /// round-trip. `f` gets the current value, or `None` if the key is absent, and
+    /// returns the new one, or `None` to delete the key. The key keeps its expiry.
+    ///
+    /// `f` runs on a worker while it holds the key, so no other operation on the
+    /// key comes between the read and the write; keep it short. Returns the new value
+    pub async fn modify<F>(
+        &self,
+        key: impl AsRef<str>,
+        f: F,
+    ) -> Result<Option<Arc<[u8]>>, CacheError>
+    where
+        F: FnOnce(Option<Arc<[u8]>>) -> Option<Vec<u8>> + Send + 'static,
+    {
+        self.send_modify(key.as_ref(), Box::new(move |current| Ok(f(current))))
+            .await
+    }
+
+    /// Like [`modify`](Self::modify), for string values. Fails with
+    /// `Deserialization`, leaving the value as it is, if it is not valid UTF-8
+    pub async fn modify_str<F>(
+        &self,
+        key: impl AsRef<str>,
+        f: F,
+    ) -> Result<Option<String>, CacheError>
+    where
+        F: FnOnce(Option<String>) -> Option<String> + Send + 'static,
+    {
+        let key = key.as_ref();
+        let owned_key = key.to_string();
+        let modify: Modifier = Box::new(move |current| {
+            let current = match current {
+                Some(v) => match std::str::from_utf8(&v) {
+                    Ok(v) => Some(v.to_string()),
+                    Err(source) => {
+                        let not_utf8 = NotUtf8 {
+                            key: owned_key,
+                            source,
+                        };
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, not_utf8));
+                    }
+                },
+                None => None,
+            };
+            Ok(f(current).map(String::into_bytes))
+        });
+
+        let value = self.send_modify(key, modify).await?;
+        into_utf8(key, value)
+    }
+
+    async fn send_modify(
+        &self,
+        key: &str,
+        modify: Modifier,
+    ) -> Result<Option<Arc<[u8]>>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Modify {
+            key: key.to_string(),
+            modify,
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Atomically sets `key` to `value` only if it holds no live value, returning
+    /// whether it did. Of concurrent calls for an absent key exactly one succeeds,
+    /// which makes it fit for one-time initialization and simple locks
+    pub async fn set_if_absent(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<bool, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::SetIfAbsent {
+            key: key.into(),
+            value: value.into(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Atomically sets `key` to `value` only if it already holds a live value,
+    /// returning whether it did
+    pub async fn set_if_present(
+        &self,
+        key: impl Into<String>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<bool, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::SetIfPresent {
+            key: key.into(),
+            value: value.into(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Checks whether `key` holds an unexpired value without fetching it
+    pub async fn contains_key(&self, key: impl AsRef<str>) -> Result<bool, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Exists {
+            key: key.as_ref().to_string(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Checks which of `keys` hold an unexpired value, answering in their order,
+    /// in a single round-trip to the store
+    pub async fn exists_many(&self, keys: Vec<String>) -> Result<Vec<bool>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::ExistsMany { keys, resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Makes the value at `key` expire once `ttl` has elapsed from now, without
+    /// sending the value again. Returns `false` if the key is absent or expired
+    pub async fn touch(&self, key: impl AsRef<str>, ttl: Duration) -> Result<bool, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Touch {
+            key: key.as_ref().to_string(),
+            expires_at: SystemTime::now() + ttl,
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Returns the value at `key`, or computes it with `f`, stores it and returns it
+    /// if the key is absent.
+    ///
+    /// Concurrent callers going through the same [`Client`] wait for the first one's
+    /// `f` instead of each running their own. `f` is synchronous; see
+    /// [`get_or_insert_with_async`](Self::get_or_insert_with_async) for computations
+    /// that need to await
+    pub async fn get_or_insert_with<F>(
+        &self,
+        key: impl AsRef<str>,
+        f: F,
+    ) -> Result<Arc<[u8]>, CacheError>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        self.get_or_insert_with_async(key, move || async move { f() })
+            .await
+    }
+
+    /// Like [`get_or_insert_with`](Self::get_or_insert_with), but `f` returns a future
+    pub async fn get_or_insert_with_async<F, Fut>(
+        &self,
+        key: impl AsRef<str>,
+        f: F,
+    ) -> Result<Arc<[u8]>, CacheError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Vec<u8>>,
+    {
+        let key = key.as_ref();
+        if let Some(value) = self.get(key).await? {
+            return Ok(value);
+        }
+
+        // declared first so that it is dropped last, once the slot is let go
+        let _leaving = LeaveInFlight {
+            in_flight: &self.in_flight,
+            key,
+        };
+        let slot = Arc::clone(self.in_flight.entry(key.to_string()).or_default().value());
+        let _computing = slot.lock().await;
+        // another caller may have filled the key while this one waited
+        match self.get(key).await? {
+            Some(value) => Ok(value),
+            None => {
+                let value = f().await;
+                self.set(key.to_string(), value.clone()).await?;
+                Ok(value.into())
+            }
+        }
+    }
+
+    pub async fn delete(&self, key: impl AsRef<str>) -> Result<Option<Arc<[u8]>>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Del {
+            key: key.as_ref().to_string(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Deletes several keys in one round-trip to the store, returning the outcome
+    /// for each key in the order given: its previous value, or `None` if it was
+    /// absent. A key that fails to be deleted does not stop the others
+    pub async fn delete_many(
+        &self,
+        keys: Vec<String>,
+    ) -> Result<Vec<Result<Option<Arc<[u8]>>, CacheError>>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::DelMany { keys, resp: tx };
+        let prevs = self.send_single_record_action(action, rv).await?;
+        Ok(prevs
+            .into_iter()
+            .map(|p| p.map_err(CacheError::from))
+            .collect())
+    }
+
+    /// Deletes every key starting with `prefix`, returning how many were deleted.
+    /// An empty prefix deletes every key
+    pub async fn delete_prefix(&self, prefix: impl AsRef<str>) -> Result<usize, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::DelPrefix {
+            prefix: prefix.as_ref().to_string(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Lists every key in the store, including those only persisted on disk
+    pub async fn keys(&self) -> Result<Vec<String>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Keys { resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Counts the keys in the store, in memory or on disk, without reading any value.
+    /// Keys persisted on disk count until a read finds that they have expired
+    pub async fn len(&self) -> Result<usize, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Len { resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    pub async fn is_empty(&self) -> Result<bool, CacheError> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// Streams every entry of the store, sorted by key. The keys are listed up front,
+    /// but each value is only read when its item is polled, so memory stays bounded
+    /// by the number of keys rather than the size of the store.
+    ///
+    /// Other operations go on while the stream is read, so it may miss what changes
+    /// meanwhile: keys set after it started are left out, keys deleted before their
+    /// turn are skipped, and values set before their turn are read as they are then
+    pub fn iter(&self) -> impl Stream<Item = Result<(String, Arc<[u8]>), CacheError>> {
+        // the keys are listed on the first poll, and an error listing them ends the stream
+        let state = (self.clone(), None::<std::vec::IntoIter<String>>);
+        stream::unfold(state, |(handle, keys)| async move {
+            let mut keys = match keys {
+                Some(keys) => keys,
+                None => match handle.keys().await {
+                    Ok(keys) => keys.into_iter(),
+                    Err(e) => return Some((Err(e), (handle, Some(Vec::new().into_iter())))),
+                },
+            };
+
+            loop {
+                let key = keys.next()?;
+                match handle.get(&key).await {
+                    Ok(Some(value)) => return Some((Ok((key, value)), (handle, Some(keys)))),
+                    // deleted since the keys were listed
+                    Ok(None) => continue,
+                    Err(e) => return Some((Err(e), (handle, Some(keys)))),
+                }
+            }
+        })
+    }
+
+    /// Returns every key starting with `prefix` along with its value, sorted by key.
+    /// Keys are matched as given to `set`, so `"user:1:"` never matches `"user:12:name"`
+    pub async fn scan_prefix(&self, prefix: impl AsRef<str>) -> Result<KeyValuePairs, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::ScanPrefix {
+            prefix: prefix.as_ref().to_string(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Reads every entry, in memory or on disk, into a `HashMap`, failing with
+    /// `Deserialization` if any value is not valid UTF-8
+    pub async fn to_hashmap(&self) -> Result<HashMap<String, String>, CacheError> {
+        let mut map = HashMap::new();
+        for (key, value) in self.scan_prefix("").await? {
+            if let Some(value) = into_utf8(&key, Some(value))? {
+                map.insert(key, value);
+            }
+        }
+        Ok(map)
+    }
+
+    /// Lists the keys, in memory or on disk, that match the glob `pattern` as a whole,
+    /// sorted. Values are never read.
+    ///
+    /// `*` matches any run of characters, `:` included, and `?` any one character.
+    /// `[abc]` and `[a-z]` match one character of the set, and `[!abc]` or `[^abc]`
+    /// one outside it; a `]` first in the set or a `-` first or last in it stand for
+    /// themselves. `\` makes the character after it literal. So `user:*:session`
+    /// matches `user:42:session` but not `user:42:sessions`
+    pub async fn scan_glob(&self, pattern: impl AsRef<str>) -> Result<Vec<String>, CacheError> {
+        let pattern = crate::glob::Pattern::new(pattern.as_ref());
+        let mut keys = self.keys().await?;
+        keys.retain(|key| pattern.matches(key));
+        Ok(keys)
+    }
+
+    /// Clears the store like [`clear`](Self::clear), returning every entry it held,
+    /// whether in memory or only on disk, sorted by key. Expired entries are left
+    /// out. Handy to log what a clear removed, or to move the entries elsewhere
+    pub async fn drain(&self) -> Result<KeyValuePairs, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Drain { resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    pub async fn clear(&self) -> Result<(), CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Clear { resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Flushes every value written so far, and the directories holding them, to disk,
+    /// so that they survive a crash of the machine whatever the
+    /// [`Durability`](crate::Durability) of the store
+    pub async fn flush(&self) -> Result<(), CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Flush { resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Reclaims the disk space that writes and deletes left behind: the temporary
+    /// files of writes cut short by a crash, the shard directories left empty and,
+    /// under [`Storage::Log`](crate::Storage::Log), the records of overwritten and
+    /// deleted values. Writes wait while it runs, so it is best run now and then
+    /// at a quiet time
+    pub async fn compact(&self) -> Result<CompactReport, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Compact { resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Writes every entry, with its expiry, to a single archive file at `out_path`.
+    ///
+    /// Writes wait while the archive is taken, so it holds the store as it was at one instant
+    pub async fn snapshot(&self, out_path: &str) -> Result<(), CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Snapshot {
+            out_path: out_path.to_string(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Sets every unexpired entry of an archive written by [`snapshot`](Self::snapshot),
+    /// returning how many were set. Keys that are not in the archive are left alone
+    pub async fn restore(&self, in_path: &str) -> Result<usize, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Restore {
+            in_path: in_path.to_string(),
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Reports the lookup counters of the store and how much it holds in memory
+    pub async fn stats(&self) -> Result<CacheStats, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Stats { resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Round-trips through a worker without touching memory or disk, for readiness
+    /// probes. Fails with [`CacheError::StoreClosed`] once the store is closed, and
+    /// with [`CacheError::Timeout`] if no worker answers within the timeout
+    pub async fn ping(&self) -> Result<(), CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Ping { resp: tx };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Views the keys of the store that belong to the namespace `name`
+    pub fn namespace(&self, name: &str) -> crate::Namespace {
+        crate::Namespace::new(self.clone(), name)
+    }
+
+    /// Views the store as holding values of type `V`, encoded in the
+    /// [`Format`](crate::Format) the client was built with
+    #[cfg(feature = "serde")]
+    pub fn typed<V>(&self) -> crate::TypedClient<V> {
+        crate::TypedClient::new(self.clone(), self.format)
+    }
+
+    async fn send_set_action(
+        &self,
+        key: String,
+        value: Vec<u8>,
+        expires_at: Option<SystemTime>,
+        idle_ttl: Option<Duration>,
+    ) -> Result<Option<Arc<[u8]>>, CacheError> {
+        let (tx, rv) = oneshot::channel();
+        let action = Action::Set {
+            key,
+            value,
+            expires_at,
+            idle_ttl,
+            resp: tx,
+        };
+        self.send_single_record_action(action, rv).await
+    }
+
+    /// Sends `action` and waits for its response, for no longer than the timeout.
+    /// That covers waiting for room in a full channel too, since a wedged store
+    /// stops draining it
+    async fn send_single_record_action<T>(
+        &self,
+        action: Action,
+        rv: oneshot::Receiver<io::Result<T>>,
+    ) -> Result<T, CacheError> {
+        let exchange = self.exchange(action, rv);
+        match self.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, exchange)
+                .await
+                .unwrap_or(Err(CacheError::Timeout)),
+            None => exchange.await,
+        }
+    }
+
+    async fn exchange<T>(
+        &self,
+        action: Action,
+        rv: oneshot::Receiver<io::Result<T>>,
+    ) -> Result<T, CacheError> {
+        if self.action_sender.send(action).await.is_err() {
+            return Err(CacheError::StoreClosed);
+        }
+
+        match rv.await {
+            Ok(v) => Ok(v?),
+            Err(_) => Err(CacheError::ChannelClosed),
+        }
+    }
+}
+
+/// Forgets the slot of `key` in `in_flight` once no caller waits on it, even when
+/// the future of the last one is dropped midway
+struct LeaveInFlight<'a> {
+    in_flight: &'a DashMap<String, Arc<Mutex<()>>>,
+    key: &'a str,
+}
+
+impl Drop for LeaveInFlight<'_> {
+    fn drop(&mut self) {
+        self.in_flight
+            .remove_if(self.key, |_, slot| Arc::strong_count(slot) == 1);
+    }
+}
+
+/// Aborts the store's workers if `close` was never called, so they do not outlive the client.
+///
+/// This is best-effort: unlike `close`, it cannot wait for the workers, so an action
+/// that is mid-way through writing to disk may be cut short
+impl Drop for Client {
+    fn drop(&mut self) {
+        if let Some(store) = self.store.take() {
+            store.abort();
+        }
+    }
+}
+
+fn into_utf8(key: &str, value: Option<Arc<[u8]>>) -> Result<Option<String>, CacheError> {
+    value
+        .map(|v| match std::str::from_utf8(&v) {
+            Ok(v) => Ok(v.to_string()),
+            Err(e) => Err(CacheError::Deserialization {
+                key: key.to_string(),
+                source: Box::new(e),
+            }),
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+    use serial_test::serial;
+    use std::io::ErrorKind;
+    use tokio::time::sleep;
+
+    const STORE_PATH: &str = "client_db";
+    const KEYS: [&str; 4] = ["hey", "hi", "yoo-hoo", "bonjour"];
+    const VALUES: [&str; 4] = ["English", "English", "Slang", "French"];
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_and_read_multiple_key_value_pairs() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+
+        insert_test_data(&mut client, &keys, &values).await;
+        let received_values = get_values_for_keys(&mut client, keys).await;
+
+        let expected_values: Vec<Result<Option<String>, CacheError>> = values
+            .into_iter()
+            .map(|v| Ok(Some(v.to_string())))
+            .collect();
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_and_delete_multiple_key_value_pairs() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+        let keys_to_delete = keys[2..].to_vec();
+
+        insert_test_data(&mut client, &keys, &values).await;
+
+        for k in &keys_to_delete {
+            let _ = &client.delete(k).await;
+        }
+
+        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
+        let mut expected_values: Vec<Result<Option<String>, CacheError>> = values[..2]
+            .iter()
+            .map(|v| Ok(Some(v.to_string())))
+            .collect();
+        for _ in 0..keys_to_delete.len() {
+            expected_values.push(Ok(None));
+        }
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_and_clear() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+
+        insert_test_data(&mut client, &keys, &values).await;
+        let _ = client.clear().await;
+
+        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
+        let expected_values: Vec<Result<Option<String>, CacheError>> =
+            keys.into_iter().map(|_| Ok(None)).collect();
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn persist_to_file() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+
+        insert_test_data(&mut client, &keys, &values).await;
+        // close old client and store instances
+        client.close().await;
+
+        // Open new store instance
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
+        let expected_values: Vec<Result<Option<String>, CacheError>> = values
+            .into_iter()
+            .map(|v| Ok(Some(v.to_string())))
+            .collect();
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn persist_to_file_after_delete() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+        let keys_to_delete = keys[2..].to_vec();
+
+        insert_test_data(&mut client, &keys, &values).await;
+        delete_keys(&mut client, &keys_to_delete).await;
+
+        // Close the store
+        client.close().await;
+
+        // Open new store instance
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
+        let mut expected_values: Vec<Result<Option<String>, CacheError>> = values[..2]
+            .iter()
+            .map(|v| Ok(Some(v.to_string())))
+            .collect();
+        for _ in 0..keys_to_delete.len() {
+            expected_values.push(Ok(None));
+        }
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn persist_to_file_after_clear() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+
+        insert_test_data(&mut client, &keys, &values).await;
+        let _ = client.clear().await;
+
+        // Close the store
+        client.close().await;
+
+        // Open new store instance
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
+        let expected_values: Vec<Result<Option<String>, CacheError>> =
+            keys.into_iter().map(|_| Ok(None)).collect();
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_with_ttl_expires_value() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        let _ = client
+            .set_with_ttl(
+                KEYS[0].to_string(),
+                VALUES[0].as_bytes().to_vec(),
+                Duration::from_millis(100),
+            )
+            .await;
+
+        assert_eq!(
+            client.get_str(KEYS[0]).await.unwrap(),
+            Some(VALUES[0].to_string())
+        );
+
+        sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(client.get_str(KEYS[0]).await.unwrap(), None);
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn touch_extends_expiry() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        let _ = client
+            .set_with_ttl(
+                KEYS[0].to_string(),
+                VALUES[0].as_bytes().to_vec(),
+                Duration::from_millis(100),
+            )
+            .await;
+
+        assert!(client
+            .touch(KEYS[0], Duration::from_millis(500))
+            .await
+            .unwrap());
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(
+            client.get_str(KEYS[0]).await.unwrap(),
+            Some(VALUES[0].to_string())
+        );
+
+        // the new expiry is on disk too
+        client.reopen().await.unwrap();
+        assert_eq!(
+            client.get_str(KEYS[0]).await.unwrap(),
+            Some(VALUES[0].to_string())
+        );
+
+        sleep(Duration::from_millis(400)).await;
+        assert_eq!(client.get_str(KEYS[0]).await.unwrap(), None);
+        assert!(!client
+            .touch(KEYS[0], Duration::from_secs(60))
+            .await
+            .unwrap());
+        assert!(!client
+            .touch(KEYS[1], Duration::from_secs(60))
+            .await
+            .unwrap());
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn values_over_max_value_bytes_are_refused() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .max_value_bytes(8)
+            .build()
+            .unwrap();
+
+        let at_limit = "12345678".to_string();
+        client
+            .set_str(KEYS[0].to_string(), at_limit.clone())
+            .await
+            .unwrap();
+
+        let result = client.set(KEYS[0].to_string(), vec![b'x'; 9]).await;
+        assert!(matches!(
+            result,
+            Err(CacheError::ValueTooLarge { size: 9, limit: 8 })
+        ));
+        let result = client.set(KEYS[1].to_string(), vec![b'x'; 9]).await;
+        assert!(matches!(result, Err(CacheError::ValueTooLarge { .. })));
+
+        assert_eq!(client.get_str(KEYS[0]).await.unwrap(), Some(at_limit));
+        assert_eq!(client.get(KEYS[1]).await.unwrap(), None);
+
+        // nor was anything written to disk
+        client.reopen().await.unwrap();
+        assert_eq!(client.get(KEYS[1]).await.unwrap(), None);
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn get_into_reuses_the_buffer() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            client.set_str(k.to_string(), v.to_string()).await.unwrap();
+        }
+
+        let mut buf = Vec::with_capacity(64);
+        let capacity = buf.capacity();
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            buf.clear();
+            assert!(client.get_into(k, &mut buf).await.unwrap());
+            assert_eq!(buf, v.as_bytes());
+        }
+        assert_eq!(buf.capacity(), capacity);
+
+        // values are appended, and a miss leaves the buffer alone
+        assert!(client.get_into(KEYS[0], &mut buf).await.unwrap());
+        let expected = format!("{}{}", VALUES[3], VALUES[0]);
+        assert_eq!(buf, expected.as_bytes());
+        assert!(!client.get_into("missing", &mut buf).await.unwrap());
+        assert_eq!(buf, expected.as_bytes());
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn getters_reflect_the_builder() {
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(3)
+            .channel_capacity(8)
+            .max_entries(100)
+            .max_value_bytes(1024)
+            .build()
+            .unwrap();
+
+        assert_eq!(client.store_path(), STORE_PATH);
+        assert_eq!(client.worker_count(), 3);
+        assert_eq!(client.channel_capacity(), 8);
+        let options = client.options();
+        assert_eq!(options.max_entries, Some(100));
+        assert_eq!(options.max_bytes, None);
+        assert_eq!(options.max_value_bytes, Some(1024));
+
+        // they describe the client, open or closed
+        client.close().await;
+        assert_eq!(client.store_path(), STORE_PATH);
+        assert_eq!(client.worker_count(), 3);
+
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        assert_eq!(client.worker_count(), 2);
+        assert_eq!(client.options().max_entries, None);
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn debug_output_sums_the_client_up() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        let values: Vec<(String, Vec<u8>)> = (0..100)
+            .map(|i| (format!("key{}", i), vec![b'x'; 1000]))
+            .collect();
+        client.set_many(values).await.unwrap();
+
+        let debug = format!("{:?}", client);
+        assert!(debug.starts_with("Client { store_path: \"client_db\", workers: 2, "));
+        assert!(debug.contains("entries: 100, "), "{}", debug);
+        assert!(!debug.contains("key0"), "{}", debug);
+        assert!(debug.len() < 200, "{}", debug);
+
+        client.clear().await.unwrap();
+        client.close().await;
+        assert!(format!("{:?}", client).contains("store: None"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn get_cached_takes_a_key_on_disk_alone_as_missing() {
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .max_entries(1)
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
+        for (k, v) in KEYS.iter().zip(VALUES).take(2) {
+            client.set_str(k.to_string(), v.to_string()).await.unwrap();
+        }
+
+        // "hey" was evicted to disk when "hi" was set
+        let cached = client.get_cached(KEYS[1]).await.unwrap();
+        assert_eq!(cached.as_deref(), Some(VALUES[1].as_bytes()));
+        assert_eq!(client.get_cached(KEYS[0]).await.unwrap(), None);
+        assert_eq!(client.get_cached(KEYS[3]).await.unwrap(), None);
+
+        let stats = client.stats().await.unwrap();
+        assert_eq!((stats.hits, stats.disk_fallbacks), (1, 0));
+        let value = client.get(KEYS[0]).await.unwrap();
+        assert_eq!(value.as_deref(), Some(VALUES[0].as_bytes()));
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn metadata_follows_the_sets_and_gets_of_a_key() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        assert_eq!(client.metadata(KEYS[0]).await.unwrap(), None);
+
+        let before_set = SystemTime::now();
+        client
+            .set_str(KEYS[0].to_string(), VALUES[0].to_string())
+            .await
+            .unwrap();
+        let metadata = client.metadata(KEYS[0]).await.unwrap().unwrap();
+        let created_at = metadata.created_at.unwrap();
+        assert!(created_at >= before_set);
+        assert_eq!((metadata.last_accessed, metadata.access_count), (None, 0));
+
+        for _ in 0..3 {
+            client.get(KEYS[0]).await.unwrap();
+        }
+        // neither a peek nor a get of another key counts
+        client.peek(KEYS[0]).await.unwrap();
+        client.get(KEYS[1]).await.unwrap();
+        let metadata = client.metadata(KEYS[0]).await.unwrap().unwrap();
+        assert_eq!(metadata.access_count, 3);
+        assert_eq!(metadata.created_at, Some(created_at));
+        assert!(metadata.last_accessed.unwrap() >= created_at);
+
+        // replacing the value keeps the key's creation, deleting it forgets it
+        client
+            .set_str(KEYS[0].to_string(), VALUES[1].to_string())
+            .await
+            .unwrap();
+        let metadata = client.metadata(KEYS[0]).await.unwrap().unwrap();
+        assert_eq!(
+            (metadata.created_at, metadata.access_count),
+            (Some(created_at), 3)
+        );
+        client.delete(KEYS[0]).await.unwrap();
+        assert_eq!(client.metadata(KEYS[0]).await.unwrap(), None);
+
+        // a key set before the store opened is live, with nothing known of it
+        client
+            .set_str(KEYS[2].to_string(), VALUES[2].to_string())
+            .await
+            .unwrap();
+        client.reopen().await.unwrap();
+        let metadata = client.metadata(KEYS[2]).await.unwrap();
+        assert_eq!(metadata, Some(KeyMetadata::default()));
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn prefetch_brings_keys_on_disk_back_into_memory() {
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .max_entries(2)
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
+        for (k, v) in KEYS.iter().zip(VALUES).take(3) {
+            client.set_str(k.to_string(), v.to_string()).await.unwrap();
+        }
+        assert_eq!(client.get_cached(KEYS[0]).await.unwrap(), None);
+
+        let keys = vec![KEYS[0].to_string(), "missing".to_string()];
+        client.prefetch(keys).await.unwrap();
+        let cached = client.get_cached(KEYS[0]).await.unwrap();
+        assert_eq!(cached.as_deref(), Some(VALUES[0].as_bytes()));
+        assert_eq!(client.get_cached("missing").await.unwrap(), None);
+        assert_eq!(client.stats().await.unwrap().disk_fallbacks, 0);
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn on_evict_is_told_of_each_evicted_entry() {
+        let evicted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&evicted);
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .max_entries(2)
+            .on_evict(move |key, value| {
+                seen.lock()
+                    .unwrap()
+                    .push((key.to_string(), value.to_string()))
+            })
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
+
+        for (k, v) in KEYS.iter().zip(VALUES).take(2) {
+            client.set_str(k.to_string(), v.to_string()).await.unwrap();
+        }
+        assert!(evicted.lock().unwrap().is_empty());
+        for (k, v) in KEYS.iter().zip(VALUES).skip(2) {
+            client.set_str(k.to_string(), v.to_string()).await.unwrap();
+        }
+
+        let expected: Vec<(String, String)> = KEYS
+            .iter()
+            .zip(VALUES)
+            .take(2)
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(*evicted.lock().unwrap(), expected);
+        assert_eq!(client.stats().await.unwrap().evictions, 2);
+        // deletes are not evictions
+        client.delete(KEYS[3]).await.unwrap();
+        assert_eq!(evicted.lock().unwrap().len(), 2);
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn a_panicking_eviction_listener_leaves_the_store_serving() {
+        let evicted = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&evicted);
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(1)
+            .max_entries(1)
+            .on_evict(move |key, _| {
+                seen.lock().unwrap().push(key.to_string());
+                if key == KEYS[0] {
+                    panic!("listener failed");
+                }
+            })
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
+
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            client.set_str(*k, v).await.unwrap();
+        }
+        assert_eq!(*evicted.lock().unwrap(), KEYS[..3].to_vec());
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            assert_eq!(client.get_str(*k).await.unwrap(), Some(v.to_string()));
+        }
+        assert_eq!(client.workers_alive(), 1);
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn drain_returns_every_entry_and_empties_the_store() {
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .max_entries(2)
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            client.set_str(k.to_string(), v.to_string()).await.unwrap();
+        }
+        let _ = client
+            .set_with_ttl(
+                "stale".to_string(),
+                b"gone".to_vec(),
+                Duration::from_millis(10),
+            )
+            .await;
+        sleep(Duration::from_millis(50)).await;
+
+        // only two of the entries are still in memory, the rest are on disk alone
+        let drained = client.drain().await.unwrap();
+        let mut expected: Vec<(String, Arc<[u8]>)> = KEYS
+            .iter()
+            .zip(VALUES)
+            .map(|(k, v)| (k.to_string(), v.as_bytes().into()))
+            .collect();
+        expected.sort();
+        assert_eq!(drained, expected);
+
+        assert!(client.is_empty().await.unwrap());
+        for k in KEYS {
+            assert_eq!(client.get(k).await.unwrap(), None);
+        }
+        assert!(client.drain().await.unwrap().is_empty());
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_many_and_get_many() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+        let entries = keys
+            .iter()
+            .zip(&values)
+            .map(|(k, v)| (k.to_string(), v.as_bytes().to_vec()))
+            .collect();
+
+        let prevs = client.set_many(entries).await.unwrap();
+        assert_eq!(prevs.len(), keys.len());
+        assert!(prevs.iter().all(|p| p.is_ok()));
+
+        let keys = keys.into_iter().map(String::from).collect();
+        let received_values = client.get_many(keys).await.unwrap();
+        for (got, expected) in received_values.into_iter().zip(values) {
+            assert_eq!(got.as_deref(), Some(expected.as_bytes()));
+        }
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn close_must_close_store() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        let _ = client
+            .set_str(KEYS[0].to_string(), VALUES[0].to_string())
+            .await;
+
+        assert!(client.get_str(KEYS[0]).await.is_ok());
+
+        client.close().await;
+
+        assert!(matches!(
+            client.get_str(KEYS[0]).await,
+            Err(CacheError::StoreClosed)
+        ));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_and_read_binary_value() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        let value = vec![0xff, 0xfe, 0x00, b'\n', 0x80];
+
+        let _ = client.set(KEYS[0].to_string(), value.clone()).await;
+        let got = client.get(KEYS[0]).await.unwrap();
+        assert_eq!(got.as_deref(), Some(&value[..]));
+        assert!(matches!(
+            client.get_str(KEYS[0]).await,
+            Err(CacheError::Deserialization { key, .. }) if key == KEYS[0]
+        ));
+
+        // close old client and store instances
+        client.close().await;
+
+        // Open new store instance
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        let got = client.get(KEYS[0]).await.unwrap();
+        assert_eq!(got.as_deref(), Some(&value[..]));
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn drop_stops_workers() {
+        let client = Client::new(STORE_PATH, 2).unwrap();
+        let action_sender = client.handle.action_sender.clone();
+
+        drop(client);
+        sleep(Duration::from_millis(200)).await;
+
+        let (resp, _) = oneshot::channel();
+        let action = Action::Clear { resp };
+        assert!(action_sender.send(action).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn handles_are_shared_across_tasks() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+
+        let mut tasks = Vec::new();
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            let handle = client.handle();
+            tasks.push(tokio::spawn(async move {
+                handle.set_str(k.to_string(), v.to_string()).await
+            }));
+        }
+        for task in tasks {
+            assert!(task.await.unwrap().is_ok());
+        }
+
+        let received_values = get_values_for_keys(&mut client, KEYS.to_vec()).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.to_string()));
+        }
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn single_worker_client_serves_every_operation() {
+        let mut client = Client::new(STORE_PATH, 1).unwrap();
+        client.clear().await.unwrap();
+
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            client.set_str(k.to_string(), v.to_string()).await.unwrap();
+        }
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            assert_eq!(client.get_str(k).await.unwrap(), Some(v.to_string()));
+        }
+        client.delete(KEYS[0]).await.unwrap();
+        assert_eq!(client.get(KEYS[0]).await.unwrap(), None);
+        assert_eq!(client.increment("counter", 2).await.unwrap(), 2);
+        let swapped = client
+            .compare_and_swap("counter", Some(b"2".to_vec()), b"3".to_vec())
+            .await
+            .unwrap();
+        assert!(swapped);
+        let value = client
+            .get_or_insert_with("computed", || b"done".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(&*value, b"done");
+        assert_eq!(client.len().await.unwrap(), KEYS.len() + 1);
+
+        // many callers at once queue up for the one worker
+        let mut tasks = Vec::new();
+        for _ in 0..20 {
+            let handle = client.handle();
+            tasks.push(tokio::spawn(async move {
+                handle.increment("counter", 1).await.unwrap()
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(client.increment("counter", 0).await.unwrap(), 23);
+        assert_eq!(client.workers_alive(), 1);
+
+        client.clear().await.unwrap();
+        client.close().await;
+
+        let mut client = Client::builder().path(STORE_PATH).build().unwrap();
+        let cores = std::thread::available_parallelism().unwrap().get();
+        assert_eq!(client.worker_count(), cores);
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn builder_rejects_invalid_config() {
+        let no_path = ClientBuilder::new().workers(2).build();
+        assert_eq!(no_path.err().unwrap().kind(), ErrorKind::InvalidInput);
+
+        let no_workers = ClientBuilder::new().path(STORE_PATH).workers(0).build();
+        assert_eq!(no_workers.err().unwrap().kind(), ErrorKind::InvalidInput);
+
+        let no_capacity = ClientBuilder::new()
+            .path(STORE_PATH)
+            .channel_capacity(0)
+            .build();
+        assert_eq!(no_capacity.err().unwrap().kind(), ErrorKind::InvalidInput);
+
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .channel_capacity(1)
+            .build()
+            .unwrap();
+        let _ = client
+            .set_str(KEYS[0].to_string(), VALUES[0].to_string())
+            .await;
+        assert_eq!(
+            client.get_str(KEYS[0]).await.unwrap(),
+            Some(VALUES[0].to_string())
+        );
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn full_channel_makes_senders_wait() {
+        let (action_sender, mut action_receiver) = mpsc::channel(2);
+        let handle = ClientHandle::new(action_sender, Format::default(), None);
+
+        let mut pending = Vec::new();
+        for k in KEYS {
+            let handle = handle.clone();
+            pending.push(tokio::spawn(async move { handle.get(k).await }));
+        }
+        sleep(Duration::from_millis(200)).await;
+
+        // only `capacity` actions fit; the other senders wait instead of failing
+        assert_eq!(handle.action_sender.capacity(), 0);
+        assert!(pending.iter().all(|p| !p.is_finished()));
+
+        let _ = action_receiver.recv().await;
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(handle.action_sender.capacity(), 0);
+
+        for p in pending {
+            p.abort();
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn operations_time_out_when_the_store_does_not_answer() {
+        let (action_sender, mut action_receiver) = mpsc::channel(1);
+        let timeout = Some(Duration::from_millis(50));
+        let handle = ClientHandle::new(action_sender, Format::default(), timeout);
+
+        // nobody answers the action, which was still sent
+        let result = handle.get(KEYS[0]).await;
+        assert!(matches!(result, Err(CacheError::Timeout)));
+        assert!(action_receiver.try_recv().is_ok());
+
+        // waiting for room in a full channel times out too
+        handle.get(KEYS[0]).await.unwrap_err();
+        let result = handle.get(KEYS[1]).await;
+        assert!(matches!(result, Err(CacheError::Timeout)));
+
+        let untimed = handle.with_timeout(None);
+        let pending = tokio::spawn(async move { untimed.get(KEYS[0]).await });
+        sleep(Duration::from_millis(200)).await;
+        assert!(!pending.is_finished());
+        pending.abort();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn ping_tells_a_live_store_from_a_closed_or_wedged_one() {
+        let mut client = Client::builder().in_memory().workers(2).build().unwrap();
+        client.ping().await.unwrap();
+        let stats = client.stats().await.unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.entry_count), (0, 0, 0));
+
+        client.close().await;
+        assert!(matches!(client.ping().await, Err(CacheError::StoreClosed)));
+
+        // a store whose workers never answer
+        let (action_sender, _action_receiver) = mpsc::channel(1);
+        let timeout = Some(Duration::from_millis(50));
+        let handle = ClientHandle::new(action_sender, Format::default(), timeout);
+        assert!(matches!(handle.ping().await, Err(CacheError::Timeout)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn timed_out_operation_still_completes_in_the_store() {
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .timeout(Duration::from_millis(100))
+            .loader(|_| {
+                Box::pin(async {
+                    sleep(Duration::from_millis(300)).await;
+                    Ok(Some("loaded".to_string()))
+                })
+            })
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
+        client
+            .set_str(KEYS[0].to_string(), VALUES[0].to_string())
+            .await
+            .unwrap();
+
+        let result = client.get("slow").await;
+        assert!(matches!(result, Err(CacheError::Timeout)));
+        sleep(Duration::from_millis(400)).await;
+        let value = client.get_str("slow").await.unwrap();
+        assert_eq!(value, Some("loaded".to_string()));
+
+        let untimed = client.with_timeout(None);
+        assert_eq!(
+            untimed.get_str("other").await.unwrap(),
+            Some("loaded".to_string())
+        );
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn concurrent_modifications_are_not_lost() {
+        let mut client = Client::new(STORE_PATH, 4).unwrap();
+        client.clear().await.unwrap();
+
+        let mut tasks = Vec::new();
+        for i in 0..50 {
+            let handle = client.handle();
+            tasks.push(tokio::spawn(async move {
+                let item = format!("{},", i);
+                handle
+                    .modify_str("list", move |list| Some(list.unwrap_or_default() + &item))
+                    .await
+                    .unwrap()
+            }));
+        }
+        for task in tasks {
+            assert!(task.await.unwrap().is_some());
+        }
+        let list = client.get_str("list").await.unwrap().unwrap();
+        let mut items: Vec<u32> = list
+            .trim_end_matches(',')
+            .split(',')
+            .map(|i| i.parse().unwrap())
+            .collect();
+        items.sort();
+        assert_eq!(items, (0..50).collect::<Vec<_>>());
+
+        // returning None deletes the key, or leaves an absent one absent
+        let gone = client.modify("list", |_| None).await.unwrap();
+        assert_eq!(gone, None);
+        assert_eq!(client.get("list").await.unwrap(), None);
+        assert_eq!(client.modify("list", |_| None).await.unwrap(), None);
+
+        // the key keeps its expiry
+        let _ = client
+            .set_with_ttl(
+                "stale".to_string(),
+                b"1".to_vec(),
+                Duration::from_millis(100),
+            )
+            .await;
+        let value = client.modify("stale", |v| v.map(|v| [&v[..], b"2"].concat()));
+        assert_eq!(&*value.await.unwrap().unwrap(), b"12");
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(client.get("stale").await.unwrap(), None);
+
+        let _ = client.set("binary".to_string(), vec![0xff, 0xfe]).await;
+        let result = client.modify_str("binary", |_| Some("text".to_string()));
+        assert!(matches!(
+            result.await,
+            Err(CacheError::Deserialization { key, .. }) if key == "binary"
+        ));
+        assert_eq!(
+            &*client.get("binary").await.unwrap().unwrap(),
+            &[0xff, 0xfe]
+        );
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_detailed_tells_new_keys_from_updated_ones() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+
+        let outcome = client.set_detailed(KEYS[0], "English").await.unwrap();
+        assert_eq!(
+            outcome,
+            SetOutcome {
+                created: true,
+                previous: None
+            }
+        );
+        // held in memory
+        let outcome = client.set_detailed(KEYS[0], "Anglais").await.unwrap();
+        assert!(!outcome.created);
+        assert_eq!(outcome.previous.as_deref(), Some(&b"English"[..]));
+
+        client
+            .set_with_ttl(KEYS[1], "English", Duration::from_millis(50))
+            .await
+            .unwrap();
+        client.set(KEYS[2], "Slang").await.unwrap();
+        sleep(Duration::from_millis(100)).await;
+        // a fresh client holds nothing in memory, so the values are on disk alone
+        client.reopen().await.unwrap();
+
+        let outcome = client.set_detailed(KEYS[0], "Inglés").await.unwrap();
+        assert!(!outcome.created);
+        assert_eq!(outcome.previous.as_deref(), Some(&b"Anglais"[..]));
+        let outcome = client.set_detailed(KEYS[1], "English").await.unwrap();
+        assert!(outcome.created);
+        assert_eq!(outcome.previous, None);
+        let outcome = client.set_detailed(KEYS[2], "Argot").await.unwrap();
+        assert!(!outcome.created);
+        assert_eq!(outcome.previous.as_deref(), Some(&b"Slang"[..]));
+        assert_eq!(client.get_str(KEYS[2]).await.unwrap(), Some("Argot".into()));
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn hashmaps_round_trip_through_the_store() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        assert_eq!(client.to_hashmap().await.unwrap(), HashMap::new());
+
+        let map: HashMap<String, String> = KEYS
+            .iter()
+            .zip(VALUES)
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        client.extend(map.clone()).await.unwrap();
+        assert_eq!(client.to_hashmap().await.unwrap(), map);
+        // read back from disk as well as from memory
+        client.reopen().await.unwrap();
+        assert_eq!(client.to_hashmap().await.unwrap(), map);
+
+        client.set(KEYS[0], vec![0xff, 0xfe]).await.unwrap();
+        match client.to_hashmap().await {
+            Err(CacheError::Deserialization { key, .. }) => assert_eq!(key, KEYS[0]),
+            other => panic!("expected a deserialization error, got {:?}", other),
+        }
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn keys_and_values_are_taken_in_any_string_form() {
+        use std::borrow::Cow;
+
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+
+        client.set("hey", "English").await.unwrap();
+        client.set_str("hi", "English").await.unwrap();
+        let key = String::from("bonjour");
+        client.set(&*key, b"French".as_slice()).await.unwrap();
+        client
+            .set(Cow::Borrowed("yoo-hoo"), String::from("Slang"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.get_str("hey").await.unwrap().as_deref(),
+            Some("English")
+        );
+        assert_eq!(
+            client.get_str(&key).await.unwrap().as_deref(),
+            Some("French")
+        );
+        assert_eq!(
+            client.get_str(key.clone()).await.unwrap().as_deref(),
+            Some("French")
+        );
+        let cow: Cow<str> = Cow::Owned("yoo-hoo".to_string());
+        assert!(client.contains_key(&cow).await.unwrap());
+        assert_eq!(client.increment("counter", 1).await.unwrap(), 1);
+        assert!(client.set_if_present("hi", "Hello").await.unwrap());
+
+        let namespace = client.namespace("users");
+        namespace.set("1", "Ann").await.unwrap();
+        assert_eq!(
+            namespace.get("1").await.unwrap().as_deref(),
+            Some(&b"Ann"[..])
+        );
+        assert!(namespace.delete("1").await.unwrap().is_some());
+
+        assert!(client.delete("hey").await.unwrap().is_some());
+        assert_eq!(client.delete_prefix(key).await.unwrap(), 1);
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_if_absent_and_set_if_present() {
+        let mut client = Client::new(STORE_PATH, 4).unwrap();
+        client.clear().await.unwrap();
+
+        let mut tasks = Vec::new();
+        for i in 0..20 {
+            let handle = client.handle();
+            tasks.push(tokio::spawn(async move {
+                let value = format!("owner{}", i).into_bytes();
+                let won = handle
+                    .set_if_absent("lock".to_string(), value.clone())
+                    .await;
+                (won.unwrap(), value)
+            }));
+        }
+        let mut winners = Vec::new();
+        for task in tasks {
+            let (won, value) = task.await.unwrap();
+            if won {
+                winners.push(value);
+            }
         }
+        assert_eq!(winners.len(), 1);
+        let value = client.get("lock").await.unwrap().unwrap();
+        assert_eq!(&*value, &winners[0][..]);
+
+        let missing = "missing".to_string();
+        assert!(!client.set_if_present(missing, b"x".to_vec()).await.unwrap());
+        assert_eq!(client.get("missing").await.unwrap(), None);
+        assert!(client
+            .set_if_present("lock".to_string(), b"released".to_vec())
+            .await
+            .unwrap());
+        assert_eq!(&*client.get("lock").await.unwrap().unwrap(), b"released");
+
+        // an expired value counts as absent
+        let _ = client
+            .set_with_ttl(
+                "stale".to_string(),
+                b"old".to_vec(),
+                Duration::from_millis(10),
+            )
+            .await;
+        sleep(Duration::from_millis(50)).await;
+        let stale = "stale".to_string();
+        assert!(!client
+            .set_if_present(stale.clone(), b"x".to_vec())
+            .await
+            .unwrap());
+        assert!(client.set_if_absent(stale, b"new".to_vec()).await.unwrap());
+        assert_eq!(&*client.get("stale").await.unwrap().unwrap(), b"new");
+
+        client.clear().await.unwrap();
+        client.close().await;
     }
 
-    pub async fn set(&mut self, key: String, value: String) -> io::Result<Option<String>> {
-        let (tx, rv) = oneshot::channel();
-        let action = Action::Set {
-            key,
-            value,
-            resp: tx,
-        };
-        self.send_single_record_action(action, rv).await
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn reads_keep_idle_expiring_keys_alive() {
+        let idle_ttl = Duration::from_millis(600);
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        client
+            .set_with_idle_ttl("read", "a", idle_ttl)
+            .await
+            .unwrap();
+        client
+            .set_with_idle_ttl("idle", "b", idle_ttl)
+            .await
+            .unwrap();
+        client.set_with_ttl("fixed", "c", idle_ttl).await.unwrap();
+
+        for _ in 0..6 {
+            sleep(Duration::from_millis(150)).await;
+            assert!(client.get("read").await.unwrap().is_some());
+        }
+        // reads of a key with a fixed expiry do not extend it
+        assert_eq!(client.get("fixed").await.unwrap(), None);
+        assert_eq!(client.get("idle").await.unwrap(), None);
+
+        // the sliding expiry outlives the store, and holds for keys read from disk
+        client.reopen().await.unwrap();
+        assert!(client.get("read").await.unwrap().is_some());
+        sleep(Duration::from_millis(400)).await;
+        assert!(client.get("read").await.unwrap().is_some());
+        sleep(Duration::from_millis(800)).await;
+        assert_eq!(client.get("read").await.unwrap(), None);
+        client.close().await;
+
+        // or for every key set without an expiry of its own
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .idle_ttl(idle_ttl)
+            .build()
+            .unwrap();
+        client.set("default", "d").await.unwrap();
+        client
+            .set_with_ttl("fixed", "e", Duration::from_secs(60))
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            sleep(Duration::from_millis(300)).await;
+            assert!(client.get("default").await.unwrap().is_some());
+        }
+        sleep(Duration::from_millis(800)).await;
+        assert_eq!(client.get("default").await.unwrap(), None);
+        assert!(client.get("fixed").await.unwrap().is_some());
+
+        client.clear().await.unwrap();
+        client.close().await;
     }
 
-    pub async fn get(&mut self, key: &str) -> io::Result<Option<String>> {
-        let (tx, rv) = oneshot::channel();
-        let action = Action::Get {
-            key: key.to_string(),
-            resp: tx,
-        };
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn concurrent_increments_are_not_lost() {
+        let mut client = Client::new(STORE_PATH, 4).unwrap();
+        let _ = client.delete("counter").await;
 
-        self.send_single_record_action(action, rv).await
+        let mut tasks = Vec::new();
+        for _ in 0..50 {
+            let handle = client.handle();
+            tasks.push(tokio::spawn(
+                async move { handle.increment("counter", 2).await },
+            ));
+        }
+        for task in tasks {
+            assert!(task.await.unwrap().is_ok());
+        }
+
+        assert_eq!(client.increment("counter", 0).await.unwrap(), 100);
+
+        client.close().await;
     }
 
-    pub async fn delete(&mut self, key: &str) -> io::Result<Option<String>> {
-        let (tx, rv) = oneshot::channel();
-        let action = Action::Del {
-            key: key.to_string(),
-            resp: tx,
-        };
-        self.send_single_record_action(action, rv).await
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn only_one_concurrent_compare_and_swap_succeeds() {
+        let mut client = Client::new(STORE_PATH, 4).unwrap();
+        let _ = client.set(KEYS[0].to_string(), b"v1".to_vec()).await;
+
+        let mut tasks = Vec::new();
+        for new in [b"v2a".to_vec(), b"v2b".to_vec()] {
+            let handle = client.handle();
+            tasks.push(tokio::spawn(async move {
+                handle
+                    .compare_and_swap(KEYS[0], Some(b"v1".to_vec()), new)
+                    .await
+            }));
+        }
+
+        let mut swapped = Vec::new();
+        for task in tasks {
+            swapped.push(task.await.unwrap().unwrap());
+        }
+        assert_eq!(swapped.iter().filter(|s| **s).count(), 1);
+
+        let expected = if swapped[0] { "v2a" } else { "v2b" };
+        assert_eq!(
+            client.get_str(KEYS[0]).await.unwrap(),
+            Some(expected.to_string())
+        );
+
+        // `None` only swaps in a value for an absent key
+        let _ = client.delete(KEYS[1]).await;
+        let swapped = client
+            .compare_and_swap(KEYS[1], None, b"new".to_vec())
+            .await;
+        assert!(swapped.unwrap());
+        let swapped = client
+            .compare_and_swap(KEYS[1], None, b"newer".to_vec())
+            .await;
+        assert!(!swapped.unwrap());
+
+        client.close().await;
     }
 
-    pub async fn clear(&mut self) -> io::Result<()> {
-        let (tx, rv) = oneshot::channel();
-        let action = Action::Clear { resp: tx };
-        self.send_single_record_action(action, rv).await
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn gets_of_a_cached_value_share_one_allocation() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        let _ = client.set(KEYS[0].to_string(), vec![7; 1 << 20]).await;
+
+        let first = client.get(KEYS[0]).await.unwrap().unwrap();
+        let second = client.get(KEYS[0]).await.unwrap().unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.len(), 1 << 20);
+
+        client.close().await;
     }
 
-    async fn send_single_record_action<T>(
-        &mut self,
-        action: Action,
-        rv: oneshot::Receiver<io::Result<T>>,
-    ) -> io::Result<T> {
-        if let Err(e) = self.action_sender.send(action).await {
-            return Err(Error::new(ErrorKind::ConnectionRefused, e.to_string()));
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn reopened_client_reads_persisted_values() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            let _ = client.set_str(k.to_string(), v.to_string()).await;
         }
+        client.close().await;
 
-        match rv.await {
-            Ok(v) => v,
-            Err(e) => Err(Error::new(ErrorKind::ConnectionRefused, e.to_string())),
+        let result = client.get(KEYS[0]).await;
+        assert!(matches!(result, Err(CacheError::StoreClosed)));
+
+        client.reopen().await.unwrap();
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            assert_eq!(client.get_str(k).await.unwrap(), Some(v.to_string()));
         }
-    }
 
-    pub async fn close(&mut self) {
-        let store = self.store.take().unwrap();
-        store.close().await;
+        // reopening an open client restarts it
+        client.reopen().await.unwrap();
+        assert_eq!(
+            client.get_str(KEYS[0]).await.unwrap(),
+            Some(VALUES[0].to_string())
+        );
+
+        client.clear().await.unwrap();
+        client.close().await;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serial_test::serial;
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn files_of_older_header_versions_stay_readable() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        client.close().await;
 
-    const STORE_PATH: &str = "client_db";
-    const KEYS: [&str; 4] = ["hey", "hi", "yoo-hoo", "bonjour"];
-    const VALUES: [&str; 4] = ["English", "English", "Slang", "French"];
+        let write = |key: &str, content: &[u8]| {
+            let path = crate::fs::key_to_path(STORE_PATH, key, &StoreOptions::default());
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        // "English" as version 1 writes it: magic, version, flags (an expiry),
+        // the crc32 of the value, then the expiry of 2100-01-01
+        let mut v1 = vec![0xff, 1, 0b001, 0xe3, 0x3e, 0x3b, 0x9c];
+        v1.extend_from_slice(&4_102_444_800_000u64.to_be_bytes());
+        v1.extend_from_slice(b"English");
+        write(KEYS[0], &v1);
+        // the same, expired since a millisecond into 1970
+        v1[7..15].copy_from_slice(&1u64.to_be_bytes());
+        write(KEYS[3], &v1);
+        // written before the binary header
+        write(KEYS[1], b";crc32=e33e3b9c\nEnglish");
+        // a version from the future
+        let mut v2 = vec![0xff, 2, 0, 0, 0, 0, 0];
+        v2.extend_from_slice(b"Slang");
+        write(KEYS[2], &v2);
+
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        assert_eq!(
+            client.get_str(KEYS[0]).await.unwrap(),
+            Some("English".to_string())
+        );
+        assert_eq!(client.get(KEYS[3]).await.unwrap(), None);
+        assert_eq!(
+            client.get_str(KEYS[1]).await.unwrap(),
+            Some("English".to_string())
+        );
+        match client.get(KEYS[2]).await {
+            Err(CacheError::UnsupportedFormat { key, version }) => {
+                assert_eq!((key.as_str(), version), (KEYS[2], 2))
+            }
+            other => panic!("expected an unsupported format error, got {:?}", other),
+        }
+        // an existence check reads the header too, and fails as the get does
+        match client.contains_key(KEYS[2]).await {
+            Err(CacheError::UnsupportedFormat { key, version }) => {
+                assert_eq!((key.as_str(), version), (KEYS[2], 2))
+            }
+            other => panic!("expected an unsupported format error, got {:?}", other),
+        }
+        assert!(client.contains_key(KEYS[0]).await.unwrap());
+        assert!(!client.contains_key(KEYS[3]).await.unwrap());
+        // a version 1 header cut short
+        write("torn", &v1[..5]);
+        match client.get("torn").await {
+            Err(CacheError::Corrupt { key }) => assert_eq!(key, "torn"),
+            other => panic!("expected a corrupt value error, got {:?}", other),
+        }
+        match client.contains_key("torn").await {
+            Err(CacheError::Corrupt { key }) => assert_eq!(key, "torn"),
+            other => panic!("expected a corrupt value error, got {:?}", other),
+        }
+
+        // rewritten in the current format, the value reads the same
+        client.set_str(KEYS[1], "English").await.unwrap();
+        client.reopen().await.unwrap();
+        assert_eq!(
+            client.get_str(KEYS[1]).await.unwrap(),
+            Some("English".to_string())
+        );
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn set_and_read_multiple_key_value_pairs() {
-        let mut client = Client::new(STORE_PATH, 2);
+    async fn flipped_bytes_on_disk_are_reported_as_corrupt() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        for (k, v) in KEYS.iter().zip(VALUES).take(2) {
+            let _ = client.set_str(k.to_string(), v.to_string()).await;
+        }
+        client.close().await;
 
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
+        let path = crate::fs::key_to_path(STORE_PATH, KEYS[0], &StoreOptions::default());
+        let mut content = std::fs::read(&path).unwrap();
+        let last = content.len() - 1;
+        content[last] ^= 0x01;
+        std::fs::write(&path, content).unwrap();
 
-        insert_test_data(&mut client, &keys, &values).await;
-        let received_values = get_values_for_keys(&mut client, keys).await;
+        // a fresh client has nothing in memory, so it reads the files
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        match client.get(KEYS[0]).await {
+            Err(CacheError::Corrupt { key }) => assert_eq!(key, KEYS[0]),
+            other => panic!("expected a corrupt value error, got {:?}", other),
+        }
+        assert_eq!(
+            client.get_str(KEYS[1]).await.unwrap(),
+            Some(VALUES[1].to_string())
+        );
 
-        let expected_values: Vec<io::Result<Option<String>>> = values
-            .into_iter()
-            .map(|v| Ok(Some(v.to_string())))
-            .collect();
+        client.clear().await.unwrap();
+        client.close().await;
+    }
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn keys_traversing_upward_are_kept_inside_the_store() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        let store = std::fs::canonicalize(STORE_PATH).unwrap();
+        let parent = store.parent().unwrap();
+        let listing = || {
+            let mut names: Vec<_> = std::fs::read_dir(parent)
+                .unwrap()
+                .map(|e| e.unwrap().file_name())
+                .collect();
+            names.sort();
+            names
+        };
+        let before = listing();
+
+        let keys = ["../../outside", "../outside", "/outside", ".."];
+        for key in keys {
+            client.set(key, VALUES[0]).await.unwrap();
+            let path = crate::fs::key_to_path(&store, key, &StoreOptions::default());
+            assert!(path.starts_with(&store));
+            assert!(path.exists());
         }
+        assert!(!store.join("../../outside").exists());
+        assert!(!parent.join("outside").exists());
+        assert_eq!(listing(), before);
 
+        for key in keys {
+            assert_eq!(client.get_str(key).await.unwrap(), Some(VALUES[0].into()));
+            assert!(client.delete(key).await.unwrap().is_some());
+        }
+        assert_eq!(listing(), before);
+
+        client.clear().await.unwrap();
         client.close().await;
     }
 
+    #[cfg(unix)]
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn set_and_delete_multiple_key_value_pairs() {
-        let mut client = Client::new(STORE_PATH, 2);
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
-        let keys_to_delete = keys[2..].to_vec();
+    async fn keys_whose_file_leads_out_of_the_store_are_refused() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        let outside = std::path::Path::new("client_db_outside");
+        let _ = std::fs::remove_dir_all(outside);
+        std::fs::create_dir_all(outside).unwrap();
 
-        insert_test_data(&mut client, &keys, &values).await;
+        // the shard directory of the key swapped for a symlink to outside the store
+        let path = crate::fs::key_to_path(STORE_PATH, KEYS[0], &StoreOptions::default());
+        let shard = path.parent().unwrap();
+        std::fs::create_dir_all(shard.parent().unwrap()).unwrap();
+        let target = std::fs::canonicalize(outside).unwrap();
+        std::os::unix::fs::symlink(target, shard).unwrap();
 
-        for k in &keys_to_delete {
-            let _ = &client.delete(*k).await;
+        match client.set(KEYS[0], VALUES[0]).await {
+            Err(CacheError::InvalidKey { key }) => assert_eq!(key, KEYS[0]),
+            other => panic!("expected an invalid key error, got {:?}", other),
         }
+        assert!(std::fs::read_dir(outside).unwrap().next().is_none());
 
-        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
-        let mut expected_values: Vec<io::Result<Option<String>>> = values[..2]
-            .into_iter()
-            .map(|v| Ok(Some(v.to_string())))
-            .collect();
-        for _ in 0..keys_to_delete.len() {
-            expected_values.push(Ok(None));
+        // a file planted there is neither read, listed nor deleted
+        let planted = outside.join(path.file_name().unwrap());
+        let entry = crate::store::Entry {
+            value: VALUES[0].as_bytes().into(),
+            expires_at: None,
+            idle_ttl: None,
+        };
+        let content = crate::fs::encode_entry(&entry, Default::default()).unwrap();
+        std::fs::write(&planted, content).unwrap();
+        client.reopen().await.unwrap();
+        assert!(matches!(
+            client.get(KEYS[0]).await,
+            Err(CacheError::InvalidKey { .. })
+        ));
+        assert!(matches!(
+            client.contains_key(KEYS[0]).await,
+            Err(CacheError::InvalidKey { .. })
+        ));
+        assert!(matches!(
+            client.delete(KEYS[0]).await,
+            Err(CacheError::InvalidKey { .. })
+        ));
+        assert!(planted.exists());
+        assert!(!client.keys().await.unwrap().contains(&KEYS[0].to_string()));
+
+        // other keys are served as ever
+        client.set(KEYS[1], VALUES[1]).await.unwrap();
+        assert_eq!(
+            client.get_str(KEYS[1]).await.unwrap(),
+            Some(VALUES[1].into())
+        );
+
+        std::fs::remove_file(shard).unwrap();
+        client.clear().await.unwrap();
+        client.close().await;
+        std::fs::remove_dir_all(outside).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn operations_dropped_midway_leave_the_store_serving() {
+        let mut client = Client::new(STORE_PATH, 1).unwrap();
+        client.clear().await.unwrap();
+        client.set(KEYS[0], VALUES[0]).await.unwrap();
+
+        // polled once, a future sends its action if the queue has room, then is
+        // dropped as it waits
+        assert!(client.set(KEYS[1], VALUES[1]).now_or_never().is_none());
+        for _ in 0..100 {
+            assert!(client.get(KEYS[0]).now_or_never().is_none());
+        }
+        tokio::select! {
+            _ = client.get(KEYS[0]) => {}
+            _ = std::future::ready(()) => {}
         }
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+        // the one worker serves the actions in the order sent, the dropped set first
+        assert_eq!(
+            client.get_str(KEYS[1]).await.unwrap(),
+            Some(VALUES[1].into())
+        );
+        assert_eq!(client.workers_alive(), 1);
+        client.reopen().await.unwrap();
+        assert_eq!(
+            client.get_str(KEYS[1]).await.unwrap(),
+            Some(VALUES[1].into())
+        );
+
+        let computing = client.get_or_insert_with_async(KEYS[2], || async {
+            sleep(Duration::from_secs(60)).await;
+            VALUES[2].as_bytes().to_vec()
+        });
+        let computing = tokio::time::timeout(Duration::from_millis(50), computing);
+        assert!(computing.await.is_err());
+        assert!(client.handle.in_flight.is_empty());
+        assert_eq!(client.get(KEYS[2]).await.unwrap(), None);
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn get_or_insert_with_computes_once() {
+        let mut client = Client::new(STORE_PATH, 4).unwrap();
+        let _ = client.delete(KEYS[0]).await;
+        let computed = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let mut tasks = Vec::new();
+        for _ in 0..5 {
+            let handle = client.handle();
+            let computed = Arc::clone(&computed);
+            tasks.push(tokio::spawn(async move {
+                handle
+                    .get_or_insert_with_async(KEYS[0], || async move {
+                        computed.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        sleep(Duration::from_millis(100)).await;
+                        VALUES[0].as_bytes().to_vec()
+                    })
+                    .await
+            }));
         }
+        for task in tasks {
+            assert_eq!(&task.await.unwrap().unwrap()[..], VALUES[0].as_bytes());
+        }
+        assert_eq!(computed.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let value = client
+            .get_or_insert_with(KEYS[0], || unreachable!("value is cached"))
+            .await;
+        assert_eq!(&value.unwrap()[..], VALUES[0].as_bytes());
+        assert!(client.handle.in_flight.is_empty());
 
         client.close().await;
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn set_and_clear() {
-        let mut client = Client::new(STORE_PATH, 2);
+    async fn subscribers_hear_of_every_change() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        let mut events = client.subscribe().unwrap();
 
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
+        let _ = client
+            .set_str(KEYS[0].to_string(), VALUES[0].to_string())
+            .await;
+        let _ = client.get(KEYS[0]).await;
+        let _ = client.delete(KEYS[0]).await;
+        let _ = client.delete(KEYS[1]).await;
+        client.clear().await.unwrap();
 
-        insert_test_data(&mut client, &keys, &values).await;
-        let _ = client.clear().await;
+        let key = KEYS[0].to_string();
+        assert_eq!(
+            events.recv().await.unwrap(),
+            CacheEvent::Set { key: key.clone() }
+        );
+        assert_eq!(events.recv().await.unwrap(), CacheEvent::Deleted { key });
+        // failed deletes and reads are not changes
+        assert_eq!(events.recv().await.unwrap(), CacheEvent::Cleared);
 
-        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
-        let expected_values: Vec<io::Result<Option<String>>> =
-            keys.into_iter().map(|_| Ok(None)).collect();
+        client.close().await;
+        assert_eq!(
+            events.recv().await,
+            Err(broadcast::error::RecvError::Closed)
+        );
+        assert!(matches!(client.subscribe(), Err(CacheError::StoreClosed)));
+    }
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn scan_glob_lists_the_keys_matching_a_pattern() {
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .max_entries(2)
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
+        let keys = [
+            "user:1:session",
+            "user:12:session",
+            "user:1:profile",
+            "user:x:sessions",
+            "admin:1:session",
+            "user:7",
+        ];
+        // most of them are only on disk, past max_entries
+        for key in keys {
+            client.set(key, "1").await.unwrap();
         }
 
+        let scan = |pattern: &'static str| {
+            let handle = client.handle();
+            async move { handle.scan_glob(pattern).await.unwrap() }
+        };
+        assert_eq!(
+            scan("user:*:session").await,
+            ["user:12:session", "user:1:session"]
+        );
+        assert_eq!(
+            scan("*:1:*").await,
+            ["admin:1:session", "user:1:profile", "user:1:session"]
+        );
+        assert_eq!(
+            scan("user:?:*s*").await,
+            ["user:1:session", "user:x:sessions"]
+        );
+        assert_eq!(scan("user:[0-9]").await, ["user:7"]);
+        assert_eq!(scan("user:[!0-9]:*").await, ["user:x:sessions"]);
+        assert!(scan("guest:*").await.is_empty());
+        assert_eq!(scan("*").await.len(), keys.len());
+
+        client.clear().await.unwrap();
         client.close().await;
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn persist_to_file() {
-        let mut client = Client::new(STORE_PATH, 2);
+    async fn read_only_store_serves_reads_and_refuses_writes() {
+        let path = "read_only_db";
+        for storage in [crate::Storage::Files, crate::Storage::Log] {
+            let _ = std::fs::remove_dir_all(path);
+            let err = Client::builder()
+                .path(path)
+                .read_only(true)
+                .build()
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), ErrorKind::NotFound);
 
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
+            let mut client = Client::builder()
+                .path(path)
+                .storage(storage)
+                .build()
+                .unwrap();
+            client.set_str("hey", "English").await.unwrap();
+            client.set_str("hola", "Spanish").await.unwrap();
+            client.close().await;
 
-        insert_test_data(&mut client, &keys, &values).await;
-        // close old client and store instances
-        client.close().await;
+            let open = || {
+                Client::builder()
+                    .path(path)
+                    .storage(storage)
+                    .workers(2)
+                    .read_only(true)
+                    .build()
+            };
+            let mut client = open().unwrap();
+            // readers share the store, but keep writers off it
+            let mut other = open().unwrap();
+            let err = Client::new(path, 2).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::ResourceBusy);
 
-        // Open new store instance
-        let mut client = Client::new(STORE_PATH, 2);
+            assert_eq!(
+                client.get_str("hey").await.unwrap(),
+                Some("English".to_string())
+            );
+            assert_eq!(
+                other.get_str("hola").await.unwrap(),
+                Some("Spanish".to_string())
+            );
+            let mut keys = client.keys().await.unwrap();
+            keys.sort();
+            assert_eq!(keys, ["hey", "hola"]);
+            assert_eq!(client.scan_prefix("ho").await.unwrap().len(), 1);
 
-        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
-        let expected_values: Vec<io::Result<Option<String>>> = values
-            .into_iter()
-            .map(|v| Ok(Some(v.to_string())))
-            .collect();
+            let refused = [
+                client.set_str("hey", "Anglais").await.err(),
+                client.delete("hey").await.err(),
+                client.delete_prefix("h").await.err(),
+                client.increment("count", 1).await.err(),
+                client.clear().await.err(),
+            ];
+            for err in refused {
+                assert!(matches!(err, Some(CacheError::ReadOnly)));
+            }
+            assert_eq!(
+                client.get_str("hey").await.unwrap(),
+                Some("English".to_string())
+            );
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+            client.close().await;
+            other.close().await;
+            // closed, the readers let a writer in again
+            let mut client = Client::builder()
+                .path(path)
+                .storage(storage)
+                .build()
+                .unwrap();
+            assert_eq!(client.keys().await.unwrap().len(), 2);
+            client.close().await;
         }
 
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn delete_prefix_is_counted_and_published() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        let keys: Vec<String> = (0..5).map(|i| format!("user:{i}")).collect();
+        for key in &keys {
+            client.set(key.clone(), b"1".to_vec()).await.unwrap();
+        }
+        client
+            .set("admin:0".to_string(), b"1".to_vec())
+            .await
+            .unwrap();
+        let before = client.stats().await.unwrap().deletes;
+        let mut events = client.subscribe().unwrap();
+
+        assert_eq!(client.delete_prefix("user:").await.unwrap(), 5);
+        assert_eq!(client.delete_prefix("user:").await.unwrap(), 0);
+        client.delete("admin:0").await.unwrap();
+
+        assert_eq!(client.stats().await.unwrap().deletes, before + 6);
+        let mut deleted = Vec::new();
+        for _ in 0..5 {
+            match events.recv().await.unwrap() {
+                CacheEvent::Deleted { key } => deleted.push(key),
+                event => panic!("unexpected event: {:?}", event),
+            }
+        }
+        deleted.sort();
+        assert_eq!(deleted, keys);
+        assert_eq!(
+            events.recv().await.unwrap(),
+            CacheEvent::PrefixDeleted {
+                prefix: "user:".to_string(),
+                count: 5
+            }
+        );
+        // the prefix that matched nothing published nothing
+        assert_eq!(
+            events.recv().await.unwrap(),
+            CacheEvent::Deleted {
+                key: "admin:0".to_string()
+            }
+        );
+
         client.close().await;
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn persist_to_file_after_delete() {
-        let mut client = Client::new(STORE_PATH, 2);
+    async fn lagging_subscriber_skips_the_oldest_events() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        let mut events = client.subscribe().unwrap();
 
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
-        let keys_to_delete = keys[2..].to_vec();
+        let total = crate::store::EVENT_CAPACITY + 10;
+        for i in 0..total {
+            let _ = client.set_str(format!("{i}"), VALUES[0].to_string()).await;
+        }
 
-        insert_test_data(&mut client, &keys, &values).await;
-        delete_keys(&mut client, &keys_to_delete).await;
+        assert_eq!(
+            events.recv().await,
+            Err(broadcast::error::RecvError::Lagged(10))
+        );
+        assert_eq!(
+            events.recv().await.unwrap(),
+            CacheEvent::Set {
+                key: "10".to_string()
+            }
+        );
 
-        // Close the store
+        client.clear().await.unwrap();
         client.close().await;
+    }
 
-        // Open new store instance
-        let mut client = Client::new(STORE_PATH, 2);
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn loader_fills_in_missing_keys_once() {
+        let loaded = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = loaded.clone();
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .loader(move |key| {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                let key = key.to_string();
+                Box::pin(async move {
+                    match &key[..] {
+                        "hey" => Ok(Some("English".to_string())),
+                        "broken" => Err(io::Error::other("database is down")),
+                        _ => Ok(None),
+                    }
+                })
+            })
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
 
-        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
-        let mut expected_values: Vec<io::Result<Option<String>>> = values[..2]
-            .into_iter()
-            .map(|v| Ok(Some(v.to_string())))
-            .collect();
-        for _ in 0..keys_to_delete.len() {
-            expected_values.push(Ok(None));
+        for _ in 0..2 {
+            assert_eq!(
+                client.get_str(KEYS[0]).await.unwrap(),
+                Some(VALUES[0].to_string())
+            );
         }
+        assert_eq!(loaded.load(std::sync::atomic::Ordering::SeqCst), 1);
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+        // keys the loader does not know are not stored, so it is asked each time
+        for _ in 0..2 {
+            assert_eq!(client.get(KEYS[2]).await.unwrap(), None);
         }
+        assert_eq!(loaded.load(std::sync::atomic::Ordering::SeqCst), 3);
+        assert!(!client.contains_key(KEYS[2]).await.unwrap());
+
+        let result = client.get("broken").await;
+        assert!(matches!(result, Err(CacheError::Io(_))));
 
+        client.clear().await.unwrap();
         client.close().await;
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn persist_to_file_after_clear() {
-        let mut client = Client::new(STORE_PATH, 2);
+    async fn workers_outlive_panicking_actions() {
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .loader(|key| panic!("no loader for {:?}", key))
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
+        assert_eq!(client.workers_alive(), 2);
 
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
+        // more panics than there are workers
+        for _ in 0..4 {
+            let result = client.get(KEYS[0]).await;
+            assert!(matches!(result, Err(CacheError::ChannelClosed)));
+        }
+        assert_eq!(client.workers_alive(), 2);
 
-        insert_test_data(&mut client, &keys, &values).await;
-        let _ = client.clear().await;
+        client
+            .set_str(KEYS[0].to_string(), VALUES[0].to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            client.get_str(KEYS[0]).await.unwrap(),
+            Some(VALUES[0].to_string())
+        );
 
-        // Close the store
+        client.clear().await.unwrap();
         client.close().await;
+        assert_eq!(client.workers_alive(), 0);
+    }
 
-        // Open new store instance
-        let mut client = Client::new(STORE_PATH, 2);
-
-        let received_values = get_values_for_keys(&mut client, keys.clone()).await;
-        let expected_values: Vec<io::Result<Option<String>>> =
-            keys.into_iter().map(|_| Ok(None)).collect();
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn in_memory_client_creates_no_directory() {
+        let path = "in_memory_db";
+        let mut client = Client::builder()
+            .path(path)
+            .workers(2)
+            .in_memory()
+            .build()
+            .unwrap();
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+        insert_test_data(&mut client, &KEYS, &VALUES).await;
+        let received_values = get_values_for_keys(&mut client, KEYS.to_vec()).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.to_string()));
         }
 
+        delete_keys(&mut client, &vec![KEYS[0]]).await;
+        assert!(!client.contains_key(KEYS[0]).await.unwrap());
+        assert_eq!(client.increment("counter", 3).await.unwrap(), 3);
+        assert_eq!(client.keys().await.unwrap().len(), KEYS.len());
+
+        let _ = client
+            .set_with_ttl(
+                KEYS[0].to_string(),
+                VALUES[0].as_bytes().to_vec(),
+                Duration::from_millis(100),
+            )
+            .await;
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(client.get(KEYS[0]).await.unwrap(), None);
+
+        client.clear().await.unwrap();
+        assert!(client.keys().await.unwrap().is_empty());
+        assert!(!std::path::Path::new(path).exists());
+        client.close().await;
+
+        // no path is needed either
+        let mut client = Client::builder().workers(2).in_memory().build().unwrap();
+        let _ = client
+            .set_str(KEYS[0].to_string(), VALUES[0].to_string())
+            .await;
+        assert_eq!(
+            client.get_str(KEYS[0]).await.unwrap(),
+            Some(VALUES[0].to_string())
+        );
         client.close().await;
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn close_must_close_store() {
-        let mut client = Client::new(STORE_PATH, 2);
-        let _ = client.set(KEYS[0].to_string(), VALUES[0].to_string()).await;
+    async fn iter_streams_entries_while_keeping_few_in_memory() {
+        use futures_util::StreamExt;
+
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .max_entries(10)
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
 
-        assert!(client.get(KEYS[0]).await.is_ok());
+        let value = vec![b'v'; 1024];
+        let entries: Vec<(String, Vec<u8>)> = (0..500)
+            .map(|i| (format!("key-{i:03}"), value.clone()))
+            .collect();
+        client.set_many(entries).await.unwrap();
+
+        let mut stream = std::pin::pin!(client.iter());
+        let mut count = 0;
+        while let Some(item) = stream.next().await {
+            let (key, got) = item.unwrap();
+            assert_eq!(key, format!("key-{count:03}"));
+            assert_eq!(&got[..], &value[..]);
+            count += 1;
+
+            // keys deleted before their turn are skipped
+            if count == 250 {
+                client.delete("key-499").await.unwrap();
+            }
+        }
+        assert_eq!(count, 499);
+        assert!(client.stats().await.unwrap().entry_count <= 10);
 
+        client.clear().await.unwrap();
         client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn store_recovers_from_its_directory_being_removed() {
+        let path = "removed_db";
+        for storage in [crate::Storage::Files, crate::Storage::Log] {
+            let _ = std::fs::remove_dir_all(path);
+            let options = StoreOptions {
+                storage,
+                ..StoreOptions::default()
+            };
+            let mut client = Client::with_options(path, 2, options).unwrap();
+            client
+                .set_str("hey".to_string(), "English".to_string())
+                .await
+                .unwrap();
+
+            std::fs::remove_dir_all(path).unwrap();
+            assert!(!client.store_exists());
+            if storage == crate::Storage::Files {
+                // a set finds its directory gone and makes it again
+                client
+                    .set_str("hi".to_string(), "English".to_string())
+                    .await
+                    .unwrap();
+                assert!(client.store_exists());
+            }
+
+            client.ensure_store().await.unwrap();
+            assert!(client.store_exists());
+            client
+                .set_str("bonjour".to_string(), "French".to_string())
+                .await
+                .unwrap();
+            // the store has its lock back
+            let err = Client::new(path, 2).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::ResourceBusy);
+
+            // what memory held was written back, so it outlives the store
+            client.reopen().await.unwrap();
+            assert_eq!(
+                client.get_str("hey").await.unwrap(),
+                Some("English".to_string())
+            );
+            assert_eq!(
+                client.get_str("bonjour").await.unwrap(),
+                Some("French".to_string())
+            );
+            client.close().await;
+        }
 
-        assert!(client.get(KEYS[0]).await.is_err());
+        std::fs::remove_dir_all(path).unwrap();
     }
 
     async fn delete_keys(client: &mut Client, keys_to_delete: &Vec<&str>) {
         for k in keys_to_delete {
-            let _ = &client.delete(*k).await;
+            let _ = &client.delete(k).await;
         }
     }
 
     async fn get_values_for_keys(
         client: &mut Client,
         keys: Vec<&str>,
-    ) -> Vec<io::Result<Option<String>>> {
+    ) -> Vec<Result<Option<String>, CacheError>> {
         let mut received_values = Vec::with_capacity(keys.len());
 
         for k in keys {
-            let _ = &received_values.push(client.get(k).await);
+            let _ = &received_values.push(client.get_str(k).await);
         }
 
         received_values
     }
 
-    async fn insert_test_data(client: &mut Client, keys: &Vec<&str>, values: &Vec<&str>) {
-        for (k, v) in keys.clone().into_iter().zip(values) {
-            let _ = &client.set(k.to_string(), v.to_string()).await;
+    async fn insert_test_data(client: &mut Client, keys: &[&str], values: &[&str]) {
+        for (k, v) in keys.iter().zip(values) {
+            let _ = &client.set_str(k.to_string(), v.to_string()).await;
         }
     }
 }
@@ -0,0 +1,308 @@
+use std::error::Error;
+use std::fmt;
+use tokio::io;
+
+/// Errors returned by the [`Client`](crate::Client)
+#[derive(Debug)]
+pub enum CacheError {
+    /// A filesystem operation on the store failed
+    Io(io::Error),
+    /// The worker handling the request went away before responding
+    ChannelClosed,
+    /// A value could not be converted to its stored form, or a JSON document could
+    /// not be imported. `key` names the key of the value, if the failure was one's
+    Serialization {
+        key: Option<String>,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The value stored at `key` could not be read back as the type asked for, as
+    /// when the type of a typed client's values has changed since it was stored
+    Deserialization {
+        key: String,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    /// The store has been closed and accepts no more requests
+    StoreClosed,
+    /// The file of `key` failed its checksum, so its value cannot be trusted
+    Corrupt { key: String },
+    /// The loader called for a missing value failed
+    Loader(Box<dyn Error + Send + Sync>),
+    /// A value of `size` bytes was refused for being over the store's
+    /// `max_value_bytes`, or its `max_bytes`, of `limit`
+    ValueTooLarge { size: usize, limit: usize },
+    /// A [`BlockingClient`](crate::BlockingClient) was called from within an async
+    /// runtime, where blocking would stall it
+    NestedRuntime,
+    /// The store did not answer within the client's timeout. The operation may
+    /// still be carried out afterwards
+    Timeout,
+    /// The store was opened read-only, so it refuses to change its entries
+    ReadOnly,
+    /// The file of `key` was written in a newer format, of `version`, than this
+    /// version of the crate can read
+    UnsupportedFormat { key: String, version: u8 },
+    /// The file of `key` would lie outside the store's directory, as when part of
+    /// its path was swapped for a symlink leading elsewhere, so it was not touched
+    InvalidKey { key: String },
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Io(e) => write!(f, "io error: {}", e),
+            CacheError::ChannelClosed => write!(f, "channel closed before a response was sent"),
+            CacheError::Serialization {
+                key: Some(key),
+                source,
+            } => write!(f, "cannot serialize the value of key {:?}: {}", key, source),
+            CacheError::Serialization { key: None, source } => {
+                write!(f, "serialization error: {}", source)
+            }
+            CacheError::Deserialization { key, source } => {
+                write!(
+                    f,
+                    "cannot deserialize the value of key {:?}: {}",
+                    key, source
+                )
+            }
+            CacheError::StoreClosed => write!(f, "store is closed"),
+            CacheError::Corrupt { key } => write!(f, "value of key {:?} is corrupt", key),
+            CacheError::Loader(e) => write!(f, "loader error: {}", e),
+            CacheError::ValueTooLarge { size, limit } => write!(
+                f,
+                "value of {} bytes is larger than the limit of {} bytes",
+                size, limit
+            ),
+            CacheError::NestedRuntime => {
+                write!(f, "blocking client called from within an async runtime")
+            }
+            CacheError::Timeout => write!(f, "store did not respond in time"),
+            CacheError::ReadOnly => write!(f, "store is read-only"),
+            CacheError::UnsupportedFormat { key, version } => write!(
+                f,
+                "value of key {:?} is stored in format version {}, which is not supported",
+                key, version
+            ),
+            CacheError::InvalidKey { key } => {
+                write!(f, "file of key {:?} lies outside the store", key)
+            }
+        }
+    }
+}
+
+impl Error for CacheError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CacheError::Io(e) => Some(e),
+            CacheError::Serialization { source, .. }
+            | CacheError::Deserialization { source, .. }
+            | CacheError::Loader(source) => Some(source.as_ref()),
+            CacheError::ChannelClosed
+            | CacheError::StoreClosed
+            | CacheError::Corrupt { .. }
+            | CacheError::ValueTooLarge { .. }
+            | CacheError::NestedRuntime
+            | CacheError::Timeout
+            | CacheError::ReadOnly
+            | CacheError::UnsupportedFormat { .. }
+            | CacheError::InvalidKey { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for CacheError {
+    fn from(e: io::Error) -> Self {
+        if let Some(corrupt) = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<CorruptEntry>())
+        {
+            return CacheError::Corrupt {
+                key: corrupt.key.clone(),
+            };
+        }
+        if e.get_ref().is_some_and(|inner| inner.is::<NotUtf8>()) {
+            let not_utf8 = e.into_inner().unwrap().downcast::<NotUtf8>().unwrap();
+            return CacheError::Deserialization {
+                key: not_utf8.key,
+                source: Box::new(not_utf8.source),
+            };
+        }
+        if let Some(too_large) = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<ValueTooLarge>())
+        {
+            return CacheError::ValueTooLarge {
+                size: too_large.size,
+                limit: too_large.limit,
+            };
+        }
+        if let Some(unsupported) = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<UnsupportedFormat>())
+        {
+            return CacheError::UnsupportedFormat {
+                key: unsupported.key.clone(),
+                version: unsupported.version,
+            };
+        }
+        if let Some(invalid) = e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<InvalidKey>())
+        {
+            return CacheError::InvalidKey {
+                key: invalid.key.clone(),
+            };
+        }
+        if e.get_ref().is_some_and(|inner| inner.is::<ReadOnly>()) {
+            return CacheError::ReadOnly;
+        }
+
+        CacheError::Io(e)
+    }
+}
+
+/// Copies `e` for answering several callers with it, keeping the marker it carries,
+/// if any, so that each copy still turns into the same [`CacheError`]
+pub(crate) fn duplicate(e: &io::Error) -> io::Error {
+    if let Some(code) = e.raw_os_error() {
+        return io::Error::from_raw_os_error(code);
+    }
+    let inner = match e.get_ref() {
+        Some(inner) => inner,
+        None => return io::Error::from(e.kind()),
+    };
+
+    let kind = e.kind();
+    if let Some(m) = inner.downcast_ref::<CorruptEntry>() {
+        io::Error::new(kind, m.clone())
+    } else if let Some(m) = inner.downcast_ref::<NotUtf8>() {
+        io::Error::new(kind, m.clone())
+    } else if let Some(m) = inner.downcast_ref::<ValueTooLarge>() {
+        io::Error::new(kind, m.clone())
+    } else if let Some(m) = inner.downcast_ref::<UnsupportedFormat>() {
+        io::Error::new(kind, m.clone())
+    } else if let Some(m) = inner.downcast_ref::<InvalidKey>() {
+        io::Error::new(kind, m.clone())
+    } else if let Some(m) = inner.downcast_ref::<ReadOnly>() {
+        io::Error::new(kind, m.clone())
+    } else {
+        io::Error::new(kind, e.to_string())
+    }
+}
+
+/// Carried by the io errors the store raises for corrupt value files,
+/// to be turned into [`CacheError::Corrupt`] once they reach the client
+#[derive(Clone, Debug)]
+pub(crate) struct CorruptEntry {
+    pub(crate) key: String,
+}
+
+impl fmt::Display for CorruptEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "value file of key {:?} is corrupt", self.key)
+    }
+}
+
+impl Error for CorruptEntry {}
+
+/// Carried by the io errors the store raises for values over `max_value_bytes` or
+/// `max_bytes`, to be turned into [`CacheError::ValueTooLarge`] once they reach
+/// the client
+#[derive(Clone, Debug)]
+pub(crate) struct ValueTooLarge {
+    pub(crate) size: usize,
+    pub(crate) limit: usize,
+}
+
+impl fmt::Display for ValueTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "a value of {} bytes is larger than the limit of {} bytes",
+            self.size, self.limit
+        )
+    }
+}
+
+impl Error for ValueTooLarge {}
+
+/// Carried by the io errors the store raises for value files in a format too new
+/// for it, to be turned into [`CacheError::UnsupportedFormat`] once they reach the client
+#[derive(Clone, Debug)]
+pub(crate) struct UnsupportedFormat {
+    pub(crate) key: String,
+    pub(crate) version: u8,
+}
+
+impl fmt::Display for UnsupportedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "value file of key {:?} has header version {}, which is not supported",
+            self.key, self.version
+        )
+    }
+}
+
+impl Error for UnsupportedFormat {}
+
+/// Carried by the io errors the store raises for keys whose file would lie outside
+/// it, to be turned into [`CacheError::InvalidKey`] once they reach the client
+#[derive(Clone, Debug)]
+pub(crate) struct InvalidKey {
+    pub(crate) key: String,
+}
+
+impl InvalidKey {
+    pub(crate) fn error(key: &str) -> io::Error {
+        let key = key.to_string();
+        io::Error::new(io::ErrorKind::InvalidInput, InvalidKey { key })
+    }
+}
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the file of key {:?} lies outside the store", self.key)
+    }
+}
+
+impl Error for InvalidKey {}
+
+/// Carried by the io errors a read-only store raises for writes,
+/// to be turned into [`CacheError::ReadOnly`] once they reach the client
+#[derive(Clone, Debug)]
+pub(crate) struct ReadOnly;
+
+impl ReadOnly {
+    pub(crate) fn error() -> io::Error {
+        io::Error::new(io::ErrorKind::PermissionDenied, ReadOnly)
+    }
+}
+
+impl fmt::Display for ReadOnly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the store is read-only")
+    }
+}
+
+impl Error for ReadOnly {}
+
+/// Carried by the io errors raised for string values that are not valid UTF-8,
+/// to be turned into [`CacheError::Deserialization`] once they reach the client
+#[derive(Clone, Debug)]
+pub(crate) struct NotUtf8 {
+    pub(crate) key: String,
+    pub(crate) source: std::str::Utf8Error,
+}
+
+impl fmt::Display for NotUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "value of key {:?} is not valid UTF-8: {}",
+            self.key, self.source
+        )
+    }
+}
+
+impl Error for NotUtf8 {}
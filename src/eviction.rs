@@ -0,0 +1,138 @@
+use crate::lru::LruOrder;
+use crate::store::Eviction;
+use std::collections::{BTreeMap, HashMap};
+
+/// Picks the entry a store drops from memory once it holds too much. The store
+/// tells it of every change to its memory while holding it, so that both always
+/// track the same keys
+pub(crate) trait EvictionPolicy: Send {
+    /// `key` was read from memory
+    fn on_access(&mut self, key: &str);
+
+    /// `key` was cached, either for the first time or replacing its entry
+    fn on_insert(&mut self, key: &str);
+
+    /// `key` was dropped from memory other than by being evicted
+    fn on_remove(&mut self, key: &str);
+
+    /// Forgets and returns the key to evict next
+    fn evict_candidate(&mut self) -> Option<String>;
+
+    fn clear(&mut self);
+}
+
+pub(crate) fn new_policy(eviction: Eviction) -> Box<dyn EvictionPolicy> {
+    match eviction {
+        Eviction::Lru => Box::<Lru>::default(),
+        Eviction::Lfu => Box::<Lfu>::default(),
+        Eviction::Fifo => Box::<Fifo>::default(),
+    }
+}
+
+/// Evicts the least recently used key
+#[derive(Default)]
+pub(crate) struct Lru(LruOrder);
+
+impl EvictionPolicy for Lru {
+    fn on_access(&mut self, key: &str) {
+        self.0.touch(key);
+    }
+
+    fn on_insert(&mut self, key: &str) {
+        self.0.touch(key);
+    }
+
+    fn on_remove(&mut self, key: &str) {
+        self.0.remove(key);
+    }
+
+    fn evict_candidate(&mut self) -> Option<String> {
+        self.0.pop_oldest()
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Evicts the key cached first, however much it has been used since. Replacing
+/// the entry of a key keeps its place
+#[derive(Default)]
+pub(crate) struct Fifo(LruOrder);
+
+impl EvictionPolicy for Fifo {
+    fn on_access(&mut self, _key: &str) {}
+
+    fn on_insert(&mut self, key: &str) {
+        if !self.0.contains(key) {
+            self.0.touch(key);
+        }
+    }
+
+    fn on_remove(&mut self, key: &str) {
+        self.0.remove(key);
+    }
+
+    fn evict_candidate(&mut self) -> Option<String> {
+        self.0.pop_oldest()
+    }
+
+    fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Evicts the least frequently used key, sets counting as uses. Of keys used
+/// equally often, the least recently used one goes first
+#[derive(Default)]
+pub(crate) struct Lfu {
+    /// Use count and tick of the last use of each key
+    uses: HashMap<String, (u64, u64)>,
+    /// Keys by use count, then by the tick of their last use
+    keys: BTreeMap<(u64, u64), String>,
+    next_tick: u64,
+}
+
+impl Lfu {
+    fn record_use(&mut self, key: &str) {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+
+        let count = match self.uses.get(key) {
+            Some(&prev) => {
+                self.keys.remove(&prev);
+                prev.0 + 1
+            }
+            None => 1,
+        };
+        self.uses.insert(key.to_string(), (count, tick));
+        self.keys.insert((count, tick), key.to_string());
+    }
+}
+
+impl EvictionPolicy for Lfu {
+    fn on_access(&mut self, key: &str) {
+        self.record_use(key);
+    }
+
+    fn on_insert(&mut self, key: &str) {
+        self.record_use(key);
+    }
+
+    fn on_remove(&mut self, key: &str) {
+        if let Some(uses) = self.uses.remove(key) {
+            self.keys.remove(&uses);
+        }
+    }
+
+    fn evict_candidate(&mut self) -> Option<String> {
+        let (_, key) = self.keys.pop_first()?;
+        self.uses.remove(&key);
+        Some(key)
+    }
+
+    fn clear(&mut self) {
+        self.uses.clear();
+        self.keys.clear();
+    }
+}
@@ -1,36 +1,1156 @@
+use crate::error::{CorruptEntry, InvalidKey, UnsupportedFormat};
+use crate::store::{
+    CompactReport, Compression, Durability, Entry, Layout, ShardHasher, Storage, StoreOptions,
+};
 use std::io::ErrorKind::NotFound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::{fs, io};
 
-pub(crate) async fn save_to_file(store_path: &str, key: &String, value: &String) -> io::Result<()> {
-    let file_path = format!("{}/{}", store_path, key);
-    fs::write(file_path, value.clone()).await
+/// Longest file name, in bytes, that common filesystems accept
+const MAX_FILE_NAME_LEN: usize = 255;
+
+/// Name given to the file of the empty key. A lone `%` is never produced by
+/// percent-encoding so it cannot clash with any other key
+const EMPTY_KEY_FILE_NAME: &str = "%";
+
+/// Names Windows keeps for devices, whatever their extension. `com` and `lpt`
+/// are reserved followed by any digit
+const RESERVED_FILE_NAMES: [&str; 4] = ["con", "prn", "aux", "nul"];
+
+/// First byte of a value file with a binary header. It never starts the text
+/// header of older files, nor any UTF-8 text
+const HEADER_MAGIC: u8 = 0xff;
+
+/// Version of the binary header written, following [`HEADER_MAGIC`]
+const HEADER_VERSION: u8 = 1;
+
+/// Flags of the binary header, saying which of its optional parts follow
+const HAS_EXPIRY: u8 = 0b001;
+const HAS_IDLE_TTL: u8 = 0b010;
+const IS_GZIP: u8 = 0b100;
+
+/// Field of the text header of older files holding the checksum of the value bytes
+const CRC32_FIELD: &str = "crc32";
+
+/// Header field holding the idle time, in milliseconds, after which the value
+/// expires, for values whose expiry slides with each read
+const IDLE_FIELD: &str = "idle";
+
+/// Header field marking values that are stored gzip compressed
+const GZIP_FIELD: &str = "gzip";
+
+/// File in the store directory that an open store holds an advisory lock on
+const LOCK_FILE_NAME: &str = ".lock";
+
+/// File in the store directory naming the layout the store was created with
+const LAYOUT_FILE_NAME: &str = ".layout";
+
+/// Starts every snapshot archive, naming the version of its layout
+const SNAPSHOT_MAGIC: &[u8] = b"diskcache_rs snapshot v2\n";
+
+/// Started the archives written before they kept how long entries may stay idle
+const SNAPSHOT_MAGIC_V1: &[u8] = b"diskcache_rs snapshot v1\n";
+
+/// Distinguishes the temporary files of concurrent writes within this process
+static TMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes `entry` to a temporary file next to the key's file and renames it into
+/// place, so that readers only ever see the old or the new value in full.
+///
+/// The value is compressed as `options.compression` says, and flushed to disk
+/// as far as `options.durability` asks
+pub(crate) async fn save_to_file(
+    root: &Path,
+    key: &str,
+    entry: &Entry,
+    options: &StoreOptions,
+) -> io::Result<()> {
+    let file_path = key_file(root, key, options).await?;
+    if let Some(parent) = file_path.parent() {
+        create_dirs(parent, options.dir_mode).await?;
+    }
+
+    let content = encode_entry(entry, options.compression)?;
+    let sync = options.durability != Durability::None;
+    let mode = options.file_mode;
+    match write_atomically(&file_path, &content, sync, mode).await {
+        // the directory was removed from under the store since it was created above
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if let Some(parent) = file_path.parent() {
+                create_dirs(parent, options.dir_mode).await?;
+            }
+            write_atomically(&file_path, &content, sync, mode).await?;
+        }
+        written => written?,
+    }
+    if options.durability == Durability::Full {
+        sync_dirs(root, &file_path).await?;
+    }
+
+    Ok(())
 }
 
-pub(crate) async fn get_from_file(store_path: &str, key: &String) -> io::Result<Option<String>> {
-    let file_path = format!("{}/{}", store_path, key);
-    let result = fs::read_to_string(file_path).await;
+/// Writes `content` to a temporary file next to `path` and renames it over `path`
+async fn write_atomically(
+    path: &Path,
+    content: &[u8],
+    sync: bool,
+    mode: Option<u32>,
+) -> io::Result<()> {
+    let tmp_path = tmp_path_for(path);
+    let written = write_tmp_file(&tmp_path, content, sync, mode).await;
+    let renamed = match written {
+        Ok(()) => fs::rename(&tmp_path, path).await,
+        Err(e) => Err(e),
+    };
+    if renamed.is_err() {
+        let _ = fs::remove_file(&tmp_path).await;
+    }
+
+    renamed
+}
+
+async fn write_tmp_file(
+    tmp_path: &Path,
+    content: &[u8],
+    sync: bool,
+    mode: Option<u32>,
+) -> io::Result<()> {
+    let mut options = file_options(mode);
+    options.write(true).truncate(true);
+    let mut file = fs::OpenOptions::from(options).open(tmp_path).await?;
+    file.write_all(content).await?;
+    if !sync {
+        // waits for the write, which the file may still be running in the background
+        return file.flush().await;
+    }
+
+    file.sync_all().await
+}
+
+/// Options creating a file if it is missing, with the permission bits `mode` on
+/// Unix. Callers still say how the file is written
+#[cfg_attr(not(unix), allow(unused_variables))]
+pub(crate) fn file_options(mode: Option<u32>) -> std::fs::OpenOptions {
+    let mut options = std::fs::OpenOptions::new();
+    options.create(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, mode);
+    }
+
+    options
+}
+
+/// Creates the directory at `path` and its missing parents, with the permission
+/// bits `mode` on Unix
+#[cfg_attr(not(unix), allow(unused_variables))]
+async fn create_dirs(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        builder.mode(mode);
+    }
+
+    builder.create(path).await
+}
+
+/// Temporary file names contain `.`, which `encode_key` always escapes,
+/// so they are never mistaken for keys
+fn tmp_path_for(file_path: &Path) -> PathBuf {
+    let name = file_path.file_name().unwrap_or_default().to_string_lossy();
+    let suffix = format!(
+        ".tmp.{}.{}",
+        std::process::id(),
+        TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    // encoded names are ASCII, so truncating them to fit the suffix is safe
+    let keep = MAX_FILE_NAME_LEN
+        .saturating_sub(suffix.len())
+        .min(name.len());
+
+    file_path.with_file_name(format!("{}{}", &name[..keep], suffix))
+}
+
+pub(crate) async fn get_from_file(
+    root: &Path,
+    key: &str,
+    options: &StoreOptions,
+) -> io::Result<Option<Entry>> {
+    let file_path = key_file(root, key, options).await?;
+    let result = fs::read(file_path).await;
 
     match result {
-        Ok(value) => Ok(Some(value)),
-        Err(_) => Ok(None),
+        Ok(content) => decode_entry(key, content).map(Some),
+        Err(e) if e.kind() == NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Checks whether an unexpired entry is persisted for `key`, reading no
+/// further than the expiry header. A header that [`get_from_file`] could not read
+/// fails the same way
+pub(crate) async fn exists_in_file(
+    root: &Path,
+    key: &str,
+    options: &StoreOptions,
+) -> io::Result<bool> {
+    let file = match fs::File::open(key_file(root, key, options).await?).await {
+        Ok(f) => f,
+        Err(e) if e.kind() == NotFound => return Ok(false),
+        Err(e) => return Err(e),
+    };
+
+    let mut reader = BufReader::new(file);
+    let expires_at = if reader.fill_buf().await?.first() == Some(&HEADER_MAGIC) {
+        read_header(key, reader.buffer())?.0.expires_at
+    } else {
+        let mut header = Vec::new();
+        reader.read_until(b'\n', &mut header).await?;
+        // a file without a line break is a value alone, as decode_entry reads it
+        match header.strip_suffix(b"\n") {
+            Some(line) => {
+                parse_text_header(line)
+                    .ok_or_else(|| corrupt(key))?
+                    .expires_at
+            }
+            None => None,
+        }
+    };
+
+    Ok(!matches!(expires_at, Some(t) if t <= SystemTime::now()))
+}
+
+pub(crate) async fn remove_from_file(
+    root: &Path,
+    key: &str,
+    options: &StoreOptions,
+) -> io::Result<()> {
+    let file_path = key_file(root, key, options).await?;
+    fs::remove_file(&file_path).await?;
+
+    // prune the directories that were only created to hold this long key,
+    // leaving the shard directories in place for other keys
+    let mut dir = file_path.parent();
+    while let Some(d) = dir {
+        let is_chunk = d
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().ends_with('%'));
+        if !is_chunk || fs::remove_dir(d).await.is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+
+    if options.durability == Durability::Full {
+        sync_dirs(root, &file_path).await?;
+    }
+
+    Ok(())
+}
+
+/// Deletes every shard directory, and the key files in them, leaving `store_path`
+/// itself in place along with anything else in it. Above all the lock file stays,
+/// since a lock file deleted while held would let another store lock a new one.
+/// In the flat layout, the key files and the directories of long keys go instead
+pub(crate) async fn clear_from_file(store_path: &str, options: &StoreOptions) -> io::Result<()> {
+    let width = match options.layout {
+        Layout::Sharded { width, .. } => width,
+        Layout::Flat => {
+            let (key_files, dirs) = walk_blocking(store_path, options, |store_path, options| {
+                let dirs = sub_dirs(Path::new(store_path));
+                (list_key_files(store_path, options), dirs)
+            })
+            .await?;
+            for (_, path) in key_files {
+                match fs::remove_file(&path).await {
+                    Err(e) if e.kind() != NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            for dir in dirs {
+                if dir.to_string_lossy().ends_with('%') {
+                    match fs::remove_dir_all(dir).await {
+                        Err(e) if e.kind() != NotFound => return Err(e),
+                        _ => {}
+                    }
+                }
+            }
+            return Ok(());
+        }
+    };
+
+    let mut read_dir = match fs::read_dir(store_path).await {
+        Ok(v) => v,
+        Err(e) if e.kind() == NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    while let Some(dir_entry) = read_dir.next_entry().await? {
+        if !is_shard_name(&dir_entry.file_name(), width) || !dir_entry.file_type().await?.is_dir() {
+            continue;
+        }
+
+        match fs::remove_dir_all(dir_entry.path()).await {
+            Err(e) if e.kind() != NotFound => return Err(e),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the temporary files that writes cut short by a crash left in the
+/// shard directories, or in `store_path` itself under the flat layout, along with
+/// the shard directories and directories of long keys that hold nothing. Expects
+/// no write to be in progress
+pub(crate) async fn compact_store(
+    store_path: &str,
+    options: &StoreOptions,
+) -> io::Result<CompactReport> {
+    walk_blocking(store_path, options, |store_path, options| {
+        let width = match options.layout {
+            Layout::Sharded { width, .. } => Some(width),
+            Layout::Flat => None,
+        };
+        let mut report = CompactReport::default();
+        compact_dir(Path::new(store_path), width, &mut report)?;
+        Ok(report)
+    })
+    .await?
+}
+
+/// Compacts `dir` as [`compact_store`] does, returning whether it is left empty
+fn compact_dir(dir: &Path, width: Option<u8>, report: &mut CompactReport) -> io::Result<bool> {
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(e) if e.kind() == NotFound => return Ok(true),
+        Err(e) => return Err(e),
+    };
+
+    let mut empty = true;
+    for dir_entry in read_dir {
+        let dir_entry = dir_entry?;
+        let name = dir_entry.file_name();
+        let path = dir_entry.path();
+        let name = name.to_string_lossy();
+
+        if dir_entry.file_type()?.is_dir() {
+            let ours = name.ends_with('%')
+                || width.is_some_and(|w| is_shard_name(dir_entry.file_name().as_os_str(), w));
+            if ours && compact_dir(&path, width, report)? {
+                std::fs::remove_dir(&path)?;
+                report.dirs_removed += 1;
+                continue;
+            }
+        } else if name.contains(".tmp.") {
+            report.bytes_reclaimed += dir_entry.metadata()?.len();
+            std::fs::remove_file(&path)?;
+            report.tmp_files_removed += 1;
+            continue;
+        }
+        empty = false;
+    }
+
+    Ok(empty)
+}
+
+/// Whether `name` is that of a shard directory: `width` lowercase hex digits
+fn is_shard_name(name: &std::ffi::OsStr, width: u8) -> bool {
+    name.to_str().is_some_and(|n| {
+        n.len() == usize::from(width) && n.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    })
+}
+
+/// Names the layout of the store `options` describe, as recorded in its directory
+fn layout_name(options: &StoreOptions) -> String {
+    match (options.storage, options.layout) {
+        (Storage::Log, _) => "log".to_string(),
+        (_, Layout::Flat) => "files flat".to_string(),
+        (_, Layout::Sharded { depth, width }) => format!("files sharded {} {}", depth, width),
     }
 }
 
-pub(crate) async fn remove_from_file(store_path: &str, key: &String) -> io::Result<()> {
-    let file_path = format!("{}/{}", store_path, key);
-    fs::remove_file(file_path).await
+/// Fails with `InvalidInput` if the store at `store_path` was created with another
+/// layout than the one of `options`, or if that layout asks for shard directories
+/// the hash of a key cannot name. A store without a record of its layout, such as
+/// a new one, is given one, unless it is opened read-only
+pub(crate) fn check_layout(store_path: &str, options: &StoreOptions) -> io::Result<()> {
+    if let (Storage::Files, Layout::Sharded { depth, width }) = (options.storage, options.layout) {
+        if depth == 0 || width == 0 || u32::from(depth) * u32::from(width) > 8 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "{} levels of {} hex digits are not a valid layout",
+                    depth, width
+                ),
+            ));
+        }
+    }
+
+    let path = Path::new(store_path).join(LAYOUT_FILE_NAME);
+    let expected = layout_name(options);
+    match std::fs::read_to_string(&path) {
+        Ok(found) if found.trim_end() == expected => Ok(()),
+        Ok(found) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "store at {:?} has the layout {:?}, not {:?}",
+                store_path,
+                found.trim_end(),
+                expected
+            ),
+        )),
+        Err(e) if e.kind() == NotFound && !options.read_only => {
+            use std::io::Write;
+            let mut file = file_options(options.file_mode)
+                .write(true)
+                .truncate(true)
+                .open(&path)?;
+            writeln!(file, "{}", expected)
+        }
+        Err(e) if e.kind() == NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
 }
 
-pub(crate) async fn clear_from_file(store_path: &str) -> io::Result<()> {
-    if let Err(e) = fs::remove_dir_all(store_path).await {
-        if e.kind() != NotFound {
-            return Err(e);
+/// Creates the directory of the store, with the permission bits `mode` on Unix,
+/// naming `store_path` in the error if it cannot
+#[cfg_attr(not(unix), allow(unused_variables))]
+pub(crate) fn initialize_file_db(store_path: &str, mode: Option<u32>) -> io::Result<()> {
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, mode);
+    }
+
+    builder.create(store_path).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("cannot create store directory {:?}: {}", store_path, e),
+        )
+    })
+}
+
+/// Checks whether the lock file of the store at `store_path` is still there
+pub(crate) fn lock_file_exists(store_path: &str) -> bool {
+    Path::new(store_path).join(LOCK_FILE_NAME).exists()
+}
+
+/// Locks the store at `store_path` for this store alone, failing with `ResourceBusy`
+/// if another store, in this process or any other, has it open. The lock is released
+/// when the returned file is unlocked or closed, including when the process dies
+pub(crate) fn lock_store(store_path: &str) -> io::Result<std::fs::File> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(Path::new(store_path).join(LOCK_FILE_NAME))?;
+
+    match file.try_lock() {
+        Ok(()) => Ok(file),
+        Err(std::fs::TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            format!("store at {:?} is already in use", store_path),
+        )),
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Locks the store at `store_path` for reading, alongside any other read-only
+/// stores, failing with `ResourceBusy` if a store that writes has it open, and
+/// with `NotFound` if there is no store directory. A store that was never opened
+/// for writing has no lock file, and so nothing to lock
+pub(crate) fn share_store(store_path: &str) -> io::Result<Option<std::fs::File>> {
+    let file = match std::fs::File::open(Path::new(store_path).join(LOCK_FILE_NAME)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !Path::new(store_path).is_dir() => {
+            return Err(io::Error::new(
+                e.kind(),
+                format!("store directory {:?} does not exist", store_path),
+            ))
         }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
     };
 
+    match file.try_lock_shared() {
+        Ok(()) => Ok(Some(file)),
+        Err(std::fs::TryLockError::WouldBlock) => Err(io::Error::new(
+            io::ErrorKind::ResourceBusy,
+            format!("store at {:?} is being written to", store_path),
+        )),
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Reads every entry persisted under `store_path`, skipping files whose names
+/// are not valid encoded keys
+pub(crate) fn load_all_from_file(store_path: &str, options: &StoreOptions) -> Vec<(String, Entry)> {
+    list_key_files(store_path, options)
+        .into_iter()
+        .filter_map(|(key, path)| {
+            let content = std::fs::read(path).ok()?;
+            let entry = decode_entry(&key, content).ok()?;
+            Some((key, entry))
+        })
+        .collect()
+}
+
+/// Lists the keys persisted under `store_path` without reading their values
+pub(crate) async fn keys_from_file(store_path: &str, options: &StoreOptions) -> Vec<String> {
+    walk_blocking(store_path, options, list_key_files)
+        .await
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect()
+}
+
+/// Flushes every value file under `store_path` to disk, along with the
+/// directories holding them so that their creation and renames last too
+pub(crate) async fn sync_store(store_path: &str, options: &StoreOptions) -> io::Result<()> {
+    let (key_files, dirs) = walk_blocking(store_path, options, |store_path, options| {
+        let mut dirs = vec![PathBuf::from(store_path)];
+        let mut i = 0;
+        while i < dirs.len() {
+            let sub_dirs = sub_dirs(&dirs[i]);
+            dirs.extend(sub_dirs);
+            i += 1;
+        }
+        (list_key_files(store_path, options), dirs)
+    })
+    .await?;
+
+    for (_, path) in key_files {
+        fs::File::open(path).await?.sync_all().await?;
+    }
+
+    for dir in dirs {
+        sync_dir(&dir).await?;
+    }
+
     Ok(())
 }
 
-pub(crate) fn initialize_file_db(store_path: &str) {
-    let _ = std::fs::create_dir_all(store_path);
+/// Flushes the directories from the one that held `path` up to `store_path`, so
+/// that the creation, rename or removal of `path` and of its parents lasts
+async fn sync_dirs(store_path: &Path, path: &Path) -> io::Result<()> {
+    let mut dir = path.parent();
+    // compared by components, so that `db`, `db/` and `./db/.` stop at the same place
+    while let Some(d) = dir.filter(|d| d.starts_with(store_path)) {
+        // pruned chunk directories of a removed long key are gone
+        match sync_dir(d).await {
+            Err(e) if e.kind() == NotFound => {}
+            synced => synced?,
+        }
+        dir = d.parent();
+    }
+
+    Ok(())
+}
+
+/// Directories cannot be opened as files everywhere, but unix needs them synced
+#[cfg(unix)]
+async fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir).await?.sync_all().await
+}
+
+#[cfg(not(unix))]
+async fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Writes `entries` to a single archive at `out_path`.
+///
+/// After [`SNAPSHOT_MAGIC`], each entry is laid out as the key's length (u32) and
+/// bytes, the expiry in milliseconds since the unix epoch (u64, 0 if none), how
+/// long the entry may stay idle in milliseconds (u64, 0 if not set), then the
+/// value's length (u64) and bytes, all numbers big-endian
+pub(crate) async fn write_snapshot(out_path: &str, entries: &[(String, Entry)]) -> io::Result<()> {
+    let mut content = SNAPSHOT_MAGIC.to_vec();
+    for (key, entry) in entries {
+        let key_len = u32::try_from(key.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "key is too long"))?;
+        let expires_at = entry.expires_at.map_or(0, |t| {
+            t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+        });
+        let idle_ttl = entry.idle_ttl.map_or(0, |d| d.as_millis() as u64);
+
+        content.extend_from_slice(&key_len.to_be_bytes());
+        content.extend_from_slice(key.as_bytes());
+        content.extend_from_slice(&expires_at.to_be_bytes());
+        content.extend_from_slice(&idle_ttl.to_be_bytes());
+        content.extend_from_slice(&(entry.value.len() as u64).to_be_bytes());
+        content.extend_from_slice(&entry.value);
+    }
+
+    write_atomically(Path::new(out_path), &content, true, None).await
+}
+
+/// Reads back the entries of an archive written by [`write_snapshot`], or by the
+/// versions before it, whose entries have no idle time
+pub(crate) async fn read_snapshot(in_path: &str) -> io::Result<Vec<(String, Entry)>> {
+    let content = fs::read(in_path).await?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed snapshot archive");
+
+    let (mut rest, has_idle_ttl) = match content.strip_prefix(SNAPSHOT_MAGIC) {
+        Some(rest) => (rest, true),
+        None => (
+            content
+                .strip_prefix(SNAPSHOT_MAGIC_V1)
+                .ok_or_else(invalid)?,
+            false,
+        ),
+    };
+    let mut entries = Vec::new();
+    while !rest.is_empty() {
+        let key_len = u32::from_be_bytes(take_array(&mut rest).ok_or_else(invalid)?);
+        let key = take(&mut rest, key_len as usize).ok_or_else(invalid)?;
+        let key = String::from_utf8(key.to_vec()).map_err(|_| invalid())?;
+        let expires_at = u64::from_be_bytes(take_array(&mut rest).ok_or_else(invalid)?);
+        let idle_ttl = match has_idle_ttl {
+            true => u64::from_be_bytes(take_array(&mut rest).ok_or_else(invalid)?),
+            false => 0,
+        };
+        let value_len = u64::from_be_bytes(take_array(&mut rest).ok_or_else(invalid)?);
+        let value_len = usize::try_from(value_len).map_err(|_| invalid())?;
+        let value = take(&mut rest, value_len).ok_or_else(invalid)?.to_vec();
+
+        let expires_at = (expires_at != 0).then(|| UNIX_EPOCH + Duration::from_millis(expires_at));
+        let entry = Entry {
+            value: value.into(),
+            expires_at,
+            idle_ttl: (idle_ttl != 0).then(|| Duration::from_millis(idle_ttl)),
+        };
+        entries.push((key, entry));
+    }
+
+    Ok(entries)
+}
+
+/// Splits the first `len` bytes off `rest`
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if rest.len() < len {
+        return None;
+    }
+    let (head, tail) = rest.split_at(len);
+    *rest = tail;
+    Some(head)
+}
+
+fn take_array<const N: usize>(rest: &mut &[u8]) -> Option<[u8; N]> {
+    take(rest, N)?.try_into().ok()
+}
+
+/// Runs `walk` over the store on the blocking pool: it reads directories through
+/// std::fs, which on a large store would hold up the worker awaiting it
+async fn walk_blocking<T: Send + 'static>(
+    store_path: &str,
+    options: &StoreOptions,
+    walk: fn(&str, &StoreOptions) -> T,
+) -> io::Result<T> {
+    let (store_path, options) = (store_path.to_string(), options.clone());
+    tokio::task::spawn_blocking(move || walk(&store_path, &options))
+        .await
+        .map_err(io::Error::other)
+}
+
+/// Lists the key files under `store_path` along with their keys, skipping files
+/// that lack the store's extension
+fn list_key_files(store_path: &str, options: &StoreOptions) -> Vec<(String, PathBuf)> {
+    let mut dirs = vec![PathBuf::from(store_path)];
+    if let Layout::Sharded { depth, width } = options.layout {
+        for _ in 0..depth {
+            dirs = dirs
+                .iter()
+                .flat_map(|dir| sub_dirs(dir))
+                .filter(|dir| dir.file_name().is_some_and(|n| is_shard_name(n, width)))
+                .collect();
+        }
+    }
+
+    let mut key_files = Vec::new();
+    for dir in dirs {
+        collect_key_files(&dir, String::new(), &mut key_files);
+    }
+
+    key_files
+        .into_iter()
+        .filter_map(|(name, path)| {
+            let name = match &options.file_extension {
+                Some(extension) => name.strip_suffix(extension)?.strip_suffix('.')?,
+                None => &name[..],
+            };
+            Some((decode_key(name)?, path))
+        })
+        .collect()
+}
+
+fn sub_dirs(dir: &Path) -> Vec<PathBuf> {
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(_) => return Vec::new(),
+    };
+
+    // the store never makes symlinks, so one met here may lead out of it
+    read_dir
+        .flatten()
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|e| e.path())
+        .collect()
+}
+
+/// Collects the files under `dir` with their names, those split over chunk
+/// directories put back together
+fn collect_key_files(dir: &Path, name_prefix: String, key_files: &mut Vec<(String, PathBuf)>) {
+    let read_dir = match std::fs::read_dir(dir) {
+        Ok(v) => v,
+        Err(_) => return,
+    };
+
+    for dir_entry in read_dir.flatten() {
+        let file_name = match dir_entry.file_name().into_string() {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        let path = dir_entry.path();
+
+        match dir_entry.file_type() {
+            Ok(t) if t.is_dir() => {
+                if let Some(chunk) = file_name.strip_suffix('%') {
+                    collect_key_files(&path, format!("{}{}", name_prefix, chunk), key_files);
+                }
+            }
+            Ok(t) if t.is_file() => {
+                key_files.push((format!("{}{}", name_prefix, file_name), path));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Builds the path of the file holding `key`.
+///
+/// Files are spread over shard directories named after the key's hash as the
+/// layout says, e.g. `store_path/ab/cd/<name>` by default, so that no single
+/// directory grows huge, or else lie right in `store_path`.
+///
+/// The key is percent-encoded so that separators and dot segments like `..` can
+/// never point outside `store_path`. Encoded names longer than the filesystem
+/// allows are split into nested directories, each ending in a `%` that is never
+/// produced by the encoding, so no directory can clash with a key's file. The
+/// store's file extension, if any, ends the name; the encoding escapes every `.`,
+/// so it cannot be mistaken for part of the key.
+///
+/// Names stay valid on Windows too: uppercase letters are encoded, so keys that
+/// differ only in case never share a file on a case-insensitive filesystem, and
+/// a file name that Windows reserves for a device has its first letter encoded
+pub(crate) fn key_to_path(
+    store_path: impl AsRef<Path>,
+    key: &str,
+    options: &StoreOptions,
+) -> PathBuf {
+    let mut path = store_path.as_ref().to_path_buf();
+    if let Layout::Sharded { depth, width } = options.layout {
+        let hash = format!("{:08x}", shard_hash(key, options.shard_hasher));
+        for level in 0..usize::from(depth) {
+            let start = level * usize::from(width);
+            path.push(&hash[start..start + usize::from(width)]);
+        }
+    }
+
+    let mut name = encode_key(key);
+    if let Some(extension) = &options.file_extension {
+        name.push('.');
+        name.push_str(extension);
+    }
+
+    while name.len() > MAX_FILE_NAME_LEN {
+        let rest = name.split_off(MAX_FILE_NAME_LEN - 1);
+        name.push('%');
+        path.push(&name);
+        name = rest;
+    }
+
+    path.push(escape_reserved_name(name));
+    path
+}
+
+/// Builds the path of the file holding `key` under `root`, the canonical path of
+/// the store, like [`key_to_path`], making sure it leads to somewhere inside the
+/// store. The encoding of the key alone keeps the path there, but a shard directory
+/// or file swapped for a symlink could still lead out of it, so the path is also
+/// checked with symlinks resolved. Fails with [`InvalidKey`] if it leads outside.
+///
+/// The check is made before the file is opened, not as it is, so a symlink swapped
+/// in between the two still leads outside. It guards against a store directory
+/// tampered with while closed, not against someone changing it under a running store
+async fn key_file(root: &Path, key: &str, options: &StoreOptions) -> io::Result<PathBuf> {
+    let path = key_to_path(root, key, options);
+    let stays_inside = path.strip_prefix(root).is_ok_and(|rest| {
+        rest.components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+    });
+    if !stays_inside || !resolves_inside(root, &path).await? {
+        return Err(InvalidKey::error(key));
+    }
+
+    Ok(path)
+}
+
+/// Whether `path`, under `root`, still lies inside it once symlinks are resolved.
+/// Of the parts of `path` yet to be created, none can be a symlink, so the deepest
+/// one that exists decides
+async fn resolves_inside(root: &Path, path: &Path) -> io::Result<bool> {
+    for existing in path.ancestors().take_while(|p| *p != root) {
+        match fs::canonicalize(existing).await {
+            Ok(resolved) => return Ok(resolved.starts_with(root)),
+            Err(e) if e.kind() == NotFound => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(true)
+}
+
+/// Hashes `key` with `hasher`. Unlike the std hashers, each of these is guaranteed
+/// to stay the same across releases, which the on-disk layout relies on
+fn shard_hash(key: &str, hasher: ShardHasher) -> u32 {
+    match hasher {
+        ShardHasher::Fnv1a => fnv1a(key.as_bytes()),
+        ShardHasher::Murmur3 => murmur3(key.as_bytes()),
+    }
+}
+
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, b| {
+        (hash ^ u32::from(*b)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// MurmurHash3 x86_32 with a seed of 0
+fn murmur3(bytes: &[u8]) -> u32 {
+    fn mix(k: u32) -> u32 {
+        k.wrapping_mul(0xcc9e_2d51)
+            .rotate_left(15)
+            .wrapping_mul(0x1b87_3593)
+    }
+
+    let mut hash = 0u32;
+    let mut blocks = bytes.chunks_exact(4);
+    for block in &mut blocks {
+        hash ^= mix(u32::from_le_bytes(block.try_into().unwrap()));
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        let k = tail
+            .iter()
+            .rev()
+            .fold(0u32, |k, b| (k << 8) | u32::from(*b));
+        hash ^= mix(k);
+    }
+
+    hash ^= bytes.len() as u32;
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+/// Percent-encodes every byte of `key` apart from lowercase ASCII letters, digits,
+/// `-` and `_`
+fn encode_key(key: &str) -> String {
+    if key.is_empty() {
+        return EMPTY_KEY_FILE_NAME.to_string();
+    }
+
+    let mut encoded = String::with_capacity(key.len());
+    for b in key.bytes() {
+        if is_unencoded(b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    encoded
+}
+
+/// Reverses `encode_key`, returning `None` if `name` could not have been produced by it
+fn decode_key(name: &str) -> Option<String> {
+    if name == EMPTY_KEY_FILE_NAME {
+        return Some(String::new());
+    }
+
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else if is_unencoded(b) {
+            bytes.push(b);
+            rest = tail;
+        } else {
+            return None;
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+fn is_unencoded(b: u8) -> bool {
+    b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-' || b == b'_'
+}
+
+/// Encodes the first letter of `name` if Windows reserves the part of it before
+/// the extension. Decoding the name still gives back the same key
+fn escape_reserved_name(name: String) -> String {
+    let stem = name.split('.').next().unwrap_or_default();
+    let reserved = RESERVED_FILE_NAMES.contains(&stem)
+        || matches!(stem.as_bytes(), [b'c', b'o', b'm', d] | [b'l', b'p', b't', d] if d.is_ascii_digit());
+    if !reserved {
+        return name;
+    }
+
+    format!("%{:02X}{}", name.as_bytes()[0], &name[1..])
+}
+
+/// Serializes an entry as a binary header followed by the value bytes.
+///
+/// The header is [`HEADER_MAGIC`] and [`HEADER_VERSION`], then a byte of flags
+/// saying which of the parts after it are there: the checksum of the value bytes
+/// as stored (u32), the expiry in milliseconds since the unix epoch (u64) if the
+/// entry expires, and the idle time after which it expires (u64 milliseconds) if
+/// its expiry slides with each read, all numbers big-endian. The flags also mark
+/// values stored gzip compressed
+pub(crate) fn encode_entry(entry: &Entry, compression: Compression) -> io::Result<Vec<u8>> {
+    let value = match compression {
+        Compression::None => std::borrow::Cow::Borrowed(&entry.value[..]),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => std::borrow::Cow::Owned(gzip::compress(&entry.value)?),
+    };
+
+    let mut flags = 0;
+    let mut fields = Vec::with_capacity(20);
+    fields.extend_from_slice(&crc32(&value).to_be_bytes());
+    if let Some(t) = entry.expires_at {
+        flags |= HAS_EXPIRY;
+        let millis = t.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        fields.extend_from_slice(&millis.to_be_bytes());
+    }
+    if let Some(idle_ttl) = entry.idle_ttl {
+        flags |= HAS_IDLE_TTL;
+        fields.extend_from_slice(&(idle_ttl.as_millis() as u64).to_be_bytes());
+    }
+    if compression != Compression::None {
+        flags |= IS_GZIP;
+    }
+
+    let mut content = Vec::with_capacity(3 + fields.len() + value.len());
+    content.extend_from_slice(&[HEADER_MAGIC, HEADER_VERSION, flags]);
+    content.extend_from_slice(&fields);
+    content.extend_from_slice(&value);
+    Ok(content)
+}
+
+/// Reads back an entry serialized by [`encode_entry`], or by an older version of
+/// it: files written before the binary header start with a text header line
+/// instead, and the oldest hold the value alone
+pub(crate) fn decode_entry(key: &str, mut content: Vec<u8>) -> io::Result<Entry> {
+    let (header, header_len) = if content.first() == Some(&HEADER_MAGIC) {
+        read_header(key, &content)?
+    } else {
+        match content.iter().position(|b| *b == b'\n') {
+            Some(pos) => {
+                let header = parse_text_header(&content[..pos]).ok_or_else(|| corrupt(key))?;
+                (header, pos + 1)
+            }
+            None => {
+                return Ok(Entry {
+                    value: content.into(),
+                    expires_at: None,
+                    idle_ttl: None,
+                })
+            }
+        }
+    };
+
+    let mut value = content.split_off(header_len);
+    if matches!(header.crc32, Some(crc) if crc != crc32(&value)) {
+        return Err(corrupt(key));
+    }
+    if header.gzip {
+        value = decompress_gzip(&value)?;
+    }
+
+    Ok(Entry {
+        value: value.into(),
+        expires_at: header.expires_at,
+        idle_ttl: header.idle_ttl,
+    })
+}
+
+fn corrupt(key: &str) -> io::Error {
+    let key = key.to_string();
+    io::Error::new(io::ErrorKind::InvalidData, CorruptEntry { key })
+}
+
+/// Fields of the header of a value file
+struct Header {
+    expires_at: Option<SystemTime>,
+    crc32: Option<u32>,
+    idle_ttl: Option<Duration>,
+    gzip: bool,
+}
+
+/// Parses the binary header that `content` starts with, returning it along with
+/// its length. Fails with [`UnsupportedFormat`] for versions this one cannot read
+fn read_header(key: &str, content: &[u8]) -> io::Result<(Header, usize)> {
+    let version = *content.get(1).ok_or_else(|| corrupt(key))?;
+    let parsed = match version {
+        1 => parse_v1_header(&content[2..]),
+        version => {
+            let key = key.to_string();
+            let unsupported = UnsupportedFormat { key, version };
+            return Err(io::Error::new(io::ErrorKind::InvalidData, unsupported));
+        }
+    };
+
+    let (header, len) = parsed.ok_or_else(|| corrupt(key))?;
+    Ok((header, 2 + len))
+}
+
+/// Parses a version 1 header, past its magic byte and version
+fn parse_v1_header(mut rest: &[u8]) -> Option<(Header, usize)> {
+    let start = rest.len();
+    let [flags] = take_array(&mut rest)?;
+    if flags & !(HAS_EXPIRY | HAS_IDLE_TTL | IS_GZIP) != 0 {
+        return None;
+    }
+
+    let crc32 = u32::from_be_bytes(take_array(&mut rest)?);
+    let mut millis = |flag| match flags & flag {
+        0 => Some(None),
+        _ => take_array(&mut rest).map(|b| Some(Duration::from_millis(u64::from_be_bytes(b)))),
+    };
+    let expires_at = millis(HAS_EXPIRY)?.map(|since_epoch| UNIX_EPOCH + since_epoch);
+    let idle_ttl = millis(HAS_IDLE_TTL)?;
+
+    let header = Header {
+        expires_at,
+        crc32: Some(crc32),
+        idle_ttl,
+        gzip: flags & IS_GZIP != 0,
+    };
+    Some((header, start - rest.len()))
+}
+
+/// Parses the header line of a file written before the binary header, returning
+/// `None` if it is malformed or has fields this version does not know.
+///
+/// The line starts with the expiry (milliseconds since the unix epoch, empty if
+/// the entry never expires), followed by `;`-separated fields: `crc32=<hex>`,
+/// `idle=<milliseconds>` and `gzip`. The oldest files hold the expiry alone
+fn parse_text_header(line: &[u8]) -> Option<Header> {
+    let line = std::str::from_utf8(line).ok()?;
+    let mut fields = line.split(';');
+    let mut header = Header {
+        expires_at: parse_expiry(fields.next()?.as_bytes()),
+        crc32: None,
+        idle_ttl: None,
+        gzip: false,
+    };
+
+    for field in fields {
+        match field.split_once('=') {
+            Some((CRC32_FIELD, v)) => header.crc32 = Some(u32::from_str_radix(v, 16).ok()?),
+            Some((IDLE_FIELD, v)) => header.idle_ttl = Some(Duration::from_millis(v.parse().ok()?)),
+            None if field == GZIP_FIELD => header.gzip = true,
+            _ => return None,
+        }
+    }
+
+    Some(header)
+}
+
+/// CRC-32 (IEEE) of `bytes`
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, b| {
+        CRC32_TABLE[((crc ^ u32::from(*b)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+
+    table
+}
+
+#[cfg(feature = "gzip")]
+fn decompress_gzip(value: &[u8]) -> io::Result<Vec<u8>> {
+    gzip::decompress(value)
+}
+
+#[cfg(not(feature = "gzip"))]
+fn decompress_gzip(_value: &[u8]) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "value is gzip compressed but the gzip feature is disabled",
+    ))
+}
+
+#[cfg(feature = "gzip")]
+mod gzip {
+    use flate2::read::GzDecoder;
+    use flate2::write::GzEncoder;
+    use std::io::{self, Read, Write};
+
+    pub(super) fn compress(value: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(value)?;
+        encoder.finish()
+    }
+
+    pub(super) fn decompress(value: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        GzDecoder::new(value).read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+}
+
+fn parse_expiry(header: &[u8]) -> Option<SystemTime> {
+    std::str::from_utf8(header)
+        .ok()
+        .and_then(|header| header.parse::<u64>().ok())
+        .map(|millis| UNIX_EPOCH + Duration::from_millis(millis))
 }
@@ -0,0 +1,170 @@
+/// A glob pattern that keys are matched against as a whole.
+///
+/// `*` matches any run of characters, `:` and `/` included, and `?` any single
+/// character. `[...]` matches one character of the set it lists, such as `[abc]` or
+/// the range `[0-9]`, and `[!...]` or `[^...]` one character outside it. A `]`
+/// right after the opening bracket is part of the set, as is a `-` first or last.
+/// `\` makes the character after it literal, and a `[` never closed is literal too
+pub(crate) struct Pattern(Vec<Token>);
+
+enum Token {
+    Char(char),
+    AnyChar,
+    AnyRun,
+    Set {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+}
+
+impl Token {
+    /// Whether this token, being neither `*` nor its like, matches `c`
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(expected) => *expected == c,
+            Token::AnyChar => true,
+            Token::AnyRun => false,
+            Token::Set { ranges, negated } => {
+                ranges.iter().any(|(lo, hi)| (*lo..=*hi).contains(&c)) != *negated
+            }
+        }
+    }
+}
+
+impl Pattern {
+    pub(crate) fn new(pattern: &str) -> Pattern {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            let token = match chars[i] {
+                '*' => Token::AnyRun,
+                '?' => Token::AnyChar,
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    Token::Char(chars[i])
+                }
+                '[' => match parse_set(&chars[i + 1..]) {
+                    Some((set, len)) => {
+                        i += len;
+                        set
+                    }
+                    None => Token::Char('['),
+                },
+                c => Token::Char(c),
+            };
+            tokens.push(token);
+            i += 1;
+        }
+
+        Pattern(tokens)
+    }
+
+    pub(crate) fn matches(&self, key: &str) -> bool {
+        let tokens = &self.0;
+        let key: Vec<char> = key.chars().collect();
+        let (mut t, mut k) = (0, 0);
+        // the last `*` seen, and where in the key it was last tried to end
+        let mut any_run: Option<(usize, usize)> = None;
+
+        while k < key.len() {
+            match tokens.get(t) {
+                Some(Token::AnyRun) => {
+                    any_run = Some((t, k));
+                    t += 1;
+                }
+                Some(token) if token.matches(key[k]) => {
+                    t += 1;
+                    k += 1;
+                }
+                // lets the last `*` take one more character, and tries again from there
+                _ => match any_run {
+                    Some((star, end)) => {
+                        any_run = Some((star, end + 1));
+                        t = star + 1;
+                        k = end + 1;
+                    }
+                    None => return false,
+                },
+            }
+        }
+
+        tokens[t..]
+            .iter()
+            .all(|token| matches!(token, Token::AnyRun))
+    }
+}
+
+/// Parses the set that `chars` starts, just past its `[`, returning it along with
+/// how many characters it took, its `]` included. `None` if it is never closed
+fn parse_set(chars: &[char]) -> Option<(Token, usize)> {
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    let mut i = usize::from(negated);
+    let mut ranges = Vec::new();
+    let start = i;
+
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && i > start {
+            return Some((Token::Set { ranges, negated }, i + 1));
+        }
+
+        match (chars.get(i + 1), chars.get(i + 2)) {
+            (Some('-'), Some(&hi)) if hi != ']' => {
+                ranges.push((c, hi));
+                i += 3;
+            }
+            _ => {
+                ranges.push((c, c));
+                i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, key: &str) -> bool {
+        Pattern::new(pattern).matches(key)
+    }
+
+    #[test]
+    fn wildcards_match_runs_and_single_characters() {
+        assert!(matches("user:*:session", "user:42:session"));
+        assert!(matches("user:*:session", "user::session"));
+        assert!(matches("user:*:session", "user:a:b:session"));
+        assert!(!matches("user:*:session", "user:42:sessions"));
+        assert!(matches("*:*:*", "a:b:c"));
+        assert!(!matches("*:*:*", "a:b"));
+        assert!(matches("*", ""));
+        assert!(matches("h?y", "hey"));
+        assert!(!matches("h?y", "hy"));
+        assert!(matches("é?", "éa"));
+        assert!(matches("hey", "hey"));
+        assert!(!matches("hey", "hey!"));
+    }
+
+    #[test]
+    fn sets_match_one_character_of_them_or_outside_them() {
+        assert!(matches("key[0-9]", "key7"));
+        assert!(!matches("key[0-9]", "keyx"));
+        assert!(!matches("key[0-9]", "key10"));
+        assert!(matches("key[0-9]*", "key10"));
+        assert!(matches("[!a-c]x", "dx"));
+        assert!(!matches("[^a-c]x", "bx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[ab", "[ab"));
+    }
+
+    #[test]
+    fn escaped_characters_are_literal() {
+        assert!(matches(r"what\?", "what?"));
+        assert!(!matches(r"what\?", "whats"));
+        assert!(matches(r"\*", "*"));
+        assert!(!matches(r"\*", "a"));
+        assert!(matches(r"end\", r"end\"));
+    }
+}
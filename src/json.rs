@@ -0,0 +1,117 @@
+use crate::client::ClientHandle;
+use crate::error::CacheError;
+use std::collections::BTreeMap;
+
+impl ClientHandle {
+    /// Renders every entry as a JSON object mapping keys to their values.
+    ///
+    /// Values must be UTF-8, since they become JSON strings. Expiry is not exported
+    pub async fn export_json(&self) -> Result<String, CacheError> {
+        let mut object = BTreeMap::new();
+        for (key, value) in self.scan_prefix("").await? {
+            let value = match std::str::from_utf8(&value) {
+                Ok(v) => v.to_string(),
+                Err(e) => {
+                    return Err(CacheError::Deserialization {
+                        key,
+                        source: Box::new(e),
+                    })
+                }
+            };
+            object.insert(key, value);
+        }
+
+        serde_json::to_string(&object).map_err(|e| CacheError::Serialization {
+            key: None,
+            source: Box::new(e),
+        })
+    }
+
+    /// Sets every key of a JSON object whose values are all strings, as written by
+    /// [`export_json`](Self::export_json), returning how many keys were set.
+    ///
+    /// A key given more than once takes its last value
+    pub async fn import_json(&self, json: &str) -> Result<usize, CacheError> {
+        let object: BTreeMap<String, String> =
+            serde_json::from_str(json).map_err(|e| CacheError::Serialization {
+                key: None,
+                source: Box::new(e),
+            })?;
+        let entries: Vec<(String, Vec<u8>)> = object
+            .into_iter()
+            .map(|(k, v)| (k, v.into_bytes()))
+            .collect();
+
+        let imported = entries.len();
+        for prev in self.set_many(entries).await? {
+            prev?;
+        }
+
+        Ok(imported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Client;
+    use serial_test::serial;
+
+    const STORE_PATH: &str = "client_db";
+    const IMPORT_STORE_PATH: &str = "client_db_import";
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn export_then_import_into_another_store() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        let pairs = [
+            ("hey", "English"),
+            ("user:1", "{\"a\": \"b\"}"),
+            ("", "ünïcödé\n"),
+        ];
+        for (k, v) in pairs {
+            let _ = client.set_str(k.to_string(), v.to_string()).await;
+        }
+
+        let json = client.export_json().await.unwrap();
+        client.clear().await.unwrap();
+        client.close().await;
+
+        let mut other = Client::new(IMPORT_STORE_PATH, 2).unwrap();
+        other.clear().await.unwrap();
+        assert_eq!(other.import_json(&json).await.unwrap(), pairs.len());
+        for (k, v) in pairs {
+            assert_eq!(other.get_str(k).await.unwrap(), Some(v.to_string()));
+        }
+        assert_eq!(other.export_json().await.unwrap(), json);
+
+        other.clear().await.unwrap();
+        other.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn import_keeps_last_of_duplicate_keys() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+
+        let imported = client
+            .import_json(r#"{"hey": "first", "hi": "English", "hey": "last"}"#)
+            .await;
+        assert_eq!(imported.unwrap(), 2);
+        assert_eq!(
+            client.get_str("hey").await.unwrap(),
+            Some("last".to_string())
+        );
+
+        let not_strings = client.import_json(r#"{"hey": 1}"#).await;
+        assert!(matches!(
+            not_strings,
+            Err(CacheError::Serialization { key: None, .. })
+        ));
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+}
@@ -1,5 +1,30 @@
+mod backend;
+mod blocking;
+mod builder;
 pub mod client;
+mod error;
+mod eviction;
 mod fs;
+mod glob;
+#[cfg(feature = "serde")]
+mod json;
+mod log;
+mod lru;
+mod metrics;
+mod namespace;
 mod store;
+#[cfg(feature = "serde")]
+mod typed;
 
-pub use client::Client;
+pub use blocking::BlockingClient;
+pub use builder::ClientBuilder;
+pub use client::{Client, ClientHandle};
+pub use error::CacheError;
+pub use namespace::Namespace;
+pub use store::{
+    CacheEvent, CacheStats, CompactReport, Compression, Durability, Eviction, EvictionListener,
+    Format, KeyMetadata, Layout, Loader, LoaderFuture, Retry, SetOutcome, ShardHasher, Storage,
+    StoreOptions,
+};
+#[cfg(feature = "serde")]
+pub use typed::TypedClient;
@@ -0,0 +1,592 @@
+use crate::backend::StorageBackend;
+use crate::store::{CompactReport, Compression, Durability, Entry, StoreOptions};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio::sync::RwLock;
+use tokio::{fs, io};
+
+/// Name of the log in the store directory
+const LOG_FILE_NAME: &str = "entries.log";
+
+/// Least garbage, in bytes, worth compacting the log for
+const MIN_COMPACTION_GARBAGE: u64 = 64 * 1024;
+
+/// Tag of a record setting the value of its key
+const PUT: u8 = 0;
+/// Tag of a record deleting its key
+const DELETE: u8 = 1;
+
+/// Bytes of a record before its key: the tag and the key's length
+const RECORD_PREFIX_LEN: u64 = 1 + 4;
+/// Bytes of a record between its key and its value: the value's length
+const VALUE_LEN_LEN: u64 = 8;
+
+/// Appends every set and delete to a single log file, and finds the latest value
+/// of each key through an index of their offsets held in memory.
+///
+/// Each record is a tag, the key's length (u32) and bytes, then the value's length
+/// (u64) and bytes, all numbers big-endian. Values are laid out as in the files of
+/// the file-per-key layout. Overwritten and deleted values stay in the log until
+/// they make up more than half of it, when the log is rewritten without them
+pub(crate) struct LogBackend {
+    path: PathBuf,
+    compression: Compression,
+    durability: Durability,
+    file_mode: Option<u32>,
+    state: RwLock<LogState>,
+}
+
+struct LogState {
+    /// Handle that records are appended through
+    file: fs::File,
+    /// Where the value of the latest record of each key lies in the log
+    index: HashMap<String, Span>,
+    /// Length of the log
+    len: u64,
+    /// Bytes of the log taken by records that are no longer the latest of their key
+    garbage: u64,
+}
+
+#[derive(Clone, Copy)]
+struct Span {
+    offset: u64,
+    len: u64,
+}
+
+struct Record {
+    tag: u8,
+    key: String,
+    value: Span,
+    /// The value bytes, if they were asked for
+    content: Option<Vec<u8>>,
+}
+
+impl LogBackend {
+    /// Opens the log in `store_path`, creating it if needed, and indexes its records.
+    /// A record cut short by a crash in the middle of an append is dropped. A
+    /// read-only store only reads the log, which must then exist, and leaves any
+    /// such record be
+    pub(crate) fn open(store_path: &str, options: &StoreOptions) -> io::Result<LogBackend> {
+        let path = Path::new(store_path).join(LOG_FILE_NAME);
+        let file = match options.read_only {
+            true => std::fs::File::open(&path)?,
+            false => crate::fs::file_options(options.file_mode)
+                .append(true)
+                .open(&path)?,
+        };
+
+        let mut index = HashMap::new();
+        let mut garbage = 0;
+        let len = scan(&path, false, |record| {
+            let replaced = match record.tag {
+                PUT => index.insert(record.key.clone(), record.value),
+                _ => {
+                    garbage += record_len(&record.key, record.value.len);
+                    index.remove(&record.key)
+                }
+            };
+            if let Some(old) = replaced {
+                garbage += record_len(&record.key, old.len);
+            }
+        })?;
+        if !options.read_only && file.metadata()?.len() > len {
+            file.set_len(len)?;
+        }
+
+        Ok(LogBackend {
+            path,
+            compression: options.compression,
+            durability: options.durability,
+            file_mode: options.file_mode,
+            state: RwLock::new(LogState {
+                file: fs::File::from_std(file),
+                index,
+                len,
+                garbage,
+            }),
+        })
+    }
+
+    /// Appends a record, returning where its value lies. A failed append is cut
+    /// off again so that the next one does not land after a partial record
+    async fn append(
+        &self,
+        state: &mut LogState,
+        tag: u8,
+        key: &str,
+        content: &[u8],
+    ) -> io::Result<Span> {
+        let record = encode_record(tag, key, content);
+        let written = match state.file.write_all(&record).await {
+            Ok(()) => state.file.flush().await,
+            Err(e) => Err(e),
+        };
+        let synced = match written {
+            Ok(()) if self.durability != Durability::None => state.file.sync_data().await,
+            other => other,
+        };
+        if let Err(e) = synced {
+            let _ = state.file.set_len(state.len).await;
+            return Err(e);
+        }
+
+        let span = Span {
+            offset: state.len + record.len() as u64 - content.len() as u64,
+            len: content.len() as u64,
+        };
+        state.len += record.len() as u64;
+        Ok(span)
+    }
+
+    /// Compacts the log once the records it holds for nothing outweigh the rest.
+    /// The write that called it has already succeeded, so failures are only logged
+    async fn compact_if_wasteful(&self, state: &mut LogState) {
+        if state.garbage < MIN_COMPACTION_GARBAGE || state.garbage * 2 < state.len {
+            return;
+        }
+
+        let tmp_path = self.path.with_extension("log.compacting");
+        if let Err(_e) = self.rewrite(state, &tmp_path).await {
+            let _ = fs::remove_file(&tmp_path).await;
+            #[cfg(feature = "tracing")]
+            tracing::warn!(error = %_e, "compacting the log failed");
+        }
+    }
+
+    /// Writes the latest record of each key to a new log at `tmp_path`, then
+    /// renames it over the old one
+    async fn rewrite(&self, state: &mut LogState, tmp_path: &Path) -> io::Result<()> {
+        let mut old = fs::File::open(&self.path).await?;
+        let mut options = crate::fs::file_options(self.file_mode);
+        options.write(true).truncate(true);
+        let new = fs::OpenOptions::from(options).open(tmp_path).await?;
+        let mut new = io::BufWriter::new(new);
+        let mut index = HashMap::with_capacity(state.index.len());
+        let mut len = 0;
+
+        for (key, span) in &state.index {
+            let content = read_span(&mut old, *span).await?;
+            let record = encode_record(PUT, key, &content);
+            new.write_all(&record).await?;
+            len += record.len() as u64;
+            let value = Span {
+                offset: len - span.len,
+                len: span.len,
+            };
+            index.insert(key.clone(), value);
+        }
+
+        new.flush().await?;
+        if self.durability != Durability::None {
+            new.get_ref().sync_all().await?;
+        }
+        fs::rename(tmp_path, &self.path).await?;
+
+        state.file = fs::OpenOptions::new().append(true).open(&self.path).await?;
+        state.index = index;
+        state.len = len;
+        state.garbage = 0;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageBackend for LogBackend {
+    async fn save(&self, key: &str, entry: &Entry) -> io::Result<()> {
+        let content = crate::fs::encode_entry(entry, self.compression)?;
+        let mut state = self.state.write().await;
+        let span = self.append(&mut state, PUT, key, &content).await?;
+        if let Some(old) = state.index.insert(key.to_string(), span) {
+            state.garbage += record_len(key, old.len);
+        }
+
+        self.compact_if_wasteful(&mut state).await;
+        Ok(())
+    }
+
+    async fn load(&self, key: &str) -> io::Result<Option<Entry>> {
+        // held while reading, so that a compaction cannot move the value meanwhile
+        let state = self.state.read().await;
+        let span = match state.index.get(key) {
+            Some(span) => *span,
+            None => return Ok(None),
+        };
+
+        let mut file = fs::File::open(&self.path).await?;
+        let content = read_span(&mut file, span).await?;
+        drop(state);
+
+        crate::fs::decode_entry(key, content).map(Some)
+    }
+
+    async fn remove(&self, key: &str) -> io::Result<()> {
+        let mut state = self.state.write().await;
+        let old = match state.index.get(key) {
+            Some(span) => *span,
+            None => return Err(io::Error::new(ErrorKind::NotFound, "no such key")),
+        };
+
+        let tombstone = self.append(&mut state, DELETE, key, &[]).await?;
+        state.index.remove(key);
+        state.garbage += record_len(key, old.len) + record_len(key, tombstone.len);
+
+        self.compact_if_wasteful(&mut state).await;
+        Ok(())
+    }
+
+    async fn clear(&self) -> io::Result<()> {
+        let mut state = self.state.write().await;
+        state.file.set_len(0).await?;
+        if self.durability != Durability::None {
+            state.file.sync_data().await?;
+        }
+
+        state.index.clear();
+        state.len = 0;
+        state.garbage = 0;
+        Ok(())
+    }
+
+    async fn keys(&self) -> Vec<String> {
+        self.state.read().await.index.keys().cloned().collect()
+    }
+
+    fn load_all(&self) -> Vec<(String, Entry)> {
+        let mut latest = HashMap::new();
+        let scanned = scan(&self.path, true, |record| match record.tag {
+            PUT => {
+                latest.insert(record.key, record.content.unwrap_or_default());
+            }
+            _ => {
+                latest.remove(&record.key);
+            }
+        });
+        if scanned.is_err() {
+            return Vec::new();
+        }
+
+        latest
+            .into_iter()
+            .filter_map(|(key, content)| {
+                let entry = crate::fs::decode_entry(&key, content).ok()?;
+                Some((key, entry))
+            })
+            .collect()
+    }
+
+    async fn sync(&self) -> io::Result<()> {
+        self.state.read().await.file.sync_all().await
+    }
+
+    /// Rewrites the log without the records it holds for nothing, however few,
+    /// along with any new log that a crash in the middle of a rewrite left behind
+    async fn compact(&self) -> io::Result<CompactReport> {
+        let mut state = self.state.write().await;
+        let mut report = CompactReport::default();
+        let tmp_path = self.path.with_extension("log.compacting");
+        if let Ok(metadata) = fs::metadata(&tmp_path).await {
+            fs::remove_file(&tmp_path).await?;
+            report.tmp_files_removed += 1;
+            report.bytes_reclaimed += metadata.len();
+        }
+        if state.garbage == 0 {
+            return Ok(report);
+        }
+
+        let len = state.len;
+        if let Err(e) = self.rewrite(&mut state, &tmp_path).await {
+            let _ = fs::remove_file(&tmp_path).await;
+            return Err(e);
+        }
+        report.bytes_reclaimed += len - state.len;
+        Ok(report)
+    }
+
+    /// Starts an empty log if the old one was removed. Appends would otherwise go
+    /// on landing in the removed file, where nothing can read them back
+    async fn repair(&self) -> io::Result<()> {
+        let mut state = self.state.write().await;
+        if self.path.exists() {
+            return Ok(());
+        }
+
+        let mut options = crate::fs::file_options(self.file_mode);
+        options.append(true);
+        state.file = fs::OpenOptions::from(options).open(&self.path).await?;
+        state.index.clear();
+        state.len = 0;
+        state.garbage = 0;
+        Ok(())
+    }
+}
+
+fn encode_record(tag: u8, key: &str, content: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(record_len(key, content.len() as u64) as usize);
+    record.push(tag);
+    record.extend_from_slice(&(key.len() as u32).to_be_bytes());
+    record.extend_from_slice(key.as_bytes());
+    record.extend_from_slice(&(content.len() as u64).to_be_bytes());
+    record.extend_from_slice(content);
+    record
+}
+
+fn record_len(key: &str, value_len: u64) -> u64 {
+    RECORD_PREFIX_LEN + key.len() as u64 + VALUE_LEN_LEN + value_len
+}
+
+async fn read_span(file: &mut fs::File, span: Span) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(span.offset)).await?;
+    let mut content = vec![0; span.len as usize];
+    file.read_exact(&mut content).await?;
+    Ok(content)
+}
+
+/// Reads the records of the log at `path` in order, with their values if
+/// `with_values` is set. Stops at the first record that is cut short or malformed,
+/// returning the length of the log up to it
+fn scan(path: &Path, with_values: bool, mut visit: impl FnMut(Record)) -> io::Result<u64> {
+    let file = std::fs::File::open(path)?;
+    let file_len = file.metadata()?.len();
+    let mut reader = BufReader::new(file);
+    let mut len = 0;
+
+    loop {
+        let mut prefix = [0; RECORD_PREFIX_LEN as usize];
+        if !read_whole(&mut reader, &mut prefix)? {
+            break;
+        }
+        let [tag, key_len @ ..] = prefix;
+        if tag != PUT && tag != DELETE {
+            break;
+        }
+
+        // a corrupt length could ask for gigabytes, so it is checked before allocating
+        let key_len = u64::from(u32::from_be_bytes(key_len));
+        if len + RECORD_PREFIX_LEN + key_len + VALUE_LEN_LEN > file_len {
+            break;
+        }
+        let mut key = vec![0; key_len as usize];
+        let mut value_len = [0; VALUE_LEN_LEN as usize];
+        if !read_whole(&mut reader, &mut key)? || !read_whole(&mut reader, &mut value_len)? {
+            break;
+        }
+        let key = match String::from_utf8(key) {
+            Ok(key) => key,
+            Err(_) => break,
+        };
+
+        let value = Span {
+            offset: len + RECORD_PREFIX_LEN + key.len() as u64 + VALUE_LEN_LEN,
+            len: u64::from_be_bytes(value_len),
+        };
+        // a corrupt length could also run past the end of the file, or of a u64
+        let end = match value.offset.checked_add(value.len) {
+            Some(end) if end <= file_len => end,
+            _ => break,
+        };
+        let content = if with_values {
+            let mut content = vec![0; value.len as usize];
+            reader.read_exact(&mut content)?;
+            Some(content)
+        } else {
+            reader.seek_relative(value.len as i64)?;
+            None
+        };
+
+        len = end;
+        visit(Record {
+            tag,
+            key,
+            value,
+            content,
+        });
+    }
+
+    Ok(len)
+}
+
+/// Fills `buf`, returning `false` if the reader ran out first
+fn read_whole(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<bool> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    const STORE_PATH: &str = "log_db";
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn overwritten_values_are_compacted_away() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        std::fs::create_dir_all(STORE_PATH).unwrap();
+        let log = LogBackend::open(STORE_PATH, &StoreOptions::default()).unwrap();
+
+        let mut peak = 0;
+        for i in 0..500 {
+            let entry = Entry {
+                value: format!("{i:01024}").into_bytes().into(),
+                expires_at: None,
+                idle_ttl: None,
+            };
+            log.save("hey", &entry).await.unwrap();
+            peak = peak.max(log_len());
+        }
+
+        // 500 values of 1KiB were appended, but no more than the garbage allowed stays
+        assert!(peak <= 2 * MIN_COMPACTION_GARBAGE + 2048);
+        assert!(log_len() < peak);
+        let entry = log.load("hey").await.unwrap().unwrap();
+        assert_eq!(&entry.value[..], format!("{:01024}", 499).as_bytes());
+
+        log.remove("hey").await.unwrap();
+        log.save(
+            "hi",
+            &Entry {
+                value: b"English".to_vec().into(),
+                expires_at: None,
+                idle_ttl: None,
+            },
+        )
+        .await
+        .unwrap();
+        drop(log);
+
+        let log = LogBackend::open(STORE_PATH, &StoreOptions::default()).unwrap();
+        assert!(log.load("hey").await.unwrap().is_none());
+        assert_eq!(log.keys().await, vec!["hi".to_string()]);
+        let entries = log.load_all();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(&entries[0].1.value[..], b"English");
+
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn record_cut_short_by_a_crash_is_dropped() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        std::fs::create_dir_all(STORE_PATH).unwrap();
+        let log = LogBackend::open(STORE_PATH, &StoreOptions::default()).unwrap();
+        for (key, value) in [("hey", "English"), ("hi", "English")] {
+            let entry = Entry {
+                value: value.as_bytes().to_vec().into(),
+                expires_at: None,
+                idle_ttl: None,
+            };
+            log.save(key, &entry).await.unwrap();
+        }
+        drop(log);
+
+        let full_len = log_len();
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(Path::new(STORE_PATH).join(LOG_FILE_NAME))
+            .unwrap();
+        file.set_len(full_len - 3).unwrap();
+
+        let log = LogBackend::open(STORE_PATH, &StoreOptions::default()).unwrap();
+        assert_eq!(log.keys().await, vec!["hey".to_string()]);
+        assert!(log_len() < full_len - 3);
+
+        // appends go on from the last whole record
+        let entry = Entry {
+            value: b"Slang".to_vec().into(),
+            expires_at: None,
+            idle_ttl: None,
+        };
+        log.save("yoo-hoo", &entry).await.unwrap();
+        drop(log);
+        let log = LogBackend::open(STORE_PATH, &StoreOptions::default()).unwrap();
+        let mut keys = log.keys().await;
+        keys.sort();
+        assert_eq!(keys, vec!["hey".to_string(), "yoo-hoo".to_string()]);
+
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn record_with_a_corrupt_key_length_is_dropped() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        std::fs::create_dir_all(STORE_PATH).unwrap();
+        let log = LogBackend::open(STORE_PATH, &StoreOptions::default()).unwrap();
+        let mut first_len = 0;
+        for (key, value) in [("hey", "English"), ("hi", "English")] {
+            let entry = Entry {
+                value: value.as_bytes().to_vec().into(),
+                expires_at: None,
+                idle_ttl: None,
+            };
+            log.save(key, &entry).await.unwrap();
+            if first_len == 0 {
+                first_len = log_len();
+            }
+        }
+        drop(log);
+
+        // the key length of the second record, claiming 4GiB
+        let path = Path::new(STORE_PATH).join(LOG_FILE_NAME);
+        let mut content = std::fs::read(&path).unwrap();
+        let start = first_len as usize + 1;
+        content[start..start + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        std::fs::write(&path, content).unwrap();
+
+        let log = LogBackend::open(STORE_PATH, &StoreOptions::default()).unwrap();
+        assert_eq!(log.keys().await, vec!["hey".to_string()]);
+        assert_eq!(log_len(), first_len);
+
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn record_with_a_corrupt_value_length_is_dropped() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        std::fs::create_dir_all(STORE_PATH).unwrap();
+        let log = LogBackend::open(STORE_PATH, &StoreOptions::default()).unwrap();
+        let mut first_len = 0;
+        for (key, value) in [("hi", "English"), ("hey", "English")] {
+            let entry = Entry {
+                value: value.as_bytes().to_vec().into(),
+                expires_at: None,
+                idle_ttl: None,
+            };
+            log.save(key, &entry).await.unwrap();
+            if first_len == 0 {
+                first_len = log_len();
+            }
+        }
+        drop(log);
+
+        // the value length of "hey", just past its 5 byte prefix and 3 byte key,
+        // claiming more bytes than a u64 can add up to
+        let path = Path::new(STORE_PATH).join(LOG_FILE_NAME);
+        let mut content = std::fs::read(&path).unwrap();
+        let start = first_len as usize;
+        content[start + 8..start + 16].copy_from_slice(&u64::MAX.to_be_bytes());
+        std::fs::write(&path, content).unwrap();
+
+        let log = LogBackend::open(STORE_PATH, &StoreOptions::default()).unwrap();
+        assert_eq!(log.keys().await, vec!["hi".to_string()]);
+        assert!(log.load("hey").await.unwrap().is_none());
+        assert_eq!(log_len(), first_len);
+
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+    }
+
+    fn log_len() -> u64 {
+        std::fs::metadata(Path::new(STORE_PATH).join(LOG_FILE_NAME))
+            .unwrap()
+            .len()
+    }
+}
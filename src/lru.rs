@@ -0,0 +1,52 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Tracks the order in which keys were last used, so that the least recently
+/// used one can be found without scanning every key
+#[derive(Default)]
+pub(crate) struct LruOrder {
+    /// Tick of the last use of each key
+    ticks: HashMap<String, u64>,
+    /// Keys by the tick of their last use, oldest first
+    keys: BTreeMap<u64, String>,
+    next_tick: u64,
+}
+
+impl LruOrder {
+    /// Marks `key` as the most recently used
+    pub(crate) fn touch(&mut self, key: &str) {
+        let tick = self.next_tick;
+        self.next_tick += 1;
+
+        match self.ticks.insert(key.to_string(), tick) {
+            Some(prev) => {
+                let key = self.keys.remove(&prev).unwrap_or_else(|| key.to_string());
+                self.keys.insert(tick, key);
+            }
+            None => {
+                self.keys.insert(tick, key.to_string());
+            }
+        }
+    }
+
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.ticks.contains_key(key)
+    }
+
+    pub(crate) fn remove(&mut self, key: &str) {
+        if let Some(tick) = self.ticks.remove(key) {
+            self.keys.remove(&tick);
+        }
+    }
+
+    /// Forgets and returns the least recently used key
+    pub(crate) fn pop_oldest(&mut self) -> Option<String> {
+        let (_, key) = self.keys.pop_first()?;
+        self.ticks.remove(&key);
+        Some(key)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.ticks.clear();
+        self.keys.clear();
+    }
+}
@@ -0,0 +1,173 @@
+use crate::client::ClientHandle;
+use crate::error::CacheError;
+use crate::store::CacheStats;
+use std::fmt::Write;
+
+impl CacheStats {
+    /// Renders the stats in the Prometheus text exposition format, the lookup
+    /// counters as counters, and what memory holds and the latencies as gauges
+    pub fn to_prometheus(&self) -> String {
+        let metrics = [
+            (
+                "diskcache_hits_total",
+                "counter",
+                "Lookups served from memory.",
+                self.hits as f64,
+            ),
+            (
+                "diskcache_misses_total",
+                "counter",
+                "Lookups of keys that were missing or had expired.",
+                self.misses as f64,
+            ),
+            (
+                "diskcache_disk_fallbacks_total",
+                "counter",
+                "Lookups that had to read the value from disk.",
+                self.disk_fallbacks as f64,
+            ),
+            (
+                "diskcache_evictions_total",
+                "counter",
+                "Entries dropped from memory to stay within the limits.",
+                self.evictions as f64,
+            ),
+            (
+                "diskcache_deletes_total",
+                "counter",
+                "Keys deleted, on their own or as part of a prefix.",
+                self.deletes as f64,
+            ),
+            (
+                "diskcache_operations_total",
+                "counter",
+                "Operations served by the workers.",
+                self.operations as f64,
+            ),
+            (
+                "diskcache_entries",
+                "gauge",
+                "Entries held in memory.",
+                self.entry_count as f64,
+            ),
+            (
+                "diskcache_cached_bytes",
+                "gauge",
+                "Total size of the values held in memory.",
+                self.cached_bytes as f64,
+            ),
+            (
+                "diskcache_operation_latency_min_seconds",
+                "gauge",
+                "Shortest time a worker took over an operation.",
+                self.min_latency.as_secs_f64(),
+            ),
+            (
+                "diskcache_operation_latency_max_seconds",
+                "gauge",
+                "Longest time a worker took over an operation.",
+                self.max_latency.as_secs_f64(),
+            ),
+            (
+                "diskcache_operation_latency_avg_seconds",
+                "gauge",
+                "Average time a worker took over an operation.",
+                self.avg_latency.as_secs_f64(),
+            ),
+        ];
+
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            let _ = writeln!(text, "{} {}", name, value);
+        }
+
+        text
+    }
+}
+
+impl ClientHandle {
+    /// Renders the current [`stats`](Self::stats) for a Prometheus `/metrics`
+    /// endpoint; see [`CacheStats::to_prometheus`]
+    pub async fn metrics_prometheus(&self) -> Result<String, CacheError> {
+        Ok(self.stats().await?.to_prometheus())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Client;
+    use serial_test::serial;
+    use std::collections::HashMap;
+
+    const STORE_PATH: &str = "client_db";
+
+    /// Checks `text` against the exposition format, returning each sample's value
+    /// and each metric's type
+    fn parse(text: &str) -> (HashMap<String, f64>, HashMap<String, String>) {
+        let is_name = |name: &str| {
+            let mut chars = name.chars();
+            matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+        };
+
+        let mut samples = HashMap::new();
+        let mut types = HashMap::new();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.splitn(4, ' ').collect();
+            match fields[..] {
+                ["#", "HELP", name, _] => assert!(is_name(name), "{}", line),
+                ["#", "TYPE", name, kind] => {
+                    assert!(["counter", "gauge"].contains(&kind), "{}", line);
+                    types.insert(name.to_string(), kind.to_string());
+                }
+                [name, value] => {
+                    assert!(is_name(name), "{}", line);
+                    // every sample comes after the type of its metric
+                    assert!(types.contains_key(name), "{}", line);
+                    samples.insert(name.to_string(), value.parse().unwrap());
+                }
+                _ => panic!("not a valid line: {:?}", line),
+            }
+        }
+
+        (samples, types)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn metrics_render_as_prometheus_text() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        client
+            .set_str("hey".to_string(), "English".to_string())
+            .await
+            .unwrap();
+        client.get("hey").await.unwrap();
+        client.get("missing").await.unwrap();
+        let stats = client.stats().await.unwrap();
+
+        let text = client.metrics_prometheus().await.unwrap();
+        let (samples, types) = parse(&text);
+
+        assert_eq!(samples["diskcache_hits_total"], stats.hits as f64);
+        assert_eq!(samples["diskcache_misses_total"], stats.misses as f64);
+        assert_eq!(samples["diskcache_entries"], 1.0);
+        assert_eq!(samples["diskcache_cached_bytes"], 7.0);
+        assert!(samples["diskcache_operation_latency_max_seconds"] > 0.0);
+        assert_eq!(types.len(), 11);
+        for (name, kind) in types {
+            let expected = if name.ends_with("_total") {
+                "counter"
+            } else {
+                "gauge"
+            };
+            assert_eq!(kind, expected);
+            assert!(samples.contains_key(&name));
+        }
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+}
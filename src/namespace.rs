@@ -0,0 +1,127 @@
+use crate::client::ClientHandle;
+use crate::error::CacheError;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Ends the name of a namespace in the keys of its entries
+const SEPARATOR: char = ':';
+
+/// A view of a [`ClientHandle`] whose keys live apart from those of other namespaces.
+///
+/// Got from [`ClientHandle::namespace`]. Every key is stored with the namespace's name
+/// and a `:` in front of it, so the namespaces share the store and its workers
+/// while two of them can hold the same key independently
+#[derive(Clone)]
+pub struct Namespace {
+    handle: ClientHandle,
+    prefix: String,
+}
+
+impl Namespace {
+    /// Panics if `name` contains a `:`, which would let it overlap other namespaces
+    pub(crate) fn new(handle: ClientHandle, name: &str) -> Namespace {
+        assert!(
+            !name.contains(SEPARATOR),
+            "namespace name {:?} contains a '{}'",
+            name,
+            SEPARATOR
+        );
+
+        Namespace {
+            handle,
+            prefix: format!("{}{}", name, SEPARATOR),
+        }
+    }
+
+    pub async fn set(
+        &self,
+        key: impl AsRef<str>,
+        value: impl Into<Vec<u8>>,
+    ) -> Result<Option<Arc<[u8]>>, CacheError> {
+        self.handle.set(self.key(key.as_ref()), value).await
+    }
+
+    /// Sets a value that is treated as missing once `ttl` has elapsed
+    pub async fn set_with_ttl(
+        &self,
+        key: impl AsRef<str>,
+        value: impl Into<Vec<u8>>,
+        ttl: Duration,
+    ) -> Result<Option<Arc<[u8]>>, CacheError> {
+        self.handle
+            .set_with_ttl(self.key(key.as_ref()), value, ttl)
+            .await
+    }
+
+    pub async fn get(&self, key: impl AsRef<str>) -> Result<Option<Arc<[u8]>>, CacheError> {
+        self.handle.get(self.key(key.as_ref())).await
+    }
+
+    /// Deletes `key`, returning the value it held
+    pub async fn delete(&self, key: impl AsRef<str>) -> Result<Option<Arc<[u8]>>, CacheError> {
+        self.handle.delete(self.key(key.as_ref())).await
+    }
+
+    /// Lists the keys of this namespace, without its name
+    pub async fn keys(&self) -> Result<Vec<String>, CacheError> {
+        let keys = self.handle.keys().await?;
+        Ok(keys
+            .into_iter()
+            .filter_map(|k| k.strip_prefix(&self.prefix[..]).map(str::to_string))
+            .collect())
+    }
+
+    /// Deletes every key of this namespace, leaving the other namespaces alone.
+    /// Returns how many were deleted
+    pub async fn clear(&self) -> Result<usize, CacheError> {
+        self.handle.delete_prefix(&self.prefix).await
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Client;
+    use serial_test::serial;
+
+    const STORE_PATH: &str = "client_db";
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn namespaces_hold_the_same_key_independently() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        let sessions = client.namespace("sessions");
+        let pages = client.namespace("pages");
+
+        sessions.set("hey", b"English".to_vec()).await.unwrap();
+        pages.set("hey", b"Slang".to_vec()).await.unwrap();
+        pages.set("hi", b"French".to_vec()).await.unwrap();
+
+        assert_eq!(&sessions.get("hey").await.unwrap().unwrap()[..], b"English");
+        assert_eq!(&pages.get("hey").await.unwrap().unwrap()[..], b"Slang");
+        assert_eq!(sessions.get("hi").await.unwrap(), None);
+        assert_eq!(pages.keys().await.unwrap(), vec!["hey", "hi"]);
+
+        assert_eq!(pages.clear().await.unwrap(), 2);
+        assert!(pages.keys().await.unwrap().is_empty());
+        assert_eq!(&sessions.get("hey").await.unwrap().unwrap()[..], b"English");
+
+        let deleted = sessions.delete("hey").await.unwrap();
+        assert_eq!(&deleted.unwrap()[..], b"English");
+        assert!(client.keys().await.unwrap().is_empty());
+
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    #[should_panic(expected = "contains a ':'")]
+    async fn namespace_names_cannot_contain_the_separator() {
+        let client = Client::builder().workers(2).in_memory().build().unwrap();
+        client.namespace("a:b");
+    }
+}
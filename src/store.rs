@@ -1,336 +1,4792 @@
+use crate::backend::{FileBackend, MemoryBackend, RetryingBackend, StorageBackend};
+use crate::error::{ReadOnly, ValueTooLarge};
+use crate::eviction::EvictionPolicy;
+use crate::log::LogBackend;
 use core::option::Option::{None, Some};
-use std::collections::HashMap;
+use dashmap::DashMap;
+use futures_util::FutureExt;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::io;
-use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::sync::{
+    broadcast, mpsc, oneshot, watch, Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
 use tokio::task::JoinHandle;
 use tokio::time::{sleep, Duration};
 
+/// Outcome of a batch operation, holding one result per key in request order
+pub type BatchResult = Vec<io::Result<Option<Arc<[u8]>>>>;
+
+/// Values looked up for several keys, in the order of the keys, `None` for missing ones
+pub type Values = Vec<Option<Arc<[u8]>>>;
+
+/// Keys paired with their values, sorted by key
+pub type KeyValuePairs = Vec<(String, Arc<[u8]>)>;
+
+/// Computes the new value of a key from its current one, run by a worker while it
+/// holds the key. `None` stands for the key being absent, before and after
+pub type Modifier = Box<dyn FnOnce(Option<Arc<[u8]>>) -> io::Result<Option<Vec<u8>>> + Send>;
+
 pub enum Action {
     Set {
         key: String,
-        value: String,
-        resp: oneshot::Sender<io::Result<Option<String>>>,
+        value: Vec<u8>,
+        expires_at: Option<SystemTime>,
+        idle_ttl: Option<Duration>,
+        resp: oneshot::Sender<io::Result<Option<Arc<[u8]>>>>,
+    },
+    SetDetailed {
+        key: String,
+        value: Vec<u8>,
+        resp: oneshot::Sender<io::Result<SetOutcome>>,
+    },
+    SetMany {
+        entries: Vec<(String, Vec<u8>)>,
+        resp: oneshot::Sender<io::Result<BatchResult>>,
     },
     Get {
         key: String,
-        resp: oneshot::Sender<io::Result<Option<String>>>,
+        resp: oneshot::Sender<io::Result<Option<Arc<[u8]>>>>,
+    },
+    Peek {
+        key: String,
+        resp: oneshot::Sender<io::Result<Option<Arc<[u8]>>>>,
+    },
+    GetCached {
+        key: String,
+        resp: oneshot::Sender<io::Result<Option<Arc<[u8]>>>>,
+    },
+    Meta {
+        key: String,
+        resp: oneshot::Sender<io::Result<Option<KeyMetadata>>>,
+    },
+    GetMany {
+        keys: Vec<String>,
+        resp: oneshot::Sender<io::Result<Values>>,
+    },
+    Prefetch {
+        keys: Vec<String>,
+        resp: oneshot::Sender<io::Result<()>>,
+    },
+    Incr {
+        key: String,
+        delta: i64,
+        resp: oneshot::Sender<io::Result<i64>>,
+    },
+    Cas {
+        key: String,
+        expected: Option<Vec<u8>>,
+        new: Vec<u8>,
+        resp: oneshot::Sender<io::Result<bool>>,
+    },
+    Modify {
+        key: String,
+        modify: Modifier,
+        resp: oneshot::Sender<io::Result<Option<Arc<[u8]>>>>,
+    },
+    SetIfAbsent {
+        key: String,
+        value: Vec<u8>,
+        resp: oneshot::Sender<io::Result<bool>>,
+    },
+    SetIfPresent {
+        key: String,
+        value: Vec<u8>,
+        resp: oneshot::Sender<io::Result<bool>>,
+    },
+    Exists {
+        key: String,
+        resp: oneshot::Sender<io::Result<bool>>,
+    },
+    ExistsMany {
+        keys: Vec<String>,
+        resp: oneshot::Sender<io::Result<Vec<bool>>>,
+    },
+    Touch {
+        key: String,
+        expires_at: SystemTime,
+        resp: oneshot::Sender<io::Result<bool>>,
     },
     Del {
         key: String,
-        resp: oneshot::Sender<io::Result<Option<String>>>,
+        resp: oneshot::Sender<io::Result<Option<Arc<[u8]>>>>,
+    },
+    DelMany {
+        keys: Vec<String>,
+        resp: oneshot::Sender<io::Result<BatchResult>>,
+    },
+    DelPrefix {
+        prefix: String,
+        resp: oneshot::Sender<io::Result<usize>>,
+    },
+    Keys {
+        resp: oneshot::Sender<io::Result<Vec<String>>>,
+    },
+    Len {
+        resp: oneshot::Sender<io::Result<usize>>,
+    },
+    ScanPrefix {
+        prefix: String,
+        resp: oneshot::Sender<io::Result<KeyValuePairs>>,
     },
     Clear {
         resp: oneshot::Sender<io::Result<()>>,
     },
+    Drain {
+        resp: oneshot::Sender<io::Result<KeyValuePairs>>,
+    },
+    Flush {
+        resp: oneshot::Sender<io::Result<()>>,
+    },
+    Compact {
+        resp: oneshot::Sender<io::Result<CompactReport>>,
+    },
+    Snapshot {
+        out_path: String,
+        resp: oneshot::Sender<io::Result<()>>,
+    },
+    Restore {
+        in_path: String,
+        resp: oneshot::Sender<io::Result<usize>>,
+    },
+    Stats {
+        resp: oneshot::Sender<io::Result<CacheStats>>,
+    },
+    Ping {
+        resp: oneshot::Sender<io::Result<()>>,
+    },
+    /// Sets queued one right behind the other for `key`, gathered by a worker of a
+    /// store that coalesces sets. Never sent by a client
+    SetCoalesced { key: String, sets: Vec<QueuedSet> },
+}
+
+/// A set of [`Action::SetCoalesced`], as it was sent
+pub struct QueuedSet {
+    value: Vec<u8>,
+    expires_at: Option<SystemTime>,
+    idle_ttl: Option<Duration>,
+    resp: oneshot::Sender<io::Result<Option<Arc<[u8]>>>>,
+}
+
+#[cfg(feature = "tracing")]
+impl Action {
+    /// Names the operation, for instrumentation
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Set { .. } => "set",
+            Action::SetDetailed { .. } => "set_detailed",
+            Action::SetMany { .. } => "set_many",
+            Action::Get { .. } => "get",
+            Action::Peek { .. } => "peek",
+            Action::GetCached { .. } => "get_cached",
+            Action::Meta { .. } => "metadata",
+            Action::GetMany { .. } => "get_many",
+            Action::Prefetch { .. } => "prefetch",
+            Action::Incr { .. } => "increment",
+            Action::Cas { .. } => "compare_and_swap",
+            Action::Modify { .. } => "modify",
+            Action::SetIfAbsent { .. } => "set_if_absent",
+            Action::SetIfPresent { .. } => "set_if_present",
+            Action::Exists { .. } => "exists",
+            Action::ExistsMany { .. } => "exists_many",
+            Action::Touch { .. } => "touch",
+            Action::Del { .. } => "delete",
+            Action::DelMany { .. } => "delete_many",
+            Action::DelPrefix { .. } => "delete_prefix",
+            Action::Keys { .. } => "keys",
+            Action::Len { .. } => "len",
+            Action::ScanPrefix { .. } => "scan_prefix",
+            Action::Clear { .. } => "clear",
+            Action::Drain { .. } => "drain",
+            Action::Flush { .. } => "flush",
+            Action::Compact { .. } => "compact",
+            Action::Snapshot { .. } => "snapshot",
+            Action::Restore { .. } => "restore",
+            Action::Stats { .. } => "stats",
+            Action::Ping { .. } => "ping",
+            Action::SetCoalesced { .. } => "set_coalesced",
+        }
+    }
+
+    /// The single key the action works on, if it has one
+    fn key(&self) -> Option<&str> {
+        match self {
+            Action::Set { key, .. }
+            | Action::SetCoalesced { key, .. }
+            | Action::SetDetailed { key, .. }
+            | Action::Get { key, .. }
+            | Action::Peek { key, .. }
+            | Action::GetCached { key, .. }
+            | Action::Meta { key, .. }
+            | Action::Incr { key, .. }
+            | Action::Cas { key, .. }
+            | Action::Modify { key, .. }
+            | Action::SetIfAbsent { key, .. }
+            | Action::SetIfPresent { key, .. }
+            | Action::Exists { key, .. }
+            | Action::Touch { key, .. }
+            | Action::Del { key, .. } => Some(key),
+            _ => None,
+        }
+    }
+}
+
+/// A value together with the instant after which it is no longer served.
+///
+/// The value is shared, so reads hand out a reference-counted pointer to it instead
+/// of copying it: cloning an entry out of memory costs no allocation whatever its size
+#[derive(Clone)]
+pub(crate) struct Entry {
+    pub(crate) value: Arc<[u8]>,
+    pub(crate) expires_at: Option<SystemTime>,
+    /// Set for entries that expire once idle: each get then moves `expires_at`
+    /// this far past the read
+    pub(crate) idle_ttl: Option<Duration>,
+}
+
+impl Entry {
+    pub(crate) fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(t) => t <= SystemTime::now(),
+            None => false,
+        }
+    }
+
+    /// Returns the value unless the entry has already expired
+    fn into_live_value(self) -> Option<Arc<[u8]>> {
+        if self.is_expired() {
+            None
+        } else {
+            Some(self.value)
+        }
+    }
+}
+
+/// Settings that tune how a store keeps its data
+#[derive(Clone, Debug, Default)]
+pub struct StoreOptions {
+    /// Load every unexpired entry persisted in the store directory into memory on startup
+    pub preload: bool,
+    /// How much sets and deletes are flushed to disk before they return
+    pub durability: Durability,
+    /// How often a set or delete that hit a transient filesystem error is tried
+    /// again before the error is returned
+    pub retry: Retry,
+    /// Where and how entries are persisted
+    pub storage: Storage,
+    /// Most entries kept in memory. Once exceeded, the entries `eviction` picks are
+    /// dropped from memory but stay on disk, where later reads still find them
+    pub max_entries: Option<usize>,
+    /// Most value bytes kept in memory. Once exceeded, entries are dropped from
+    /// memory as with `max_entries`. Setting a single value larger than this fails
+    /// with [`CacheError::ValueTooLarge`](crate::CacheError::ValueTooLarge)
+    pub max_bytes: Option<usize>,
+    /// Largest value a set accepts, in bytes. A larger one fails with
+    /// [`CacheError::ValueTooLarge`](crate::CacheError::ValueTooLarge) before
+    /// anything is written, leaving the key as it was
+    pub max_value_bytes: Option<usize>,
+    /// Which entries are dropped from memory first once over `max_entries` or
+    /// `max_bytes`
+    pub eviction: Eviction,
+    /// How values are compressed in their files. Compressed and uncompressed files
+    /// are told apart when read, so this can be changed for an existing store
+    pub compression: Compression,
+    /// How the typed client encodes values. The store itself never reads it
+    pub format: Format,
+    /// Makes keys set without an expiry of their own expire once they have not
+    /// been read for this long: every get restarts their countdown, so keys in use
+    /// stay while idle ones go. Each such read also writes the new expiry to disk
+    pub idle_ttl: Option<Duration>,
+    /// How long the client waits on the store for each operation before failing
+    /// it with [`CacheError::Timeout`](crate::CacheError::Timeout). Like `format`,
+    /// only the client reads it
+    pub timeout: Option<Duration>,
+    /// How the files of [`Storage::Files`] are laid out in the store directory
+    pub layout: Layout,
+    /// How the files of [`Storage::Files`] are spread over shard directories.
+    /// Changing it for an existing store leaves every entry in it unreachable
+    pub shard_hasher: ShardHasher,
+    /// Extension given to the files of [`Storage::Files`], without the dot, such
+    /// as `val`. Files without it are then left out when the keys are listed, so
+    /// other files can safely share the store directory. Like the shard hasher,
+    /// it cannot be changed for an existing store
+    pub file_extension: Option<String>,
+    /// Permission bits of the directories the store creates, such as `0o700`, less
+    /// those the umask clears. Only used on Unix; directories that already exist
+    /// are left as they are
+    pub dir_mode: Option<u32>,
+    /// Permission bits of the value files, and of the log of [`Storage::Log`], less
+    /// those the umask clears. Only used on Unix, like `dir_mode`
+    pub file_mode: Option<u32>,
+    /// Called on a `get` of a key that is neither in memory nor on disk. A value it
+    /// returns is set before being handed back, so the loader runs once per key
+    /// until that key is deleted or expires
+    pub loader: Option<Loader>,
+    /// Called with each entry evicted from memory to stay within `max_entries` or
+    /// `max_bytes`, just before it is removed. A panic in it is caught, so the
+    /// action that made room goes through all the same
+    pub on_evict: Option<EvictionListener>,
+    /// Write values back to disk in batches, this often, instead of on every set.
+    ///
+    /// A set then only updates memory and returns, which is much faster under
+    /// write-heavy loads. The price is that values set since the last batch are lost
+    /// if the process or machine crashes; `flush` and `close` write them out first
+    pub write_back: Option<Duration>,
+    /// Open an existing store for reading alone. Every operation that would change
+    /// its entries fails with [`CacheError::ReadOnly`](crate::CacheError::ReadOnly),
+    /// and nothing is written to its directory, which may then be on a read-only
+    /// mount. Any number of read-only stores can share a directory, but not with a
+    /// store that writes to it
+    pub read_only: bool,
+    /// Have a worker that takes a set also take the sets to the same key queued
+    /// right behind it, and write only the last of their values. Every caller is
+    /// still answered with the value its set replaced, so this only spares the disk
+    /// the writes of values that would at once be overwritten, as under a storm of
+    /// sets to a hot key
+    pub coalesce_sets: bool,
+}
+
+/// Future returned by a [`Loader`]
+pub type LoaderFuture = Pin<Box<dyn Future<Output = io::Result<Option<String>>> + Send>>;
+
+/// Fetches the value of a key the store does not have, e.g. from a database, turning
+/// the store into a read-through cache. `Ok(None)` means the key does not exist
+/// there either, and is not stored; an error is returned from the `get` as it is
+#[derive(Clone)]
+pub struct Loader(Arc<dyn Fn(&str) -> LoaderFuture + Send + Sync>);
+
+impl Loader {
+    pub fn new(load: impl Fn(&str) -> LoaderFuture + Send + Sync + 'static) -> Loader {
+        Loader(Arc::new(load))
+    }
+}
+
+impl std::fmt::Debug for Loader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Loader")
+    }
+}
+
+/// Told the key and value of each entry evicted from memory, just before the entry
+/// is removed, e.g. to move it to a colder tier. Values that are not UTF-8 are
+/// passed with their invalid bytes replaced by `U+FFFD`.
+///
+/// It runs on the worker making room once that worker holds no lock, so the other
+/// workers carry on meanwhile, but the action that made room waits on it: it should
+/// be quick and hand slow work to a task of its own. A panic in it is caught, and
+/// logged with the `tracing` feature, and the entry is evicted all the same
+#[derive(Clone)]
+pub struct EvictionListener(Arc<OnEvict>);
+
+type OnEvict = dyn Fn(&str, &str) + Send + Sync;
+
+impl EvictionListener {
+    pub fn new(on_evict: impl Fn(&str, &str) + Send + Sync + 'static) -> EvictionListener {
+        EvictionListener(Arc::new(on_evict))
+    }
+}
+
+impl std::fmt::Debug for EvictionListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("EvictionListener")
+    }
+}
+
+/// Retries of the file writes and removals behind sets and deletes that fail with
+/// an error that may pass, such as `EAGAIN` or `EBUSY` on a network filesystem.
+/// Other errors, like `NotFound` or `PermissionDenied`, are returned at once.
+///
+/// Retry `n` waits `base_delay * 2^(n - 1)` first. Nothing is retried by default
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Retry {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Retry {
+            max_retries: 0,
+            base_delay: Duration::from_millis(10),
+        }
+    }
+}
+
+/// Codec applied to values before they are written to disk
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Compression {
+    #[default]
+    None,
+    /// Needs the `gzip` feature
+    #[cfg(feature = "gzip")]
+    Gzip,
+}
+
+/// Encoding of the values a [`TypedClient`](crate::TypedClient) stores. Values
+/// written in one format do not read back in another, so a store keeps one
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    /// The value as it is. Only for values that serialize to a string, such as
+    /// `String` or a unit-only enum, which are stored as the string's bytes
+    Raw,
+    /// Human-readable JSON, which is handy to inspect the values by hand
+    #[default]
+    Json,
+    /// Compact bincode, which is smaller and faster to read and write than JSON.
+    /// Needs the `bincode` feature
+    #[cfg(feature = "bincode")]
+    Bincode,
+}
+
+/// Where and how a store persists its entries. The layouts on disk do not read
+/// each other's files, so a store keeps the one it was created with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Storage {
+    /// One file per key. Each set rewrites a small file, and the entries can be
+    /// inspected with ordinary file tools
+    #[default]
+    Files,
+    /// A single log that every set and delete is appended to. Writes are much
+    /// cheaper, at the cost of holding an index of every key in memory and of
+    /// rewriting the log now and then to reclaim the space of old values
+    Log,
+    /// Nothing is read from or written to disk, not even the store directory, and
+    /// every entry is lost once the store closes. Meant for tests, and for caches
+    /// that need no persistence
+    Memory,
+}
+
+/// Order in which a store drops entries from memory to stay within `max_entries`
+/// and `max_bytes`. Evicted entries stay on disk either way
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Eviction {
+    /// The least recently used entry goes first, which suits most workloads
+    #[default]
+    Lru,
+    /// The least frequently used entry goes first, so that a few hot keys stay in
+    /// memory through scans of many keys read once
+    Lfu,
+    /// The entry cached first goes first, however much it is used. The cheapest
+    /// to keep track of
+    Fifo,
+}
+
+/// How the files of [`Storage::Files`] are laid out in the store directory. Pick
+/// one for the number of keys expected: directories holding too many files are
+/// slow to list and to look files up in on most filesystems.
+///
+/// A store keeps the layout it was created with, which it records in the store
+/// directory, and fails to open with any other. A single file holding every
+/// entry is [`Storage::Log`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layout {
+    /// Every file right in the store directory, which suits a few thousand keys
+    Flat,
+    /// Files spread over `depth` levels of shard directories, each named after the
+    /// next `width` hex digits of the hash of the key. The 8 digits of the hash
+    /// allow for `depth * width` up to 8. Each level of `width` digits splits the
+    /// keys `16^width` ways
+    Sharded { depth: u8, width: u8 },
+}
+
+/// Two levels of 256 directories, enough for tens of millions of keys
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout::Sharded { depth: 2, width: 2 }
+    }
+}
+
+/// Hash function that picks the shard directories of a key's file under
+/// [`Storage::Files`].
+///
+/// Each key is looked for only where the hasher that wrote it put it, so a store
+/// has to keep the hasher it was created with: after a change its entries are
+/// still listed by `keys` but no longer read, replaced or deleted by key. To move
+/// a store to another hasher, snapshot it, then clear it and restore the snapshot
+/// with the new hasher in place
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShardHasher {
+    /// 32-bit FNV-1a, which is very fast on the short keys caches mostly hold
+    #[default]
+    Fnv1a,
+    /// 32-bit MurmurHash3, which spreads keys that differ only slightly, such as
+    /// numbered ones, more evenly at a small cost in speed
+    Murmur3,
+}
+
+/// How hard a store works to have its sets and deletes survive a crash of the
+/// machine, such as a power loss. Every level survives a crash of the process alone,
+/// and each is slower than the one before
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Durability {
+    /// Leaves writing to disk to the OS. A crash may lose the sets and deletes of the
+    /// last few seconds, and on some filesystems leave a value empty
+    #[default]
+    None,
+    /// Flushes each value file to disk before it replaces the previous one, so that
+    /// a value read back is always whole. A crash may still undo the latest sets and
+    /// deletes, since the directories naming the files are not flushed
+    Data,
+    /// Also flushes the directories from the value file up to the store after each
+    /// set and delete, so that once either returns it survives a crash
+    Full,
+}
+
+/// A snapshot of how a store is doing. The counters cover every lookup of a key,
+/// including the reads done by `increment` and `compare_and_swap`, since the store opened
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups served from memory
+    pub hits: u64,
+    /// Lookups of keys that were neither in memory nor on disk, or had expired
+    pub misses: u64,
+    /// Lookups that had to read the value from disk
+    pub disk_fallbacks: u64,
+    /// Entries dropped from memory to stay within `max_entries` or `max_bytes`
+    pub evictions: u64,
+    /// Keys deleted, on their own or as part of a prefix. Clears are not counted
+    pub deletes: u64,
+    /// Number of entries held in memory
+    pub entry_count: usize,
+    /// Total size of the values held in memory
+    pub cached_bytes: usize,
+    /// Operations the workers have served
+    pub operations: u64,
+    /// Shortest time a worker took over an operation, from taking it off the queue
+    /// to answering it. Zero until an operation has been served
+    pub min_latency: Duration,
+    /// Longest time a worker took over an operation, measured like `min_latency`
+    pub max_latency: Duration,
+    /// Average time a worker took over an operation, measured like `min_latency`.
+    /// Time spent waiting in the queue is left out, so this rising tells of
+    /// slower disk IO rather than of more load
+    pub avg_latency: Duration,
+}
+
+/// What a [`compact`](crate::ClientHandle::compact) cleaned up
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactReport {
+    /// Temporary files left by writes that a crash cut short
+    pub tmp_files_removed: usize,
+    /// Shard directories, and directories of long keys, that held nothing
+    pub dirs_removed: usize,
+    /// Disk space freed, in bytes: that of the files removed and, under
+    /// [`Storage::Log`], of the records the log held for nothing
+    pub bytes_reclaimed: u64,
+}
+
+/// What a [`set_detailed`](crate::ClientHandle::set_detailed) found at its key
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetOutcome {
+    /// Whether the key held no live value, in memory or on disk, before the set
+    pub created: bool,
+    /// The live value the set replaced
+    pub previous: Option<Arc<[u8]>>,
+}
+
+/// What a store has seen of a key since it opened, for finding keys that are
+/// still live but no longer read. Kept in memory only, and for the keys held there
+/// alone: it starts over whenever the store is opened, and for a key evicted from
+/// memory and later cached again
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct KeyMetadata {
+    /// When the key was set while absent, or `None` if it was set before the
+    /// store opened
+    pub created_at: Option<SystemTime>,
+    /// When the key was last read by a get, or `None` if it has not been since
+    /// the store opened
+    pub last_accessed: Option<SystemTime>,
+    /// Number of gets that found the key since the store opened
+    pub access_count: u64,
+}
+
+/// A change made to the store, published to the subscribers of
+/// [`Client::subscribe`](crate::Client::subscribe) once it has succeeded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CacheEvent {
+    /// `key` was given a new value, by a set, an increment, a swap or a restore
+    Set { key: String },
+    /// `key` was deleted, on its own or as part of a prefix
+    Deleted { key: String },
+    /// `count` keys starting with `prefix` were deleted, each of them told of by a
+    /// `Deleted` event first. Not published when no key matched
+    PrefixDeleted { prefix: String, count: usize },
+    /// Every key was deleted
+    Cleared,
+}
+
+/// Running totals behind [`CacheStats`]
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    disk_fallbacks: AtomicU64,
+    evictions: AtomicU64,
+    deletes: AtomicU64,
+    operations: AtomicU64,
+    /// Time spent on the operations, and the shortest and longest of them, in
+    /// nanoseconds. The shortest is 0 until the first operation
+    busy_nanos: AtomicU64,
+    min_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Counters {
+    fn record(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records an operation that took a worker `elapsed`
+    fn record_latency(&self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX).max(1);
+        Counters::record(&self.operations);
+        self.busy_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+        let _ = self
+            .min_nanos
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |min| {
+                (min == 0 || nanos < min).then_some(nanos)
+            });
+    }
+}
+
+/// Events held for each subscriber. A subscriber that falls further behind misses the
+/// oldest ones and is told so by `RecvError::Lagged`, so the workers never wait on it
+pub(crate) const EVENT_CAPACITY: usize = 1024;
+
+/// Number of locks the keys are spread over. Far more than any sensible number of
+/// workers, so that actions on different keys rarely wait for each other
+const KEY_LOCK_STRIPES: usize = 256;
+
+/// State shared by every handler of a store
+struct Shared {
+    db: DashMap<String, Entry>,
+    backend: Box<dyn StorageBackend>,
+    options: StoreOptions,
+    /// Serializes the writes to any one key, so that e.g. two sets of a key never
+    /// interleave their file write and memory update, and keeps reads of the key out
+    /// while one is under way. Reads share it, so any number of them run at once.
+    /// Keys are hashed onto a fixed number of stripes, and actions on keys of
+    /// different stripes run concurrently
+    key_locks: Vec<RwLock<()>>,
+    /// Shared by every action that writes, and held exclusively by clears, drains,
+    /// flushes, snapshots and restores so that they see no write half-done
+    write_gate: RwLock<()>,
+    /// Picks the keys to evict from `db`. Held while `db` itself is changed so
+    /// that the two always hold the same keys, but for those being evicted
+    eviction: std::sync::Mutex<Box<dyn EvictionPolicy>>,
+    /// Total size of the values in `db`, only changed while `eviction` is held
+    cached_bytes: AtomicUsize,
+    /// Entries picked for eviction, and the size of their values, that stay in
+    /// `db` until the eviction listener has been told of them. They no longer
+    /// count against `max_entries` and `max_bytes`
+    evicting: AtomicUsize,
+    evicting_bytes: AtomicUsize,
+    counters: Counters,
+    events: broadcast::Sender<CacheEvent>,
+    /// Entries set in write-back mode whose files have not been written yet. They
+    /// are kept here even once evicted from `db`, so that nothing set is ever lost
+    dirty: std::sync::Mutex<HashMap<String, Entry>>,
+    /// Kept for the keys in `db` alone, so that it never outgrows `max_entries`.
+    /// Added to only while `eviction` is held, so that no key evicted meanwhile
+    /// gets an entry
+    metadata: DashMap<String, KeyMetadata>,
+}
+
+impl Shared {
+    /// Waits until no other action is working on `key`'s stripe
+    async fn lock_key(&self, key: &str) -> RwLockWriteGuard<'_, ()> {
+        self.key_locks[self.key_stripe(key)].write().await
+    }
+
+    /// Waits until no write is working on `key`'s stripe, letting other reads in
+    async fn read_key(&self, key: &str) -> RwLockReadGuard<'_, ()> {
+        self.key_locks[self.key_stripe(key)].read().await
+    }
+
+    fn key_stripe(&self, key: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        (hasher.finish() % self.key_locks.len() as u64) as usize
+    }
+
+    /// Records that `key` was set, restarting its metadata if the entry it
+    /// replaced had expired
+    fn record_set(&self, key: &str, replaced_expired: bool) {
+        let _eviction = self.eviction.lock().unwrap();
+        if !self.db.contains_key(key) {
+            return;
+        }
+        if replaced_expired {
+            self.metadata.remove(key);
+        }
+        self.metadata
+            .entry(key.to_string())
+            .or_insert_with(|| KeyMetadata {
+                created_at: Some(SystemTime::now()),
+                ..KeyMetadata::default()
+            });
+    }
+
+    /// Records that a get found `key`
+    fn record_access(&self, key: &str) {
+        let _eviction = self.eviction.lock().unwrap();
+        if !self.db.contains_key(key) {
+            return;
+        }
+        let mut metadata = self.metadata.entry(key.to_string()).or_default();
+        metadata.last_accessed = Some(SystemTime::now());
+        metadata.access_count += 1;
+    }
+
+    fn publish(&self, event: CacheEvent) {
+        // fails only when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    /// Caches `entry`, evicting the entries the eviction policy picks while beyond
+    /// `max_entries` or `max_bytes`. Returns the entry it replaced
+    fn cache(&self, key: String, entry: Entry) -> Option<Entry> {
+        let mut evicted_entries = Vec::new();
+        let mut eviction = self.eviction.lock().unwrap();
+        eviction.on_insert(&key);
+        self.cached_bytes
+            .fetch_add(entry.value.len(), Ordering::Relaxed);
+        let prev = self.db.insert(key, entry);
+        if let Some(p) = &prev {
+            self.cached_bytes
+                .fetch_sub(p.value.len(), Ordering::Relaxed);
+        }
+
+        let on_evict = self.options.on_evict.as_ref();
+        while self.is_over_capacity() {
+            match eviction.evict_candidate() {
+                Some(evicted) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(key = %evicted, "evicted from memory");
+                    if on_evict.is_none() {
+                        self.remove_cached(&evicted);
+                        self.metadata.remove(&evicted);
+                    } else if let Some(e) = self.db.get(&evicted).map(|e| e.value.clone()) {
+                        // left in `db` for the listener, only out of the count
+                        self.evicting.fetch_add(1, Ordering::Relaxed);
+                        self.evicting_bytes.fetch_add(e.len(), Ordering::Relaxed);
+                        evicted_entries.push((evicted, e));
+                    }
+                }
+                None => break,
+            };
+            Counters::record(&self.counters.evictions);
+        }
+        // the listener runs unlocked, so that a panic in it cannot poison `eviction`
+        drop(eviction);
+
+        let Some(on_evict) = on_evict else {
+            return prev;
+        };
+        for (key, value) in &evicted_entries {
+            let value = String::from_utf8_lossy(value);
+            let told = std::panic::catch_unwind(AssertUnwindSafe(|| (on_evict.0)(key, &value)));
+            if told.is_err() {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(key, "eviction listener panicked");
+            }
+        }
+
+        let _eviction = self.eviction.lock().unwrap();
+        for (key, value) in evicted_entries {
+            // an entry set meanwhile is tracked by the policy again, so it stays
+            let removed = self
+                .db
+                .remove_if(&key, |_, e| Arc::ptr_eq(&e.value, &value));
+            if removed.is_some() {
+                self.cached_bytes.fetch_sub(value.len(), Ordering::Relaxed);
+                self.metadata.remove(&key);
+            }
+            self.evicting.fetch_sub(1, Ordering::Relaxed);
+            self.evicting_bytes
+                .fetch_sub(value.len(), Ordering::Relaxed);
+        }
+
+        prev
+    }
+
+    fn fits_in_memory(&self, entry: &Entry) -> bool {
+        !matches!(self.options.max_bytes, Some(max) if entry.value.len() > max)
+    }
+
+    fn is_over_capacity(&self) -> bool {
+        // an entry being evicted may have been deleted or replaced meanwhile
+        let entries = self
+            .db
+            .len()
+            .saturating_sub(self.evicting.load(Ordering::Relaxed));
+        let bytes = self
+            .cached_bytes
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.evicting_bytes.load(Ordering::Relaxed));
+        let too_many = matches!(self.options.max_entries, Some(max) if entries > max);
+        let too_big = matches!(self.options.max_bytes, Some(max) if bytes > max);
+
+        too_many || too_big
+    }
+
+    /// Looks `key` up in memory, telling the eviction policy of the use
+    fn cached(&self, key: &str) -> Option<Entry> {
+        let mut eviction = self.eviction.lock().unwrap();
+        let entry = self.db.get(key).map(|e| e.clone())?;
+        eviction.on_access(key);
+        Some(entry)
+    }
+
+    fn uncache(&self, key: &str) -> Option<Entry> {
+        let mut eviction = self.eviction.lock().unwrap();
+        eviction.on_remove(key);
+        self.remove_cached(key)
+    }
+
+    /// Drops `key` from memory if its entry has expired, returning whether it did
+    fn uncache_if_expired(&self, key: &str) -> bool {
+        let mut eviction = self.eviction.lock().unwrap();
+        let removed = self.db.remove_if(key, |_, e| e.is_expired());
+        if let Some((_, e)) = &removed {
+            self.cached_bytes
+                .fetch_sub(e.value.len(), Ordering::Relaxed);
+            eviction.on_remove(key);
+        }
+        removed.is_some()
+    }
+
+    fn clear_cache(&self) {
+        let mut eviction = self.eviction.lock().unwrap();
+        eviction.clear();
+        self.db.clear();
+        self.cached_bytes.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the entry of `key` waiting to be written back, if any
+    fn unwritten(&self, key: &str) -> Option<Entry> {
+        self.dirty.lock().unwrap().get(key).cloned()
+    }
+
+    /// Removes `key` from `db` alone. Callers hold `eviction` and keep it in step
+    fn remove_cached(&self, key: &str) -> Option<Entry> {
+        let (_, entry) = self.db.remove(key)?;
+        self.cached_bytes
+            .fetch_sub(entry.value.len(), Ordering::Relaxed);
+        Some(entry)
+    }
 }
 
-pub struct Store {
-    db: Arc<Mutex<HashMap<String, String>>>,
-    handlers: Vec<JoinHandle<()>>,
-    store_path: String,
-    receiver_mutex_arc: Arc<Mutex<mpsc::Receiver<Action>>>,
-}
+pub struct Store {
+    shared: Arc<Shared>,
+    handlers: Vec<JoinHandle<()>>,
+    receiver_mutex_arc: Arc<Mutex<mpsc::Receiver<Action>>>,
+    /// Set to `true` to have the handlers finish the queued actions and stop
+    closing: watch::Sender<bool>,
+    /// Task writing the dirty entries to disk, in write-back mode
+    write_back: Option<JoinHandle<()>>,
+    /// Holds the lock keeping other stores off `store_path` until closed.
+    /// Stores in memory touch no directory, so they have none
+    lock_file: Option<std::fs::File>,
+}
+
+/// Sums the store up instead of listing its entries
+impl std::fmt::Debug for Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Store")
+            .field("storage", &self.shared.options.storage)
+            .field("workers", &self.handlers.len())
+            .field("workers_alive", &self.workers_alive())
+            .field("entries", &self.shared.db.len())
+            .field(
+                "cached_bytes",
+                &self.shared.cached_bytes.load(Ordering::Relaxed),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Store {
+    #[cfg(test)]
+    pub(crate) fn new(
+        receiver: mpsc::Receiver<Action>,
+        num_of_handlers: usize,
+        store_path: &str,
+    ) -> Store {
+        Store::with_options(
+            receiver,
+            num_of_handlers,
+            store_path,
+            StoreOptions::default(),
+        )
+        .unwrap()
+    }
+
+    pub(crate) fn with_options(
+        receiver: mpsc::Receiver<Action>,
+        num_of_handlers: usize,
+        store_path: &str,
+        options: StoreOptions,
+    ) -> io::Result<Store> {
+        if num_of_handlers == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a store needs at least 1 worker",
+            ));
+        }
+
+        let lock_file = match options.storage {
+            Storage::Memory => None,
+            Storage::Files | Storage::Log if options.read_only => {
+                let lock_file = crate::fs::share_store(store_path)?;
+                crate::fs::check_layout(store_path, &options)?;
+                lock_file
+            }
+            Storage::Files | Storage::Log => {
+                crate::fs::initialize_file_db(store_path, options.dir_mode)?;
+                let lock_file = crate::fs::lock_store(store_path)?;
+                crate::fs::check_layout(store_path, &options)?;
+                Some(lock_file)
+            }
+        };
+        let mut backend: Box<dyn StorageBackend> = match options.storage {
+            Storage::Files => Box::new(FileBackend::new(store_path, &options)?),
+            Storage::Log => Box::new(LogBackend::open(store_path, &options)?),
+            Storage::Memory => Box::new(MemoryBackend::default()),
+        };
+        if options.retry.max_retries > 0 && options.storage != Storage::Memory {
+            backend = Box::new(RetryingBackend::new(backend, options.retry));
+        }
+
+        Ok(Store::start(
+            receiver,
+            num_of_handlers,
+            options,
+            backend,
+            lock_file,
+        ))
+    }
+
+    /// Starts the handlers of a store keeping its entries in `backend`
+    fn start(
+        receiver: mpsc::Receiver<Action>,
+        num_of_handlers: usize,
+        options: StoreOptions,
+        backend: Box<dyn StorageBackend>,
+        lock_file: Option<std::fs::File>,
+    ) -> Store {
+        let preload = options.preload;
+        let eviction = crate::eviction::new_policy(options.eviction);
+        let shared = Shared {
+            db: DashMap::new(),
+            backend,
+            options,
+            key_locks: (0..KEY_LOCK_STRIPES).map(|_| RwLock::new(())).collect(),
+            write_gate: RwLock::new(()),
+            eviction: std::sync::Mutex::new(eviction),
+            cached_bytes: AtomicUsize::new(0),
+            evicting: AtomicUsize::new(0),
+            evicting_bytes: AtomicUsize::new(0),
+            counters: Counters::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+            dirty: std::sync::Mutex::new(HashMap::new()),
+            metadata: DashMap::new(),
+        };
+
+        if preload {
+            for (key, entry) in shared.backend.load_all() {
+                if !entry.is_expired() && shared.fits_in_memory(&entry) {
+                    shared.cache(key, entry);
+                }
+            }
+        }
+
+        let mut store = Store {
+            shared: Arc::new(shared),
+            handlers: Vec::with_capacity(num_of_handlers),
+            receiver_mutex_arc: Arc::new(Mutex::new(receiver)),
+            closing: watch::channel(false).0,
+            write_back: None,
+            lock_file,
+        };
+
+        store.generate_handlers(num_of_handlers);
+        store.start_write_back();
+        store
+    }
+
+    /// Stops taking new actions and waits for the handlers to finish
+    /// the ones already queued
+    pub async fn close(&self) {
+        let _ = self.closing.send(true);
+
+        'outer: loop {
+            sleep(Duration::from_millis(200)).await;
+
+            for handler in &self.handlers {
+                if !handler.is_finished() {
+                    continue 'outer;
+                }
+            }
+
+            break;
+        }
+
+        let receiver_mutex = Arc::clone(&self.receiver_mutex_arc);
+        receiver_mutex.lock().await.close();
+
+        // the write-back task stopped on `closing`, so write what it had left
+        let _flushing = self.shared.write_gate.write().await;
+        if let Err(_e) = write_dirty(&self.shared).await {
+            #[cfg(feature = "tracing")]
+            tracing::warn!(error = %_e, "writing back on close failed");
+        }
+
+        if let Some(lock_file) = &self.lock_file {
+            let _ = lock_file.unlock();
+        }
+    }
+
+    /// Recreates the directory at `store_path`, and the lock and files in it, if
+    /// they were removed while the store ran. The entries memory still holds are
+    /// written back, those it does not are lost with the directory
+    pub(crate) async fn ensure_store(&mut self, store_path: &str) -> io::Result<()> {
+        if self.shared.options.storage == Storage::Memory {
+            return Ok(());
+        }
+        if self.shared.options.read_only {
+            return Err(ReadOnly::error());
+        }
+
+        crate::fs::initialize_file_db(store_path, self.shared.options.dir_mode)?;
+        // the lock goes with the directory, and sets only make the directory again
+        let removed = !crate::fs::lock_file_exists(store_path);
+        if removed {
+            // the old lock is on a removed file, where no other store looks for it
+            self.lock_file = Some(crate::fs::lock_store(store_path)?);
+        }
+        crate::fs::check_layout(store_path, &self.shared.options)?;
+        self.shared.backend.repair().await?;
+        if !removed {
+            return Ok(());
+        }
+
+        // keeps sets from landing between the copy taken here and its writing
+        let _repairing = self.shared.write_gate.write().await;
+        let entries: Vec<(String, Entry)> = self
+            .shared
+            .db
+            .iter()
+            .map(|e| (e.key().clone(), e.value().clone()))
+            .collect();
+        for (key, entry) in entries {
+            if !entry.is_expired() {
+                self.shared.backend.save(&key, &entry).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns a receiver of the changes made from now on
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<CacheEvent> {
+        self.shared.events.subscribe()
+    }
+
+    /// Counts the workers still running. They only stop once the store closes
+    pub(crate) fn workers_alive(&self) -> usize {
+        self.handlers.iter().filter(|h| !h.is_finished()).count()
+    }
+
+    /// Stops the handler tasks without waiting for them to finish
+    pub(crate) fn abort(&self) {
+        for handler in self.handlers.iter().chain(&self.write_back) {
+            handler.abort()
+        }
+    }
+
+    fn start_write_back(&mut self) {
+        let interval = match self.shared.options.write_back {
+            Some(interval) => interval,
+            None => return,
+        };
+        let shared = Arc::clone(&self.shared);
+        let mut closing = self.closing.subscribe();
+
+        self.write_back = Some(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = sleep(interval) => {}
+                    _ = closing.changed() => break,
+                }
+
+                // keeps sets out while the batch is written, like a flush does
+                let _flushing = shared.write_gate.write().await;
+                if let Err(_e) = write_dirty(&shared).await {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(error = %_e, "write-back failed, retrying next time");
+                }
+            }
+        }));
+    }
+
+    fn generate_handlers(&mut self, num_of_handlers: usize) {
+        for worker in 0..num_of_handlers {
+            let shared = Arc::clone(&self.shared);
+            let receiver_mutex = Arc::clone(&self.receiver_mutex_arc);
+            let mut closing = self.closing.subscribe();
+
+            let handler = tokio::spawn(async move {
+                // an action pulled while gathering sets, to be carried out next
+                let mut next = None;
+                loop {
+                    // the receiver is only held long enough to pull one action so that
+                    // the other workers can take the next ones while this one does its IO
+                    let action = match next.take() {
+                        Some(action) => Some(action),
+                        None => {
+                            let mut receiver = receiver_mutex.lock().await;
+                            tokio::select! {
+                                action = receiver.recv() => action,
+                                _ = closing.changed() => {
+                                    // refuse new actions but keep draining the queued ones
+                                    receiver.close();
+                                    receiver.recv().await
+                                }
+                            }
+                        }
+                    };
+                    let mut action = match action {
+                        Some(action) => action,
+                        // every sender is gone or the store is closing, and the queue is empty
+                        None => break,
+                    };
+                    if shared.options.coalesce_sets {
+                        action = coalesce_sets(action, &receiver_mutex, &mut next);
+                    }
+
+                    // an action that panics only drops its response, so that the
+                    // worker lives on rather than leaving the others to do its share
+                    let started = std::time::Instant::now();
+                    let ran = AssertUnwindSafe(run_action(action, &shared, worker))
+                        .catch_unwind()
+                        .await;
+                    shared.counters.record_latency(started.elapsed());
+                    if ran.is_err() {
+                        #[cfg(feature = "tracing")]
+                        tracing::error!(worker, "action panicked");
+                    }
+                }
+            });
+
+            self.handlers.push(handler);
+        }
+    }
+}
+
+/// Gathers the sets to the key of `action`, if it is a set, that are queued right
+/// behind it, to be carried out as one. The first other action pulled is left in
+/// `next`. Only the actions already queued are looked at: if another worker holds
+/// the receiver, it is waiting on an empty queue or about to take the next action
+fn coalesce_sets(
+    action: Action,
+    receiver_mutex: &Mutex<mpsc::Receiver<Action>>,
+    next: &mut Option<Action>,
+) -> Action {
+    let (key, first) = match action {
+        Action::Set {
+            key,
+            value,
+            expires_at,
+            idle_ttl,
+            resp,
+        } => (
+            key,
+            QueuedSet {
+                value,
+                expires_at,
+                idle_ttl,
+                resp,
+            },
+        ),
+        action => return action,
+    };
+    let mut sets = vec![first];
+    if let Ok(mut receiver) = receiver_mutex.try_lock() {
+        while let Ok(action) = receiver.try_recv() {
+            match action {
+                Action::Set {
+                    key: queued_key,
+                    value,
+                    expires_at,
+                    idle_ttl,
+                    resp,
+                } if queued_key == key => sets.push(QueuedSet {
+                    value,
+                    expires_at,
+                    idle_ttl,
+                    resp,
+                }),
+                action => {
+                    *next = Some(action);
+                    break;
+                }
+            }
+        }
+    }
+
+    if sets.len() > 1 {
+        return Action::SetCoalesced { key, sets };
+    }
+    let set = sets.pop().unwrap();
+    Action::Set {
+        key,
+        value: set.value,
+        expires_at: set.expires_at,
+        idle_ttl: set.idle_ttl,
+        resp: set.resp,
+    }
+}
+
+#[cfg(feature = "tracing")]
+async fn run_action(action: Action, shared: &Shared, worker: usize) {
+    use tracing::Instrument;
+
+    let span = tracing::debug_span!("action", op = action.name(), key = action.key(), worker);
+    handle_action(action, shared).instrument(span).await
+}
+
+#[cfg(not(feature = "tracing"))]
+async fn run_action(action: Action, shared: &Shared, _worker: usize) {
+    handle_action(action, shared).await
+}
+
+/// Sends the outcome of an action back to whoever asked for it. A caller that gave
+/// up waiting, by timing out or being cancelled, has nobody left to tell
+fn respond<T>(resp: oneshot::Sender<io::Result<T>>, result: io::Result<T>) {
+    #[cfg(feature = "tracing")]
+    if let Err(e) = &result {
+        tracing::warn!(error = %e, "action failed");
+    }
+
+    let _ = resp.send(result);
+}
+
+async fn handle_action(mut action: Action, shared: &Shared) {
+    let db = &shared.db;
+    if shared.options.read_only {
+        action = match refuse_write(action) {
+            Some(action) => action,
+            None => return,
+        };
+    }
+
+    match action {
+        Action::Set {
+            key,
+            value,
+            expires_at,
+            idle_ttl,
+            resp,
+        } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            let entry = Entry {
+                value: value.into(),
+                expires_at,
+                idle_ttl,
+            };
+            let prev = set_entry(shared, key, entry).await;
+            respond(resp, prev);
+        }
+        Action::SetDetailed { key, value, resp } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            let outcome = set_entry_detailed(shared, key, value).await;
+            respond(resp, outcome);
+        }
+        Action::SetMany { entries, resp } => {
+            let _writing = shared.write_gate.read().await;
+            let mut prevs = Vec::with_capacity(entries.len());
+            for (key, value) in entries {
+                let _key = shared.lock_key(&key).await;
+                let entry = Entry {
+                    value: value.into(),
+                    expires_at: None,
+                    idle_ttl: None,
+                };
+                prevs.push(set_entry(shared, key.clone(), entry).await);
+            }
+
+            respond(resp, Ok(prevs));
+        }
+        Action::Get { key, resp } => {
+            let value = get_entry(shared, &key).await;
+            respond(resp, value)
+        }
+        Action::Peek { key, resp } => {
+            let _key = shared.read_key(&key).await;
+            let value = peek_entry(shared, &key).await;
+            respond(resp, value)
+        }
+        Action::GetCached { key, resp } => {
+            let value = cached_entry(shared, &key);
+            respond(resp, Ok(value))
+        }
+        Action::Meta { key, resp } => {
+            let _key = shared.read_key(&key).await;
+            // a live key not held in memory has blank metadata
+            let metadata = match peek_entry(shared, &key).await {
+                Ok(Some(_)) => Ok(Some(
+                    shared
+                        .metadata
+                        .get(&key)
+                        .map(|m| m.clone())
+                        .unwrap_or_default(),
+                )),
+                Ok(None) => Ok(None),
+                Err(e) => Err(e),
+            };
+            respond(resp, metadata)
+        }
+        Action::GetMany { keys, resp } => {
+            let values = get_entries(shared, keys).await;
+            respond(resp, values)
+        }
+        Action::Prefetch { keys, resp } => {
+            // caching a value read before a clear would bring it back
+            let _writing = shared.write_gate.read().await;
+            let mut file_io = Ok(());
+            for key in keys {
+                let _key = shared.lock_key(&key).await;
+                file_io = prefetch_entry(shared, key).await;
+                if file_io.is_err() {
+                    break;
+                }
+            }
+
+            respond(resp, file_io)
+        }
+        Action::Incr { key, delta, resp } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            let total = increment_entry(shared, key, delta).await;
+            respond(resp, total)
+        }
+        Action::Cas {
+            key,
+            expected,
+            new,
+            resp,
+        } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            let swapped = compare_and_swap_entry(shared, key, expected, new).await;
+            respond(resp, swapped)
+        }
+        Action::Modify { key, modify, resp } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            let value = modify_entry(shared, key, modify).await;
+            respond(resp, value)
+        }
+        Action::SetIfAbsent { key, value, resp } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            let set = set_entry_if(shared, key, value, false).await;
+            respond(resp, set)
+        }
+        Action::SetIfPresent { key, value, resp } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            let set = set_entry_if(shared, key, value, true).await;
+            respond(resp, set)
+        }
+        Action::Exists { key, resp } => {
+            let _key = shared.read_key(&key).await;
+            let exists = entry_exists(shared, &key).await;
+            respond(resp, exists)
+        }
+        Action::ExistsMany { keys, resp } => {
+            let mut exists = Vec::with_capacity(keys.len());
+            let mut file_io = Ok(());
+            for key in keys {
+                let _key = shared.read_key(&key).await;
+                match entry_exists(shared, &key).await {
+                    Ok(v) => exists.push(v),
+                    Err(e) => {
+                        file_io = Err(e);
+                        break;
+                    }
+                }
+            }
+
+            respond(resp, file_io.map(|()| exists))
+        }
+        Action::Touch {
+            key,
+            expires_at,
+            resp,
+        } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            let touched = touch_entry(shared, key, expires_at).await;
+            respond(resp, touched)
+        }
+        Action::Del { key, resp } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            let prev = delete_entry(shared, key).await;
+            respond(resp, prev)
+        }
+        Action::DelMany { keys, resp } => {
+            let _writing = shared.write_gate.read().await;
+            let mut prevs = Vec::with_capacity(keys.len());
+            for key in keys {
+                let _key = shared.lock_key(&key).await;
+                let prev = match delete_entry(shared, key).await {
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                    prev => prev,
+                };
+                prevs.push(prev);
+            }
+
+            respond(resp, Ok(prevs));
+        }
+        Action::DelPrefix { prefix, resp } => {
+            let _writing = shared.write_gate.read().await;
+            let deleted = delete_prefix(shared, &prefix).await;
+            respond(resp, deleted)
+        }
+        Action::Keys { resp } => {
+            let keys = list_keys(shared).await;
+            respond(resp, Ok(keys))
+        }
+        Action::Len { resp } => {
+            // keys held both in memory and on disk are only listed once
+            let len = list_keys(shared).await.len();
+            respond(resp, Ok(len))
+        }
+        Action::ScanPrefix { prefix, resp } => {
+            let pairs = scan_prefix(shared, &prefix).await;
+            respond(resp, pairs)
+        }
+        Action::Clear { resp } => {
+            // keeps sets out, so that none lands on disk but not in memory or back
+            let _clearing = shared.write_gate.write().await;
+            let file_io = shared.backend.clear().await;
+            if file_io.is_ok() {
+                shared.clear_cache();
+                shared.dirty.lock().unwrap().clear();
+                shared.metadata.clear();
+                shared.publish(CacheEvent::Cleared);
+            }
+            respond(resp, file_io)
+        }
+        Action::Drain { resp } => {
+            // keeps sets out, so that nothing set after the entries are read is cleared
+            let _draining = shared.write_gate.write().await;
+            let drained = drain(shared).await;
+            respond(resp, drained)
+        }
+        Action::Flush { resp } => {
+            // waits for the writes in progress, and keeps new ones out until done
+            let _flushing = shared.write_gate.write().await;
+            let file_io = match write_dirty(shared).await {
+                Ok(()) => shared.backend.sync().await,
+                Err(e) => Err(e),
+            };
+            respond(resp, file_io)
+        }
+        Action::Compact { resp } => {
+            // a write in progress has a temporary file that is not stray
+            let _compacting = shared.write_gate.write().await;
+            let report = shared.backend.compact().await;
+            respond(resp, report)
+        }
+        Action::Snapshot { out_path, resp } => {
+            let _snapshotting = shared.write_gate.write().await;
+            let file_io = snapshot(shared, &out_path).await;
+            respond(resp, file_io)
+        }
+        Action::Restore { in_path, resp } => {
+            let _restoring = shared.write_gate.write().await;
+            let restored = restore(shared, &in_path).await;
+            respond(resp, restored)
+        }
+        Action::Stats { resp } => {
+            let counters = &shared.counters;
+            let operations = counters.operations.load(Ordering::Relaxed);
+            let stats = CacheStats {
+                hits: counters.hits.load(Ordering::Relaxed),
+                misses: counters.misses.load(Ordering::Relaxed),
+                disk_fallbacks: counters.disk_fallbacks.load(Ordering::Relaxed),
+                evictions: counters.evictions.load(Ordering::Relaxed),
+                deletes: counters.deletes.load(Ordering::Relaxed),
+                entry_count: db.len(),
+                cached_bytes: shared.cached_bytes.load(Ordering::Relaxed),
+                operations,
+                min_latency: Duration::from_nanos(counters.min_nanos.load(Ordering::Relaxed)),
+                max_latency: Duration::from_nanos(counters.max_nanos.load(Ordering::Relaxed)),
+                avg_latency: Duration::from_nanos(
+                    counters.busy_nanos.load(Ordering::Relaxed) / operations.max(1),
+                ),
+            };
+            respond(resp, Ok(stats))
+        }
+        Action::Ping { resp } => respond(resp, Ok(())),
+        Action::SetCoalesced { key, sets } => {
+            let _writing = shared.write_gate.read().await;
+            let _key = shared.lock_key(&key).await;
+            set_coalesced(shared, key, sets).await;
+        }
+    };
+}
+
+/// Fails the actions of a read-only store that would change its entries, handing
+/// back the others to be carried out
+fn refuse_write(action: Action) -> Option<Action> {
+    match action {
+        Action::Set { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::SetDetailed { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::SetMany { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::Incr { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::Cas { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::Modify { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::SetIfAbsent { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::SetIfPresent { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::Touch { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::Del { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::DelMany { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::DelPrefix { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::Clear { resp } => respond(resp, Err(ReadOnly::error())),
+        Action::Drain { resp } => respond(resp, Err(ReadOnly::error())),
+        Action::Restore { resp, .. } => respond(resp, Err(ReadOnly::error())),
+        Action::Compact { resp } => respond(resp, Err(ReadOnly::error())),
+        Action::SetCoalesced { sets, .. } => {
+            for set in sets {
+                respond(set.resp, Err(ReadOnly::error()));
+            }
+        }
+        action => return Some(action),
+    }
+
+    None
+}
+
+/// Looks `key` up in memory, falling back to its file on disk.
+///
+/// Expired entries are evicted lazily, on first read after expiry. Deleting the file
+/// of one writes, so callers hold the key lock exclusively
+async fn load_entry(shared: &Shared, key: &str) -> io::Result<Option<Entry>> {
+    match lookup_entry(shared, key).await? {
+        Lookup::Live(entry) => Ok(Some(entry)),
+        Lookup::Expired => {
+            if !shared.options.read_only {
+                let _ = shared.backend.remove(key).await;
+            }
+            Ok(None)
+        }
+        Lookup::Missing => Ok(None),
+    }
+}
+
+/// Reads `key` like [`load_entry`], holding `reading`, the key's shared lock, for
+/// the read alone. Deleting the file of an expired entry, or sliding the expiry of
+/// one that expires once idle when `slide` is set, writes: `reading` is traded for
+/// the exclusive lock first, and the entry looked at again, as a write may have
+/// landed in between
+async fn read_entry(
+    shared: &Shared,
+    key: &str,
+    reading: RwLockReadGuard<'_, ()>,
+    slide: bool,
+) -> io::Result<Option<Entry>> {
+    let read_only = shared.options.read_only;
+    let entry = match lookup_entry(shared, key).await? {
+        Lookup::Live(entry) => entry,
+        Lookup::Expired if !read_only => {
+            drop(reading);
+            let _key = shared.lock_key(key).await;
+            if current_entry(shared, key).await?.is_none() {
+                let _ = shared.backend.remove(key).await;
+            }
+            return Ok(None);
+        }
+        Lookup::Expired | Lookup::Missing => return Ok(None),
+    };
+    if !slide || entry.idle_ttl.is_none() || read_only {
+        return Ok(Some(entry));
+    }
+
+    drop(reading);
+    let _key = shared.lock_key(key).await;
+    if let Some(current) = current_entry(shared, key).await? {
+        slide_expiry(shared, key, current).await?;
+    }
+    Ok(Some(entry))
+}
+
+/// What [`lookup_entry`] found at a key
+enum Lookup {
+    Live(Entry),
+    /// Dropped from memory on expiring, its file left for the caller to delete
+    Expired,
+    Missing,
+}
+
+/// Does the reading part of [`load_entry`], counting the hit or miss
+async fn lookup_entry(shared: &Shared, key: &str) -> io::Result<Lookup> {
+    let counters = &shared.counters;
+    if shared.uncache_if_expired(key) {
+        Counters::record(&counters.misses);
+        shared.metadata.remove(key);
+        return Ok(Lookup::Expired);
+    }
+
+    if let Some(entry) = shared.cached(key) {
+        Counters::record(&counters.hits);
+        return Ok(Lookup::Live(entry));
+    }
+
+    // evicted from memory before it was written back
+    if let Some(entry) = shared.unwritten(key) {
+        if entry.is_expired() {
+            Counters::record(&counters.misses);
+            shared.metadata.remove(key);
+            return Ok(Lookup::Missing);
+        }
+        Counters::record(&counters.hits);
+        return Ok(Lookup::Live(entry));
+    }
+
+    match shared.backend.load(key).await? {
+        Some(e) if e.is_expired() => {
+            Counters::record(&counters.misses);
+            shared.metadata.remove(key);
+            Ok(Lookup::Expired)
+        }
+        Some(e) => {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(key, "served from disk");
+            Counters::record(&counters.disk_fallbacks);
+            Ok(Lookup::Live(e))
+        }
+        None => {
+            Counters::record(&counters.misses);
+            Ok(Lookup::Missing)
+        }
+    }
+}
+
+/// Lists the keys held in memory or on disk, sorted and without duplicates
+async fn list_keys(shared: &Shared) -> Vec<String> {
+    let mut keys: HashSet<String> = shared
+        .db
+        .iter()
+        .filter(|e| !e.is_expired())
+        .map(|e| e.key().clone())
+        .collect();
+    keys.extend(shared.backend.keys().await);
+    keys.extend(
+        shared
+            .dirty
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, e)| !e.is_expired())
+            .map(|(k, _)| k.clone()),
+    );
+
+    let mut keys: Vec<String> = keys.into_iter().collect();
+    keys.sort();
+    keys
+}
+
+/// Reads every live entry whose key starts with `prefix`, sorted by key
+async fn scan_prefix(shared: &Shared, prefix: &str) -> io::Result<KeyValuePairs> {
+    let mut pairs = Vec::new();
+    for key in list_keys(shared).await {
+        if !key.starts_with(prefix) {
+            continue;
+        }
+        // the entry may have expired or been deleted since the keys were listed
+        let reading = shared.read_key(&key).await;
+        if let Some(entry) = read_entry(shared, &key, reading, false).await? {
+            pairs.push((key, entry.value));
+        }
+    }
+
+    Ok(pairs)
+}
+
+/// Reads every live entry, in memory or on disk, then clears the store. Returns
+/// the entries sorted by key. Callers hold `write_gate` exclusively
+async fn drain(shared: &Shared) -> io::Result<KeyValuePairs> {
+    let mut pairs = Vec::new();
+    for key in list_keys(shared).await {
+        if let Some(value) = peek_entry(shared, &key).await? {
+            pairs.push((key, value));
+        }
+    }
+
+    shared.backend.clear().await?;
+    shared.clear_cache();
+    shared.dirty.lock().unwrap().clear();
+    shared.metadata.clear();
+    shared.publish(CacheEvent::Cleared);
+    Ok(pairs)
+}
+
+/// Deletes every key that starts with `prefix`, returning how many were deleted
+async fn delete_prefix(shared: &Shared, prefix: &str) -> io::Result<usize> {
+    let mut deleted = 0;
+    for key in list_keys(shared).await {
+        if !key.starts_with(prefix) {
+            continue;
+        }
+
+        let _key = shared.lock_key(&key).await;
+        let file_io = remove_entry_file(shared, &key).await;
+        match file_io {
+            Ok(()) => {
+                deleted += 1;
+                shared.uncache(&key);
+                shared.metadata.remove(&key);
+                Counters::record(&shared.counters.deletes);
+                shared.publish(CacheEvent::Deleted { key });
+            }
+            // another action deleted it since the keys were listed
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                shared.uncache(&key);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if deleted > 0 {
+        shared.publish(CacheEvent::PrefixDeleted {
+            prefix: prefix.to_string(),
+            count: deleted,
+        });
+    }
+    Ok(deleted)
+}
+
+/// Writes every live entry on disk to a single archive at `out_path`
+async fn snapshot(shared: &Shared, out_path: &str) -> io::Result<()> {
+    write_dirty(shared).await?;
+    let mut entries = Vec::new();
+    // every cached entry is also persisted, so the backend alone makes up the store
+    for key in shared.backend.keys().await {
+        match shared.backend.load(&key).await? {
+            Some(entry) if !entry.is_expired() => entries.push((key, entry)),
+            _ => {}
+        }
+    }
+
+    crate::fs::write_snapshot(out_path, &entries).await
+}
+
+/// Sets every live entry of the archive at `in_path`, keeping their expiry.
+/// Keys missing from the archive are left as they are. Returns how many entries were set
+async fn restore(shared: &Shared, in_path: &str) -> io::Result<usize> {
+    let mut restored = 0;
+    for (key, entry) in crate::fs::read_snapshot(in_path).await? {
+        if !entry.is_expired() {
+            let _key = shared.lock_key(&key).await;
+            set_entry(shared, key.clone(), entry).await?;
+            restored += 1;
+        }
+    }
+
+    Ok(restored)
+}
+
+/// Gets the value at `key`, calling the loader, if any, when the store has none
+async fn get_entry(shared: &Shared, key: &str) -> io::Result<Option<Arc<[u8]>>> {
+    let loader = match &shared.options.loader {
+        Some(loader) => loader,
+        None => {
+            let reading = shared.read_key(key).await;
+            let entry = match read_entry(shared, key, reading, true).await? {
+                Some(entry) => entry,
+                None => return Ok(None),
+            };
+            shared.record_access(key);
+            return Ok(Some(entry.value));
+        }
+    };
+
+    // a loaded value is set, so like any write it gets past the gate before the key lock
+    let _writing = shared.write_gate.read().await;
+    // held while loading, so concurrent gets of the key call the loader once
+    let _key = shared.lock_key(key).await;
+    if let Some(entry) = load_entry(shared, key).await? {
+        shared.record_access(key);
+        let value = Arc::clone(&entry.value);
+        slide_expiry(shared, key, entry).await?;
+        return Ok(Some(value));
+    }
+
+    let value: Arc<[u8]> = match (loader.0)(key).await? {
+        Some(value) => value.into_bytes().into(),
+        None => return Ok(None),
+    };
+    let entry = Entry {
+        value: value.clone(),
+        expires_at: None,
+        idle_ttl: None,
+    };
+    // a read-only store hands the value back without keeping it
+    if !shared.options.read_only {
+        set_entry(shared, key.to_string(), entry).await?;
+        shared.record_access(key);
+    }
+    Ok(Some(value))
+}
+
+/// Reads `key` from memory alone, taking a key held only on disk as missing. It
+/// waits on neither the disk nor the key lock, which a write may hold for a while
+fn cached_entry(shared: &Shared, key: &str) -> Option<Arc<[u8]>> {
+    let counters = &shared.counters;
+    if shared.uncache_if_expired(key) {
+        // the expired file is left for a later read to remove
+        Counters::record(&counters.misses);
+        shared.metadata.remove(key);
+        return None;
+    }
+
+    let entry = shared
+        .cached(key)
+        .or_else(|| shared.unwritten(key).filter(|e| !e.is_expired()))?;
+    Counters::record(&counters.hits);
+    shared.record_access(key);
+    Some(entry.value)
+}
+
+/// Brings the live entry of `key` back into memory if it is only on disk. Like a
+/// peek, it counts as no lookup, and a missing or expired key is left as it is
+async fn prefetch_entry(shared: &Shared, key: String) -> io::Result<()> {
+    if shared.db.contains_key(&key) {
+        return Ok(());
+    }
+
+    let entry = match shared.unwritten(&key) {
+        Some(entry) => Some(entry),
+        None => shared.backend.load(&key).await?,
+    };
+    if let Some(entry) = entry {
+        if !entry.is_expired() && shared.fits_in_memory(&entry) {
+            shared.cache(key, entry);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the live value of `key` like `load_entry`, but leaves the recency of the
+/// keys, the counters and any expired entry alone, and never calls the loader
+async fn peek_entry(shared: &Shared, key: &str) -> io::Result<Option<Arc<[u8]>>> {
+    Ok(current_entry(shared, key).await?.map(|e| e.value))
+}
+
+/// Finds the live entry of `key`, in memory or else on disk, leaving the counters,
+/// the eviction policy and any expired entry alone
+async fn current_entry(shared: &Shared, key: &str) -> io::Result<Option<Entry>> {
+    let entry = match shared.db.get(key).map(|e| e.clone()) {
+        Some(entry) => Some(entry),
+        None => match shared.unwritten(key) {
+            Some(entry) => Some(entry),
+            None => shared.backend.load(key).await?,
+        },
+    };
+
+    Ok(entry.filter(|e| !e.is_expired()))
+}
+
+/// Checks whether `key` holds an unexpired value, in memory or else on disk,
+/// leaving the counters and any expired entry alone
+async fn entry_exists(shared: &Shared, key: &str) -> io::Result<bool> {
+    let cached = shared
+        .db
+        .get(key)
+        .map(|e| !e.is_expired())
+        .or_else(|| shared.unwritten(key).map(|e| !e.is_expired()));
+    match cached {
+        Some(v) => Ok(v),
+        None => shared.backend.exists(key).await,
+    }
+}
+
+async fn get_entries(shared: &Shared, keys: Vec<String>) -> io::Result<Values> {
+    let mut values = Vec::with_capacity(keys.len());
+    for key in keys {
+        values.push(get_entry(shared, &key).await?);
+    }
+
+    Ok(values)
+}
+
+/// Persists `entry` and caches it, returning the live value it replaced.
+///
+/// This and the other helpers working on a single key expect its lock to be held
+async fn set_entry(
+    shared: &Shared,
+    key: String,
+    mut entry: Entry,
+) -> io::Result<Option<Arc<[u8]>>> {
+    check_size(shared, &entry)?;
+    if let (None, None, Some(idle_ttl)) =
+        (entry.expires_at, entry.idle_ttl, shared.options.idle_ttl)
+    {
+        entry.expires_at = Some(SystemTime::now() + idle_ttl);
+        entry.idle_ttl = Some(idle_ttl);
+    }
+
+    persist(shared, &key, &entry).await?;
+    let prev = shared.cache(key.clone(), entry);
+    shared.record_set(&key, prev.as_ref().is_some_and(Entry::is_expired));
+    shared.publish(CacheEvent::Set { key });
+    Ok(prev.and_then(Entry::into_live_value))
+}
+
+/// Sets `key` like `set_entry`, but reads the value it replaces from disk too
+/// when memory does not hold it, so as to tell a new key from an updated one
+async fn set_entry_detailed(
+    shared: &Shared,
+    key: String,
+    value: Vec<u8>,
+) -> io::Result<SetOutcome> {
+    let previous = peek_entry(shared, &key).await?;
+    let entry = Entry {
+        value: value.into(),
+        expires_at: None,
+        idle_ttl: None,
+    };
+    set_entry(shared, key, entry).await?;
+
+    Ok(SetOutcome {
+        created: previous.is_none(),
+        previous,
+    })
+}
+
+/// Fails with `InvalidInput` if the value of `entry` is over `max_value_bytes`,
+/// or over `max_bytes` and so too large to be held in memory at all
+fn check_size(shared: &Shared, entry: &Entry) -> io::Result<()> {
+    let limits = [shared.options.max_value_bytes, shared.options.max_bytes];
+    let size = entry.value.len();
+    for limit in limits.into_iter().flatten() {
+        if size > limit {
+            let too_large = ValueTooLarge { size, limit };
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, too_large));
+        }
+    }
+
+    Ok(())
+}
+
+/// Carries out sets made one right after the other to `key` as if each was made in
+/// turn, but writes only the value of the last. Each set is answered with the value
+/// it replaced: the one of the set before it, or the one the store held for the
+/// first. Sets refused for their size fail alone, as they would have on their own
+async fn set_coalesced(shared: &Shared, key: String, sets: Vec<QueuedSet>) {
+    let mut accepted = Vec::with_capacity(sets.len());
+    for set in sets {
+        let entry = Entry {
+            value: set.value.into(),
+            expires_at: set.expires_at,
+            idle_ttl: set.idle_ttl,
+        };
+        match check_size(shared, &entry) {
+            Ok(()) => accepted.push((set.resp, entry)),
+            Err(e) => respond(set.resp, Err(e)),
+        }
+    }
+    let last = match accepted.last() {
+        Some((_, entry)) => entry.clone(),
+        None => return,
+    };
+
+    match set_entry(shared, key.clone(), last).await {
+        Ok(mut prev) => {
+            // set_entry published the last set
+            for _ in 1..accepted.len() {
+                shared.publish(CacheEvent::Set { key: key.clone() });
+            }
+            for (resp, entry) in accepted {
+                respond(resp, Ok(prev));
+                prev = entry.into_live_value();
+            }
+        }
+        // none of the values were written, so every set failed
+        Err(e) => {
+            for (resp, _) in accepted {
+                respond(resp, Err(crate::error::duplicate(&e)));
+            }
+        }
+    }
+}
+
+/// Writes `entry` to disk, or leaves it to the write-back task in write-back mode
+async fn persist(shared: &Shared, key: &str, entry: &Entry) -> io::Result<()> {
+    if shared.options.write_back.is_some() {
+        let mut dirty = shared.dirty.lock().unwrap();
+        dirty.insert(key.to_string(), entry.clone());
+        return Ok(());
+    }
+
+    shared.backend.save(key, entry).await
+}
+
+/// Restarts the countdown of an entry that expires once idle, just read at `key`.
+/// Callers hold the key lock exclusively, since the entry is written again. A read
+/// made while a flush, snapshot or drain holds the store leaves it as it was,
+/// rather than wait or write in their midst, as does any read of a read-only store
+async fn slide_expiry(shared: &Shared, key: &str, entry: Entry) -> io::Result<()> {
+    let idle_ttl = match entry.idle_ttl {
+        Some(idle_ttl) if !shared.options.read_only => idle_ttl,
+        _ => return Ok(()),
+    };
+    let _writing = match shared.write_gate.try_read() {
+        Ok(writing) => writing,
+        Err(_) => return Ok(()),
+    };
+
+    let expires_at = Some(SystemTime::now() + idle_ttl);
+    persist(
+        shared,
+        key,
+        &Entry {
+            expires_at,
+            ..entry
+        },
+    )
+    .await?;
+    if let Some(mut cached) = shared.db.get_mut(key) {
+        cached.expires_at = expires_at;
+    }
+    Ok(())
+}
+
+/// Deletes `key` from disk and memory, returning the live value it held in memory
+async fn delete_entry(shared: &Shared, key: String) -> io::Result<Option<Arc<[u8]>>> {
+    remove_entry_file(shared, &key).await?;
+    let value = shared.uncache(&key).and_then(Entry::into_live_value);
+    shared.metadata.remove(&key);
+    Counters::record(&shared.counters.deletes);
+    shared.publish(CacheEvent::Deleted { key });
+    Ok(value)
+}
+
+/// Removes the file of `key`, along with any value of it waiting to be written back.
+/// A key that was only ever set in memory has no file, which is not an error
+async fn remove_entry_file(shared: &Shared, key: &str) -> io::Result<()> {
+    let was_dirty = shared.dirty.lock().unwrap().remove(key).is_some();
+    match shared.backend.remove(key).await {
+        Err(e) if was_dirty && e.kind() == io::ErrorKind::NotFound => Ok(()),
+        file_io => file_io,
+    }
+}
+
+/// Writes every entry waiting to be written back to its file. Callers hold
+/// `write_gate` exclusively, so that no set lands in the middle
+async fn write_dirty(shared: &Shared) -> io::Result<()> {
+    let keys: Vec<String> = shared.dirty.lock().unwrap().keys().cloned().collect();
+    for key in keys {
+        // keeps reads of the key from finding neither the entry nor its file
+        let _key = shared.lock_key(&key).await;
+        let entry = match shared.unwritten(&key) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        shared.backend.save(&key, &entry).await?;
+        shared.dirty.lock().unwrap().remove(&key);
+    }
+
+    Ok(())
+}
+
+/// Adds `delta` to the integer stored at `key`, a missing key counting as 0.
+/// The key keeps its expiry, if any
+async fn increment_entry(shared: &Shared, key: String, delta: i64) -> io::Result<i64> {
+    let (count, expires_at, idle_ttl) = match load_entry(shared, &key).await? {
+        Some(e) => (parse_counter(&e.value)?, e.expires_at, e.idle_ttl),
+        None => (0, None, None),
+    };
+    let total = count.checked_add(delta).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidData, "counter would overflow an i64")
+    })?;
+
+    let entry = Entry {
+        value: total.to_string().into_bytes().into(),
+        expires_at,
+        idle_ttl,
+    };
+    set_entry(shared, key, entry).await?;
+    Ok(total)
+}
+
+/// Replaces the value at `key` with `new` only if the current value equals `expected`,
+/// `None` meaning the key must be absent. Returns whether the swap happened
+async fn compare_and_swap_entry(
+    shared: &Shared,
+    key: String,
+    expected: Option<Vec<u8>>,
+    new: Vec<u8>,
+) -> io::Result<bool> {
+    let current = load_entry(shared, &key).await?.map(|e| e.value);
+    if current.as_deref() != expected.as_deref() {
+        return Ok(false);
+    }
+
+    let entry = Entry {
+        value: new.into(),
+        expires_at: None,
+        idle_ttl: None,
+    };
+    set_entry(shared, key, entry).await?;
+    Ok(true)
+}
+
+/// Replaces the value of `key` with what `modify` makes of it, keeping its expiry,
+/// or deletes the key if `modify` returns `None`. Returns the new value
+async fn modify_entry(
+    shared: &Shared,
+    key: String,
+    modify: Modifier,
+) -> io::Result<Option<Arc<[u8]>>> {
+    let current = load_entry(shared, &key).await?;
+    let existed = current.is_some();
+    let expires_at = current.as_ref().and_then(|e| e.expires_at);
+    let idle_ttl = current.as_ref().and_then(|e| e.idle_ttl);
+
+    match modify(current.map(|e| e.value))? {
+        Some(value) => {
+            let value: Arc<[u8]> = value.into();
+            let entry = Entry {
+                value: value.clone(),
+                expires_at,
+                idle_ttl,
+            };
+            set_entry(shared, key, entry).await?;
+            Ok(Some(value))
+        }
+        None => {
+            if existed {
+                delete_entry(shared, key).await?;
+            }
+            Ok(None)
+        }
+    }
+}
+
+/// Sets `key` to `value` only if a live entry for it exists, or only if none does
+/// when `present` is `false`. Returns whether it was set
+async fn set_entry_if(
+    shared: &Shared,
+    key: String,
+    value: Vec<u8>,
+    present: bool,
+) -> io::Result<bool> {
+    if load_entry(shared, &key).await?.is_some() != present {
+        return Ok(false);
+    }
+
+    let entry = Entry {
+        value: value.into(),
+        expires_at: None,
+        idle_ttl: None,
+    };
+    set_entry(shared, key, entry).await?;
+    Ok(true)
+}
+
+/// Moves the expiry of the live entry at `key` to `expires_at`, keeping its value.
+/// Returns `false` if there is no such entry
+async fn touch_entry(shared: &Shared, key: String, expires_at: SystemTime) -> io::Result<bool> {
+    let value = match load_entry(shared, &key).await? {
+        Some(e) => e.value,
+        None => return Ok(false),
+    };
+
+    let entry = Entry {
+        value,
+        expires_at: Some(expires_at),
+        idle_ttl: None,
+    };
+    set_entry(shared, key, entry).await?;
+    Ok(true)
+}
+
+fn parse_counter(value: &[u8]) -> io::Result<i64> {
+    std::str::from_utf8(value)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "value is not an integer"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+    use tokio::sync::mpsc::Sender;
+
+    const STORE_PATH: &str = "db";
+    const KEYS: [&str; 4] = ["hey", "hi", "yoo-hoo", "bonjour"];
+    const VALUES: [&str; 4] = ["English", "English", "Slang", "French"];
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_and_read_multiple_key_value_pairs() {
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+
+        insert_test_data(&tx, &keys, &values).await;
+        let received_values = get_values_for_keys(&tx, keys).await;
+
+        let expected_values: Vec<io::Result<Option<Vec<u8>>>> = values
+            .into_iter()
+            .map(|v| Ok(Some(v.as_bytes().to_vec())))
+            .collect();
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_and_delete_multiple_key_value_pairs() {
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+        let keys_to_delete = keys[2..].to_vec();
+
+        insert_test_data(&tx, &keys, &values).await;
+        delete_keys(&tx, &keys_to_delete).await;
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        let mut expected_values: Vec<io::Result<Option<Vec<u8>>>> = values[..2]
+            .iter()
+            .map(|v| Ok(Some(v.as_bytes().to_vec())))
+            .collect();
+        for _ in 0..keys_to_delete.len() {
+            expected_values.push(Ok(None));
+        }
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_and_clear() {
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+
+        insert_test_data(&tx, &keys, &values).await;
+        clear_test_data(&tx).await;
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        let expected_values: Vec<io::Result<Option<Vec<u8>>>> =
+            keys.into_iter().map(|_| Ok(None)).collect();
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn persist_to_file() {
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+
+        insert_test_data(&tx, &keys, &values).await;
+        // Close the store
+
+        _store.close().await;
+
+        // Open new store instance
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        let expected_values: Vec<io::Result<Option<Vec<u8>>>> = values
+            .into_iter()
+            .map(|v| Ok(Some(v.as_bytes().to_vec())))
+            .collect();
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn persist_to_file_after_delete() {
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+        let keys_to_delete = keys[2..].to_vec();
+
+        insert_test_data(&tx, &keys, &values).await;
+        delete_keys(&tx, &keys_to_delete).await;
+
+        // Close the store
+
+        _store.close().await;
+
+        // Open new store instance
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        let mut expected_values: Vec<io::Result<Option<Vec<u8>>>> = values[..2]
+            .iter()
+            .map(|v| Ok(Some(v.as_bytes().to_vec())))
+            .collect();
+        for _ in 0..keys_to_delete.len() {
+            expected_values.push(Ok(None));
+        }
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn clear_keeps_the_store_directory() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        insert_test_data(&tx, &KEYS, &VALUES).await;
+        let stray_file = std::path::Path::new(STORE_PATH).join("notes.txt");
+        std::fs::write(&stray_file, "not a value").unwrap();
+        clear_test_data(&tx).await;
+
+        assert!(std::path::Path::new(STORE_PATH).is_dir());
+        assert_eq!(files_in_store(), vec![stray_file.clone()]);
+
+        insert_test_data(&tx, &KEYS[..1], &VALUES[..1]).await;
+        let received_values = get_values_for_keys(&tx, KEYS[..1].to_vec()).await;
+        assert_eq!(
+            received_values[0].as_ref().unwrap(),
+            &Some(VALUES[0].as_bytes().to_vec())
+        );
+        assert!(key_file(KEYS[0]).is_file());
+
+        std::fs::remove_file(stray_file).unwrap();
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn clear_waits_for_the_writes_in_progress() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+        insert_test_data(&tx, &KEYS, &VALUES).await;
+
+        // a set half-done, between writing its file and caching its value
+        let writing = _store.shared.write_gate.read().await;
+        let (resp, mut recv) = oneshot::channel();
+        assert!(tx.send(Action::Clear { resp }).await.is_ok());
+        sleep(Duration::from_millis(50)).await;
+        assert!(recv.try_recv().is_err());
+        assert_eq!(_store.shared.db.len(), KEYS.len());
+
+        drop(writing);
+        recv.await.unwrap().unwrap();
+        assert!(_store.shared.db.is_empty());
+        assert!(files_in_store().is_empty());
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn persist_to_file_after_clear() {
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+
+        insert_test_data(&tx, &keys, &values).await;
+        clear_test_data(&tx).await;
+
+        // Close the store
+
+        _store.close().await;
+
+        // Open new store instance
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        let expected_values: Vec<io::Result<Option<Vec<u8>>>> =
+            keys.into_iter().map(|_| Ok(None)).collect();
+
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected.unwrap());
+        }
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn expired_entries_are_treated_as_missing() {
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let key = KEYS[0].to_string();
+        let (resp, recv) = oneshot::channel();
+        let _ = tx
+            .send(Action::Set {
+                key: key.clone(),
+                value: VALUES[0].as_bytes().to_vec(),
+                expires_at: Some(SystemTime::now() + Duration::from_millis(100)),
+                idle_ttl: None,
+                resp,
+            })
+            .await;
+        let _ = recv.await;
+
+        let received_values = get_values_for_keys(&tx, vec![KEYS[0]]).await;
+        assert_eq!(
+            received_values[0].as_ref().unwrap(),
+            &Some(VALUES[0].as_bytes().to_vec())
+        );
+
+        sleep(Duration::from_millis(200)).await;
+
+        let received_values = get_values_for_keys(&tx, vec![KEYS[0]]).await;
+        assert_eq!(received_values[0].as_ref().unwrap(), &None);
+        assert!(!key_file(&key).exists());
+
+        // a fresh file is only visible if the expired entry left the in-memory map
+        write_key_file(&key, "\nrewritten");
+        let received_values = get_values_for_keys(&tx, vec![KEYS[0]]).await;
+        assert_eq!(
+            received_values[0].as_ref().unwrap(),
+            &Some(b"rewritten".to_vec())
+        );
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn persist_expiry_to_file() {
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let (resp, recv) = oneshot::channel();
+        let _ = tx
+            .send(Action::Set {
+                key: KEYS[0].to_string(),
+                value: VALUES[0].as_bytes().to_vec(),
+                expires_at: Some(SystemTime::now() + Duration::from_millis(100)),
+                idle_ttl: None,
+                resp,
+            })
+            .await;
+        let _ = recv.await;
+
+        // Close the store
+        _store.close().await;
+        sleep(Duration::from_millis(200)).await;
+
+        // Open new store instance
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let received_values = get_values_for_keys(&tx, vec![KEYS[0]]).await;
+        assert_eq!(received_values[0].as_ref().unwrap(), &None);
+        assert!(!key_file(KEYS[0]).exists());
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn keys_unsafe_as_file_names_stay_in_store() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let long_key = "k/".repeat(300);
+        let keys = vec!["foo/bar", "..", "../escaped", "", &long_key];
+        let values = VALUES.repeat(2)[..keys.len()].to_vec();
+
+        insert_test_data(&tx, &keys, &values).await;
+        assert!(!std::path::Path::new(&format!("{}/foo", STORE_PATH)).exists());
+        assert!(!std::path::Path::new("escaped").exists());
+
+        // Close the store
+        _store.close().await;
+
+        // Open new store instance
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        for (got, expected) in received_values.into_iter().zip(&values) {
+            assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+        }
+
+        delete_keys(&tx, &keys).await;
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        for got in received_values {
+            assert_eq!(got.unwrap(), None);
+        }
+        assert!(files_in_store().is_empty());
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn preload_reads_existing_files_into_memory() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        std::fs::create_dir_all(STORE_PATH).unwrap();
+        for (k, v) in KEYS.iter().zip(VALUES) {
+            write_key_file(k, &format!("\n{}", v));
+        }
+        write_key_file("stale", "1\nexpired");
+
+        let (tx, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            preload: true,
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+        // values must now be served from memory, not from the files
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+
+        let received_values = get_values_for_keys(&tx, KEYS.to_vec()).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+        }
+        let received_values = get_values_for_keys(&tx, vec!["stale"]).await;
+        assert_eq!(received_values[0].as_ref().unwrap(), &None);
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_many_reports_previous_value_per_key() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys = KEYS.to_vec();
+        let values = VALUES.to_vec();
+        insert_test_data(&tx, &keys[..2], &values[..2]).await;
+
+        let entries = keys
+            .iter()
+            .map(|k| (k.to_string(), k.as_bytes().to_vec()))
+            .collect();
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::SetMany { entries, resp }).await;
+        let prevs = recv.await.unwrap().unwrap();
+
+        let expected_prevs = vec![
+            Some(values[0].as_bytes().to_vec()),
+            Some(values[1].as_bytes().to_vec()),
+            None,
+            None,
+        ];
+        for (got, expected) in prevs.into_iter().zip(expected_prevs) {
+            assert_eq!(got.unwrap().as_deref(), expected.as_deref());
+        }
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        for (got, k) in received_values.into_iter().zip(keys) {
+            assert_eq!(got.unwrap(), Some(k.as_bytes().to_vec()));
+        }
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn delete_many_reports_previous_value_per_key() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        insert_test_data(&tx, &KEYS[..2], &VALUES[..2]).await;
+        // a directory where the file of a key should be cannot be removed as one
+        std::fs::create_dir_all(key_file("broken")).unwrap();
+
+        let keys = vec![KEYS[0], "broken", KEYS[2], KEYS[1], KEYS[0]]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::DelMany { keys, resp }).await;
+        let prevs = recv.await.unwrap().unwrap();
+
+        assert_eq!(prevs.len(), 5);
+        assert_eq!(
+            prevs[0].as_ref().unwrap().as_deref(),
+            Some(VALUES[0].as_bytes())
+        );
+        assert!(prevs[1].is_err());
+        assert_eq!(prevs[2].as_ref().unwrap(), &None);
+        assert_eq!(
+            prevs[3].as_ref().unwrap().as_deref(),
+            Some(VALUES[1].as_bytes())
+        );
+        assert_eq!(prevs[4].as_ref().unwrap(), &None);
+
+        let received_values = get_values_for_keys(&tx, KEYS[..2].to_vec()).await;
+        for got in received_values {
+            assert_eq!(got.unwrap(), None);
+        }
+
+        std::fs::remove_dir_all(key_file("broken")).unwrap();
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn get_many_preserves_key_order() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        // "hey" and "hi" are cached, "yoo-hoo" only exists on disk, "bonjour" is missing
+        insert_test_data(&tx, &KEYS[..2], &VALUES[..2]).await;
+        write_key_file(KEYS[2], &format!("\n{}", VALUES[2]));
+
+        let keys = vec![KEYS[3], KEYS[2], KEYS[0], KEYS[1]]
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::GetMany { keys, resp }).await;
+        let received_values = recv.await.unwrap().unwrap();
+
+        let expected_values = vec![
+            None,
+            Some(VALUES[2].as_bytes()),
+            Some(VALUES[0].as_bytes()),
+            Some(VALUES[1].as_bytes()),
+        ];
+        let received_values: Vec<Option<&[u8]>> =
+            received_values.iter().map(Option::as_deref).collect();
+        assert_eq!(received_values, expected_values);
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn keys_lists_memory_and_disk_keys_once() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        insert_test_data(&tx, &["foo/bar", KEYS[0], KEYS[1]], &VALUES[..3]).await;
+        write_key_file(KEYS[2], &format!("\n{}", VALUES[2]));
+
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::Keys { resp }).await;
+        let keys = recv.await.unwrap().unwrap();
+
+        assert_eq!(keys, vec!["foo/bar", KEYS[0], KEYS[1], KEYS[2]]);
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn len_follows_sets_deletes_and_clear() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+        assert_eq!(len(&tx).await, 0);
+
+        // set keys are both in memory and on disk
+        insert_test_data(&tx, &KEYS[..3], &VALUES[..3]).await;
+        write_key_file(KEYS[3], &format!("\n{}", VALUES[3]));
+        assert_eq!(len(&tx).await, 4);
+
+        insert_test_data(&tx, &KEYS[..1], &VALUES[1..2]).await;
+        assert_eq!(len(&tx).await, 4);
+
+        delete_keys(&tx, &vec![KEYS[0], KEYS[3]]).await;
+        assert_eq!(len(&tx).await, 2);
+
+        clear_test_data(&tx).await;
+        assert_eq!(len(&tx).await, 0);
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn exists_checks_memory_and_disk() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        insert_test_data(&tx, &KEYS[..1], &VALUES[..1]).await;
+        write_key_file(KEYS[1], &format!("\n{}", VALUES[1]));
+        write_key_file(KEYS[2], "1\nexpired");
+
+        let mut exists = Vec::with_capacity(KEYS.len());
+        for k in KEYS {
+            let (resp, recv) = oneshot::channel();
+            let key = k.to_string();
+            let _ = tx.send(Action::Exists { key, resp }).await;
+            exists.push(recv.await.unwrap().unwrap());
+        }
+
+        assert_eq!(exists, vec![true, true, false, false]);
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn exists_many_answers_in_the_order_of_the_keys() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        insert_test_data(&tx, &KEYS[..1], &VALUES[..1]).await;
+        write_key_file(KEYS[1], &format!("\n{}", VALUES[1]));
+        write_key_file(KEYS[2], "1\nexpired");
+
+        let keys = [KEYS[2], "absent", KEYS[0], KEYS[1], KEYS[0], KEYS[3]];
+        let exists = exists_many(&tx, keys.iter().map(|k| k.to_string()).collect()).await;
+
+        assert_eq!(exists, vec![false, false, true, true, true, false]);
+        assert!(exists_many(&tx, Vec::new()).await.is_empty());
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn dropping_sender_finishes_tasks() {
+        let (tx, rv) = mpsc::channel(1);
+        let mut _store = Store::new(rv, 2, STORE_PATH);
+
+        drop(tx);
+
+        for handler in _store.handlers.drain(..) {
+            assert!(handler.await.is_ok());
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn slow_file_io_does_not_block_other_workers() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        insert_test_data(&tx, &KEYS[..1], &VALUES[..1]).await;
+
+        // reading a fifo blocks until something is written to it
+        let fifo_path = key_file("fifo");
+        std::fs::create_dir_all(fifo_path.parent().unwrap()).unwrap();
+        let status = std::process::Command::new("mkfifo")
+            .arg(&fifo_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let slow_tx = tx.clone();
+        let slow_get =
+            tokio::spawn(async move { get_values_for_keys(&slow_tx, vec!["fifo"]).await });
+        sleep(Duration::from_millis(100)).await;
+
+        let fast_get = tokio::time::timeout(
+            Duration::from_secs(1),
+            get_values_for_keys(&tx, vec![KEYS[0]]),
+        )
+        .await
+        .expect("get blocked behind another worker's file IO");
+        assert_eq!(
+            fast_get[0].as_ref().unwrap(),
+            &Some(VALUES[0].as_bytes().to_vec())
+        );
+
+        tokio::task::spawn_blocking(move || std::fs::write(fifo_path, "\nslow"))
+            .await
+            .unwrap()
+            .unwrap();
+        let slow_get = slow_get.await.unwrap();
+        assert_eq!(slow_get[0].as_ref().unwrap(), &Some(b"slow".to_vec()));
+
+        _store.close().await;
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn distinct_keys_are_served_concurrently() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let num_of_handlers = 4;
+        let (tx, rv) = mpsc::channel(num_of_handlers);
+        let _store = Store::new(rv, num_of_handlers, STORE_PATH);
+
+        let keys: Vec<String> = (0..num_of_handlers).map(|i| format!("fifo{}", i)).collect();
+        let stripes: HashSet<usize> = keys.iter().map(|k| _store.shared.key_stripe(k)).collect();
+        assert_eq!(stripes.len(), keys.len());
+
+        // reading a fifo blocks until something is written to it
+        let mut fifo_paths = Vec::new();
+        for key in &keys {
+            let fifo_path = key_file(key);
+            std::fs::create_dir_all(fifo_path.parent().unwrap()).unwrap();
+            let status = std::process::Command::new("mkfifo")
+                .arg(&fifo_path)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            fifo_paths.push(fifo_path);
+        }
+
+        let mut gets = Vec::new();
+        for key in keys.clone() {
+            let tx = tx.clone();
+            gets.push(tokio::spawn(async move {
+                get_values_for_keys(&tx, vec![&key]).await
+            }));
+        }
+        sleep(Duration::from_millis(100)).await;
+
+        // opening a fifo for writing blocks until it is opened for reading, so feeding
+        // them in reverse order only succeeds if every get is already reading its own
+        let feed = tokio::task::spawn_blocking(move || {
+            for (i, fifo_path) in fifo_paths.iter().enumerate().rev() {
+                std::fs::write(fifo_path, format!("\nvalue{}", i)).unwrap();
+            }
+        });
+        tokio::time::timeout(Duration::from_secs(5), feed)
+            .await
+            .expect("gets of distinct keys were not served concurrently")
+            .unwrap();
+
+        for (i, get) in gets.into_iter().enumerate() {
+            let received_values = get.await.unwrap();
+            let expected = format!("value{}", i).into_bytes();
+            assert_eq!(received_values[0].as_ref().unwrap(), &Some(expected));
+        }
+
+        _store.close().await;
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn concurrent_sets_of_one_key_keep_memory_and_disk_in_step() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(16);
+        let _store = Store::new(rv, 8, STORE_PATH);
+
+        let mut tasks = Vec::new();
+        for t in 0..8 {
+            let tx = tx.clone();
+            tasks.push(tokio::spawn(async move {
+                let values: Vec<String> = (0..25).map(|i| format!("task{}-{}", t, i)).collect();
+                for value in &values {
+                    insert_test_data(&tx, &KEYS[..1], &[value]).await;
+                }
+            }));
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let cached = _store.shared.db.get(KEYS[0]).unwrap().value.clone();
+        let root = std::fs::canonicalize(STORE_PATH).unwrap();
+        let persisted = crate::fs::get_from_file(&root, KEYS[0], &StoreOptions::default())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached, persisted.value);
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn concurrent_tasks_on_disjoint_keys() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(16);
+        let _store = Store::new(rv, 4, STORE_PATH);
+
+        let mut tasks = Vec::new();
+        for t in 0..8 {
+            let tx = tx.clone();
+            tasks.push(tokio::spawn(async move {
+                let keys: Vec<String> = (0..50).map(|i| format!("task{}-{}", t, i)).collect();
+                let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+                insert_test_data(&tx, &keys, &keys).await;
+                let received_values = get_values_for_keys(&tx, keys.clone()).await;
+                for (got, k) in received_values.into_iter().zip(&keys) {
+                    assert_eq!(got.unwrap(), Some(k.as_bytes().to_vec()));
+                }
+
+                delete_keys(&tx, &keys[..25].to_vec()).await;
+                let received_values = get_values_for_keys(&tx, keys.clone()).await;
+                for (i, got) in received_values.into_iter().enumerate() {
+                    let expected = (i >= 25).then(|| keys[i].as_bytes().to_vec());
+                    assert_eq!(got.unwrap(), expected);
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn set_leaves_no_temporary_files() {
+        for durability in [Durability::None, Durability::Data, Durability::Full] {
+            let _ = std::fs::remove_dir_all(STORE_PATH);
+            let (tx, rv) = mpsc::channel(1);
+            let options = StoreOptions {
+                durability,
+                ..StoreOptions::default()
+            };
+            let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+            let long_key = "k".repeat(600);
+            let keys = vec![KEYS[0], KEYS[1], &long_key];
+            insert_test_data(&tx, &keys, &VALUES[..3]).await;
+            insert_test_data(&tx, &keys, &VALUES[1..]).await;
+
+            let received_values = get_values_for_keys(&tx, keys).await;
+            for (got, expected) in received_values.into_iter().zip(&VALUES[1..]) {
+                assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+            }
+
+            for path in files_in_store() {
+                assert!(!path.to_string_lossy().contains(".tmp."));
+            }
+
+            _store.close().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn each_durability_level_writes_and_deletes_many_small_values() {
+        let keys: Vec<String> = (0..200).map(|i| format!("key-{i}")).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        let values = vec!["v"; keys.len()];
+
+        for durability in [Durability::None, Durability::Data, Durability::Full] {
+            let _ = std::fs::remove_dir_all(STORE_PATH);
+            let (tx, rv) = mpsc::channel(1);
+            let options = StoreOptions {
+                durability,
+                ..StoreOptions::default()
+            };
+            let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+            insert_test_data(&tx, &keys, &values).await;
+            assert_eq!(files_in_store().len(), keys.len());
+
+            delete_keys(&tx, &keys).await;
+            assert!(files_in_store().is_empty());
+
+            _store.close().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn get_surfaces_io_errors_other_than_missing_file() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        // a directory where the key's file is expected cannot be read as a value
+        std::fs::create_dir_all(key_file(KEYS[0])).unwrap();
+
+        let received_values = get_values_for_keys(&tx, vec![KEYS[0], KEYS[1]]).await;
+        assert!(received_values[0].is_err());
+        assert_eq!(received_values[1].as_ref().unwrap(), &None);
+
+        _store.close().await;
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn increment_rejects_non_numeric_values() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        insert_test_data(&tx, &KEYS[..1], &VALUES[..1]).await;
+
+        let mut totals = Vec::new();
+        for (key, delta) in [(KEYS[1], 5), (KEYS[1], -7), (KEYS[0], 1)] {
+            let (resp, recv) = oneshot::channel();
+            let key = key.to_string();
+            let _ = tx.send(Action::Incr { key, delta, resp }).await;
+            totals.push(recv.await.unwrap());
+        }
+
+        assert_eq!(totals[0].as_ref().unwrap(), &5);
+        assert_eq!(totals[1].as_ref().unwrap(), &-2);
+        assert_eq!(
+            totals[2].as_ref().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let received_values = get_values_for_keys(&tx, KEYS[..2].to_vec()).await;
+        assert_eq!(
+            received_values[0].as_ref().unwrap(),
+            &Some(VALUES[0].as_bytes().to_vec())
+        );
+        assert_eq!(received_values[1].as_ref().unwrap(), &Some(b"-2".to_vec()));
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn close_aborts_tasks() {
+        let (_tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        for handler in &_store.handlers {
+            assert!(!handler.is_finished())
+        }
+
+        // Close the store
+        _store.close().await;
+
+        for handler in &_store.handlers {
+            assert!(handler.is_finished())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn key_files_are_spread_across_shards() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys: Vec<String> = (0..200).map(|i| format!("key{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        insert_test_data(&tx, &keys, &keys).await;
+
+        let files = files_in_store();
+        assert_eq!(files.len(), keys.len());
+        for file in &files {
+            let shard = file.strip_prefix(STORE_PATH).unwrap();
+            assert_eq!(shard.components().count(), 3);
+        }
+
+        let top_level_shards = std::fs::read_dir(STORE_PATH).unwrap().count();
+        assert!(top_level_shards > 50);
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        for (got, k) in received_values.into_iter().zip(&keys) {
+            assert_eq!(got.unwrap(), Some(k.as_bytes().to_vec()));
+        }
+
+        _store.close().await;
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn created_files_and_directories_get_the_modes_asked_for() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        for storage in [Storage::Files, Storage::Log] {
+            let _ = std::fs::remove_dir_all(STORE_PATH);
+            let (tx, rv) = mpsc::channel(1);
+            let options = StoreOptions {
+                storage,
+                dir_mode: Some(0o700),
+                file_mode: Some(0o600),
+                ..StoreOptions::default()
+            };
+            let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+            insert_test_data(&tx, &KEYS, &VALUES).await;
+
+            assert_eq!(mode(STORE_PATH.as_ref()), 0o700);
+            let files = files_in_store();
+            assert!(!files.is_empty());
+            for file in files {
+                assert_eq!(mode(&file), 0o600, "{:?}", file);
+                assert_eq!(mode(file.parent().unwrap()), 0o700, "{:?}", file);
+            }
+
+            _store.close().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn shard_hashers_lay_out_keys_differently() {
+        let keys: Vec<String> = (0..20).map(|i| format!("key{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+
+        let mut layouts = Vec::new();
+        for shard_hasher in [ShardHasher::Fnv1a, ShardHasher::Murmur3] {
+            let _ = std::fs::remove_dir_all(STORE_PATH);
+            let (tx, rv) = mpsc::channel(1);
+            let options = StoreOptions {
+                shard_hasher,
+                ..StoreOptions::default()
+            };
+            let _store = Store::with_options(rv, 2, STORE_PATH, options.clone()).unwrap();
+            insert_test_data(&tx, &keys, &keys).await;
+
+            let mut files = files_in_store();
+            files.sort();
+            let mut expected: Vec<_> = keys
+                .iter()
+                .map(|k| crate::fs::key_to_path(STORE_PATH, k, &options))
+                .collect();
+            expected.sort();
+            assert_eq!(files, expected);
+
+            let received_values = get_values_for_keys(&tx, keys.clone()).await;
+            for (got, k) in received_values.into_iter().zip(&keys) {
+                assert_eq!(got.unwrap(), Some(k.as_bytes().to_vec()));
+            }
+
+            layouts.push(files);
+            _store.close().await;
+        }
+
+        assert_ne!(layouts[0], layouts[1]);
+        // MurmurHash3 of "hello" is 0x248bfa47
+        let options = StoreOptions {
+            shard_hasher: ShardHasher::Murmur3,
+            ..StoreOptions::default()
+        };
+        let path = crate::fs::key_to_path(STORE_PATH, "hello", &options);
+        assert_eq!(path, std::path::Path::new(STORE_PATH).join("24/8b/hello"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn keys_get_file_names_that_windows_accepts() {
+        let keys = vec!["nul", "CON", "com1", "Lpt9", "aux.txt", "Key", "key", "KEY"];
+        for file_extension in [None, Some("val".to_string())] {
+            let _ = std::fs::remove_dir_all(STORE_PATH);
+            let options = StoreOptions {
+                file_extension,
+                ..StoreOptions::default()
+            };
+            let (tx, rv) = mpsc::channel(1);
+            let _store = Store::with_options(rv, 2, STORE_PATH, options.clone()).unwrap();
+            insert_test_data(&tx, &keys, &keys).await;
+
+            // on Windows, a device name or a name differing only in case would
+            // have lost values here
+            let received_values = get_values_for_keys(&tx, keys.clone()).await;
+            for (got, k) in received_values.into_iter().zip(&keys) {
+                assert_eq!(got.unwrap(), Some(k.as_bytes().to_vec()));
+            }
+            let (resp, recv) = oneshot::channel();
+            let _ = tx.send(Action::Keys { resp }).await;
+            assert_eq!(recv.await.unwrap().unwrap().len(), keys.len());
+
+            let mut names = std::collections::HashSet::new();
+            for key in &keys {
+                let path = crate::fs::key_to_path(STORE_PATH, key, &options);
+                let name = path.file_name().unwrap().to_str().unwrap();
+                let stem = name.split('.').next().unwrap().to_lowercase();
+                assert!(!["con", "prn", "aux", "nul", "com1", "lpt9"].contains(&&stem[..]));
+                assert!(names.insert(name.to_lowercase()), "{}", name);
+            }
+            assert!(key_file("nul").ends_with("%6Eul"));
+
+            clear_test_data(&tx).await;
+            _store.close().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn file_extension_keeps_stray_files_out_of_the_keys() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let options = StoreOptions {
+            file_extension: Some("val".to_string()),
+            ..StoreOptions::default()
+        };
+        let long_key = "k".repeat(300);
+        let keys = vec![KEYS[0], KEYS[1], "", &long_key];
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::with_options(rv, 2, STORE_PATH, options.clone()).unwrap();
+        insert_test_data(&tx, &keys, &keys).await;
+
+        for key in &keys {
+            let path = crate::fs::key_to_path(STORE_PATH, key, &options);
+            assert_eq!(path.extension().unwrap(), "val");
+            assert!(path.is_file());
+        }
+        // neither a bare key name nor another extension is taken for a value file
+        let shard = key_file(KEYS[2]).parent().unwrap().to_path_buf();
+        std::fs::create_dir_all(&shard).unwrap();
+        std::fs::write(shard.join(KEYS[2]), "\nSlang").unwrap();
+        std::fs::write(shard.join("notes.txt"), "not a value").unwrap();
+        _store.close().await;
+
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::Keys { resp }).await;
+        let mut listed = recv.await.unwrap().unwrap();
+        listed.sort();
+        let mut expected: Vec<String> = keys.iter().map(|k| k.to_string()).collect();
+        expected.sort();
+        assert_eq!(listed, expected);
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        for (got, k) in received_values.into_iter().zip(&keys) {
+            assert_eq!(got.unwrap(), Some(k.as_bytes().to_vec()));
+        }
+        _store.close().await;
+
+        let (_, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            file_extension: Some("v/al".to_string()),
+            ..StoreOptions::default()
+        };
+        let result = Store::with_options(rv, 2, STORE_PATH, options);
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn least_recently_used_entries_fall_back_to_disk() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            max_entries: Some(2),
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+        insert_test_data(&tx, &KEYS[..2], &VALUES[..2]).await;
+        // reading "hey" makes "hi" the least recently used
+        get_values_for_keys(&tx, vec![KEYS[0]]).await;
+        insert_test_data(&tx, &KEYS[2..3], &VALUES[2..3]).await;
+
+        let db = &_store.shared.db;
+        let mut cached: Vec<String> = db.iter().map(|e| e.key().clone()).collect();
+        cached.sort();
+        assert_eq!(cached, vec![KEYS[0], KEYS[2]]);
+
+        insert_test_data(&tx, &KEYS[3..], &VALUES[3..]).await;
+        assert_eq!(db.len(), 2);
+        assert!(db.contains_key(KEYS[3]));
+
+        let received_values = get_values_for_keys(&tx, KEYS.to_vec()).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+        }
+        assert!(db.len() <= 2);
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn eviction_policies_pick_different_entries() {
+        let mut kept = Vec::new();
+        for eviction in [Eviction::Lru, Eviction::Lfu, Eviction::Fifo] {
+            let _ = std::fs::remove_dir_all(STORE_PATH);
+            let (tx, rv) = mpsc::channel(1);
+            let options = StoreOptions {
+                max_entries: Some(2),
+                eviction,
+                ..StoreOptions::default()
+            };
+            let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+            // "hey" is hot, then "hi" and "yoo-hoo" are each used once
+            insert_test_data(&tx, &KEYS[..1], &VALUES[..1]).await;
+            for _ in 0..5 {
+                get_values_for_keys(&tx, vec![KEYS[0]]).await;
+            }
+            insert_test_data(&tx, &KEYS[1..3], &VALUES[1..3]).await;
+
+            let mut cached: Vec<String> =
+                _store.shared.db.iter().map(|e| e.key().clone()).collect();
+            cached.sort();
+            kept.push(cached);
+
+            // every value is still on disk
+            let received_values = get_values_for_keys(&tx, KEYS[..3].to_vec()).await;
+            for (got, expected) in received_values.into_iter().zip(VALUES) {
+                assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+            }
+            _store.close().await;
+        }
+
+        assert_eq!(kept[0], vec![KEYS[1], KEYS[2]]);
+        assert_eq!(kept[1], vec![KEYS[0], KEYS[2]]);
+        assert_eq!(kept[2], vec![KEYS[1], KEYS[2]]);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn peek_leaves_recency_and_counters_alone() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            max_entries: Some(2),
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+        insert_test_data(&tx, &KEYS[..2], &VALUES[..2]).await;
+        let before = stats(&tx).await;
+        for (key, expected) in [(KEYS[0], Some(VALUES[0])), (KEYS[3], None)] {
+            let (resp, recv) = oneshot::channel();
+            let key = key.to_string();
+            let _ = tx.send(Action::Peek { key, resp }).await;
+            let expected = expected.map(str::as_bytes);
+            assert_eq!(recv.await.unwrap().unwrap().as_deref(), expected);
+        }
+        let after = stats(&tx).await;
+        assert_eq!(
+            (after.hits, after.misses, after.disk_fallbacks),
+            (before.hits, before.misses, before.disk_fallbacks)
+        );
+
+        // unlike a get, peeking at "hey" left it the least recently used
+        insert_test_data(&tx, &KEYS[2..3], &VALUES[2..3]).await;
+        let db = &_store.shared.db;
+        let mut cached: Vec<String> = db.iter().map(|e| e.key().clone()).collect();
+        cached.sort();
+        assert_eq!(cached, vec![KEYS[1], KEYS[2]]);
+
+        // a key only on disk is peeked at without being brought back into memory
+        let (resp, recv) = oneshot::channel();
+        let key = KEYS[0].to_string();
+        let _ = tx.send(Action::Peek { key, resp }).await;
+        assert_eq!(
+            recv.await.unwrap().unwrap().as_deref(),
+            Some(VALUES[0].as_bytes())
+        );
+        assert!(!db.contains_key(KEYS[0]));
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn metadata_is_kept_for_the_cached_keys_alone() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            max_entries: Some(2),
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+        let keys: Vec<String> = (0..50).map(|i| format!("key{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        insert_test_data(&tx, &keys, &keys).await;
+        assert!(_store.shared.metadata.len() <= 2);
+        // gets of evicted keys are served from disk, leaving them out of memory
+        get_values_for_keys(&tx, keys.clone()).await;
+        assert!(_store.shared.metadata.len() <= 2);
+        for entry in _store.shared.metadata.iter() {
+            assert!(_store.shared.db.contains_key(entry.key()));
+        }
+
+        clear_test_data(&tx).await;
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn eviction_listener_is_told_while_the_entry_is_still_cached() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let store: Arc<std::sync::OnceLock<std::sync::Weak<Shared>>> = Arc::default();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let on_evict = {
+            let (store, seen) = (Arc::clone(&store), Arc::clone(&seen));
+            EvictionListener::new(move |key, _| {
+                let shared = store.get().unwrap().upgrade().unwrap();
+                seen.lock()
+                    .unwrap()
+                    .push((key.to_string(), shared.db.contains_key(key)));
+            })
+        };
+        let options = StoreOptions {
+            max_entries: Some(1),
+            on_evict: Some(on_evict),
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+        let _ = store.set(Arc::downgrade(&_store.shared));
+
+        insert_test_data(&tx, &KEYS[..2], &VALUES[..2]).await;
+        assert_eq!(*seen.lock().unwrap(), [(KEYS[0].to_string(), true)]);
+        assert!(!_store.shared.db.contains_key(KEYS[0]));
+        assert!(_store.shared.db.contains_key(KEYS[1]));
+        assert_eq!(stats(&tx).await.cached_bytes, VALUES[1].len());
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn values_beyond_max_bytes_fall_back_to_disk() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            max_bytes: Some(20),
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+        let values = ["x".repeat(8), "y".repeat(4), "z".repeat(10), "w".repeat(21)];
+        let values: Vec<&str> = values.iter().map(String::as_str).collect();
+
+        insert_test_data(&tx, &KEYS[..2], &values[..2]).await;
+        assert_eq!(stats(&tx).await.cached_bytes, 12);
+
+        // 12 + 10 bytes do not fit, so the oldest value makes room
+        insert_test_data(&tx, &KEYS[2..3], &values[2..3]).await;
+        assert_eq!(stats(&tx).await.cached_bytes, 14);
+        assert!(!_store.shared.db.contains_key(KEYS[0]));
+
+        // overwriting a value only counts its new size
+        insert_test_data(&tx, &KEYS[1..2], &["yyyyyy"]).await;
+        assert_eq!(stats(&tx).await.cached_bytes, 16);
+
+        let (resp, recv) = oneshot::channel();
+        let _ = tx
+            .send(Action::Set {
+                key: KEYS[3].to_string(),
+                value: values[3].as_bytes().to_vec(),
+                expires_at: None,
+                idle_ttl: None,
+                resp,
+            })
+            .await;
+        let err = recv.await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(matches!(
+            crate::CacheError::from(err),
+            crate::CacheError::ValueTooLarge {
+                size: 21,
+                limit: 20
+            }
+        ));
+        assert_eq!(stats(&tx).await.cached_bytes, 16);
+
+        let received_values = get_values_for_keys(&tx, KEYS.to_vec()).await;
+        let expected_values = vec![
+            Some(values[0].as_bytes().to_vec()),
+            Some(b"yyyyyy".to_vec()),
+            Some(values[2].as_bytes().to_vec()),
+            None,
+        ];
+        for (got, expected) in received_values.into_iter().zip(expected_values) {
+            assert_eq!(got.unwrap(), expected);
+        }
+
+        delete_keys(&tx, &KEYS[1..3].to_vec()).await;
+        assert_eq!(stats(&tx).await.cached_bytes, 0);
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn stats_count_lookups_by_where_they_were_served_from() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            max_entries: Some(2),
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+        // "hey" is evicted to make room for "yoo-hoo"
+        insert_test_data(&tx, &KEYS[..3], &VALUES[..3]).await;
+        write_key_file("stale", "1\nexpired");
+        get_values_for_keys(&tx, vec![KEYS[0], KEYS[1], KEYS[3], "stale", KEYS[1]]).await;
+        delete_keys(&tx, &vec![KEYS[2]]).await;
+
+        let stats = stats(&tx).await;
+        let expected = CacheStats {
+            hits: 2,
+            misses: 2,
+            disk_fallbacks: 1,
+            evictions: 1,
+            deletes: 1,
+            entry_count: 1,
+            cached_bytes: VALUES[1].len(),
+            ..stats.clone()
+        };
+        assert_eq!(stats, expected);
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn stats_time_the_operations_served() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        // a single worker has timed each operation before it takes the next
+        let _store = Store::new(rv, 1, STORE_PATH);
+        let fresh = stats(&tx).await;
+        assert_eq!(fresh.operations, 0);
+        assert_eq!(fresh.avg_latency, Duration::ZERO);
+
+        let keys: Vec<String> = (0..50).map(|i| format!("key{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
+        insert_test_data(&tx, &keys, &keys).await;
+        get_values_for_keys(&tx, keys).await;
+
+        // a set held up by a flush taking its time
+        let flushing = _store.shared.write_gate.write().await;
+        let (resp, recv) = oneshot::channel();
+        let action = Action::Set {
+            key: KEYS[0].to_string(),
+            value: VALUES[0].as_bytes().to_vec(),
+            expires_at: None,
+            idle_ttl: None,
+            resp,
+        };
+        assert!(tx.send(action).await.is_ok());
+        sleep(Duration::from_millis(50)).await;
+        drop(flushing);
+        recv.await.unwrap().unwrap();
+
+        let stats = stats(&tx).await;
+        assert_eq!(stats.operations, 1 + 50 + 50 + 1);
+        assert!(Duration::ZERO < stats.min_latency);
+        assert!(stats.min_latency <= stats.avg_latency);
+        assert!(stats.avg_latency <= stats.max_latency);
+        assert!(stats.max_latency >= Duration::from_millis(50));
+        // the slow set alone takes the average past a 102nd of its time
+        assert!(stats.avg_latency >= Duration::from_millis(50) / 102);
+        assert!(stats.avg_latency < Duration::from_millis(50));
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn scan_prefix_matches_whole_decoded_prefix() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys = vec!["user:1:name", "user:12:name", "user:1", "user:2:name"];
+        insert_test_data(&tx, &keys, &VALUES).await;
+        write_key_file("user:1:age", "\n42");
+
+        let mut scans = Vec::new();
+        for prefix in ["user:1:", "user:1", "user:3"] {
+            let (resp, recv) = oneshot::channel();
+            let prefix = prefix.to_string();
+            let _ = tx.send(Action::ScanPrefix { prefix, resp }).await;
+            scans.push(recv.await.unwrap().unwrap());
+        }
+
+        let pair = |k: &str, v: &str| (k.to_string(), Arc::from(v.as_bytes()));
+        assert_eq!(
+            scans[0],
+            vec![pair("user:1:age", "42"), pair("user:1:name", VALUES[0])]
+        );
+        assert_eq!(
+            scans[1],
+            vec![
+                pair("user:1", VALUES[2]),
+                pair("user:12:name", VALUES[1]),
+                pair("user:1:age", "42"),
+                pair("user:1:name", VALUES[0]),
+            ]
+        );
+        assert!(scans[2].is_empty());
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn delete_prefix_leaves_other_keys_alone() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let keys = vec!["user:1:name", "user:12:name", "user:1", "user:2:name"];
+        insert_test_data(&tx, &keys, &VALUES).await;
+        write_key_file("user:1:age", "\n42");
+
+        let mut deleted = Vec::new();
+        for prefix in ["user:1:", "user:3", ""] {
+            let (resp, recv) = oneshot::channel();
+            let prefix = prefix.to_string();
+            let _ = tx.send(Action::DelPrefix { prefix, resp }).await;
+            deleted.push(recv.await.unwrap().unwrap());
+
+            if deleted.len() == 1 {
+                let received_values = get_values_for_keys(&tx, keys.clone()).await;
+                let expected_values = vec![None, Some(VALUES[1]), Some(VALUES[2]), Some(VALUES[3])];
+                for (got, expected) in received_values.into_iter().zip(expected_values) {
+                    assert_eq!(got.unwrap(), expected.map(|v| v.as_bytes().to_vec()));
+                }
+            }
+        }
+
+        assert_eq!(deleted, vec![2, 0, 3]);
+        assert!(_store.shared.db.is_empty());
+        assert!(files_in_store().is_empty());
+
+        _store.close().await;
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn gzip_values_are_smaller_on_disk_and_read_back() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            compression: Compression::Gzip,
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+        let document = "{\"id\": 1, \"name\": \"diskcache\"}, ".repeat(500);
+        insert_test_data(&tx, &KEYS[..1], &[&document]).await;
+        write_key_file(KEYS[1], &format!("\n{}", VALUES[1]));
+
+        let file_len = std::fs::metadata(key_file(KEYS[0])).unwrap().len();
+        assert!(file_len < document.len() as u64 / 10);
+
+        _store.close().await;
+
+        // a store without compression still reads the compressed file
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let received_values = get_values_for_keys(&tx, KEYS[..2].to_vec()).await;
+        assert_eq!(
+            received_values[0].as_ref().unwrap(),
+            &Some(document.into_bytes())
+        );
+        assert_eq!(
+            received_values[1].as_ref().unwrap(),
+            &Some(VALUES[1].as_bytes().to_vec())
+        );
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn snapshot_then_restore_recovers_every_entry() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let snapshot_path = format!("{}.snapshot", STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let long_key = "k/".repeat(300);
+        let keys = vec![KEYS[0], KEYS[1], "", &long_key];
+        insert_test_data(&tx, &keys, &VALUES).await;
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let (resp, recv) = oneshot::channel();
+        let _ = tx
+            .send(Action::Set {
+                key: "binary".to_string(),
+                value: vec![0, 159, 146, 150, b'\n'],
+                expires_at: Some(expires_at),
+                idle_ttl: None,
+                resp,
+            })
+            .await;
+        let _ = recv.await;
+
+        let (resp, recv) = oneshot::channel();
+        let out_path = snapshot_path.clone();
+        let _ = tx.send(Action::Snapshot { out_path, resp }).await;
+        recv.await.unwrap().unwrap();
+        clear_test_data(&tx).await;
+
+        let (resp, recv) = oneshot::channel();
+        let in_path = snapshot_path.clone();
+        let _ = tx.send(Action::Restore { in_path, resp }).await;
+        assert_eq!(recv.await.unwrap().unwrap(), 5);
+
+        let received_values = get_values_for_keys(&tx, keys.clone()).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+        }
+        let entry = load_entry(&_store.shared, "binary").await.unwrap().unwrap();
+        assert_eq!(&entry.value[..], [0, 159, 146, 150, b'\n']);
+        let restored_expiry = entry.expires_at.unwrap();
+        let drift = restored_expiry
+            .duration_since(expires_at)
+            .unwrap_or_else(|e| e.duration());
+        assert!(drift < Duration::from_millis(1));
+
+        _store.close().await;
+        let _ = std::fs::remove_file(snapshot_path);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn snapshot_then_restore_keeps_the_idle_ttl() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let snapshot_path = format!("{}.snapshot", STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let idle_ttl = Duration::from_secs(60);
+        let (resp, recv) = oneshot::channel();
+        let _ = tx
+            .send(Action::Set {
+                key: KEYS[0].to_string(),
+                value: VALUES[0].as_bytes().to_vec(),
+                expires_at: None,
+                idle_ttl: Some(idle_ttl),
+                resp,
+            })
+            .await;
+        recv.await.unwrap().unwrap();
+        insert_test_data(&tx, &KEYS[1..2], &VALUES[1..2]).await;
+
+        let (resp, recv) = oneshot::channel();
+        let out_path = snapshot_path.clone();
+        let _ = tx.send(Action::Snapshot { out_path, resp }).await;
+        recv.await.unwrap().unwrap();
+        clear_test_data(&tx).await;
+
+        let (resp, recv) = oneshot::channel();
+        let in_path = snapshot_path.clone();
+        let _ = tx.send(Action::Restore { in_path, resp }).await;
+        assert_eq!(recv.await.unwrap().unwrap(), 2);
+
+        let idle = load_entry(&_store.shared, KEYS[0]).await.unwrap().unwrap();
+        assert_eq!(idle.idle_ttl, Some(idle_ttl));
+        let kept = load_entry(&_store.shared, KEYS[1]).await.unwrap().unwrap();
+        assert_eq!((kept.expires_at, kept.idle_ttl), (None, None));
+
+        _store.close().await;
+        let _ = std::fs::remove_file(snapshot_path);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn flushed_sets_are_seen_after_restart() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let long_key = "k/".repeat(300);
+        let keys = vec![KEYS[0], KEYS[1], KEYS[2], &long_key];
+        insert_test_data(&tx, &keys, &VALUES).await;
+
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::Flush { resp }).await;
+        recv.await.unwrap().unwrap();
+
+        // Close the store
+        _store.close().await;
+
+        // Open new store instance
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let received_values = get_values_for_keys(&tx, keys).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+        }
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn write_back_defers_files_until_a_tick_or_flush() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            write_back: Some(Duration::from_millis(300)),
+            max_entries: Some(1),
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+        insert_test_data(&tx, &KEYS[..2], &VALUES[..2]).await;
+        // the first key was evicted from memory before it had a file
+        let received_values = get_values_for_keys(&tx, KEYS[..2].to_vec()).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+        }
+        assert!(files_in_store().is_empty());
+
+        sleep(Duration::from_millis(600)).await;
+        assert_eq!(files_in_store().len(), 2);
+        _store.close().await;
+
+        let (tx, rv) = mpsc::channel(1);
+        let options = StoreOptions {
+            write_back: Some(Duration::from_secs(3600)),
+            ..StoreOptions::default()
+        };
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+
+        insert_test_data(&tx, &KEYS[2..3], &VALUES[2..3]).await;
+        assert_eq!(files_in_store().len(), 2);
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::Flush { resp }).await;
+        recv.await.unwrap().unwrap();
+        assert_eq!(files_in_store().len(), 3);
+
+        // close writes back what is left
+        insert_test_data(&tx, &KEYS[3..], &VALUES[3..]).await;
+        assert_eq!(files_in_store().len(), 3);
+        _store.close().await;
+        assert_eq!(files_in_store().len(), 4);
+
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+        let received_values = get_values_for_keys(&tx, KEYS.to_vec()).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+        }
+
+        clear_test_data(&tx).await;
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn log_storage_persists_sets_and_deletes() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let options = StoreOptions {
+            storage: Storage::Log,
+            ..StoreOptions::default()
+        };
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::with_options(rv, 2, STORE_PATH, options.clone()).unwrap();
+
+        insert_test_data(&tx, &KEYS, &VALUES).await;
+        delete_keys(&tx, &vec![KEYS[1]]).await;
+        assert!(files_in_store()
+            .iter()
+            .all(|p| p.file_name().unwrap() == "entries.log"));
+        _store.close().await;
+
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+        let received_values = get_values_for_keys(&tx, KEYS.to_vec()).await;
+        for (i, (got, expected)) in received_values.into_iter().zip(VALUES).enumerate() {
+            let expected = (i != 1).then(|| expected.as_bytes().to_vec());
+            assert_eq!(got.unwrap(), expected);
+        }
+
+        clear_test_data(&tx).await;
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::Keys { resp }).await;
+        assert!(recv.await.unwrap().unwrap().is_empty());
+
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn every_layout_persists_sets_and_deletes() {
+        let long_key = "k".repeat(300);
+        let keys = [KEYS[0], KEYS[1], KEYS[2], KEYS[3], &long_key];
+        let values = [VALUES[0], VALUES[1], VALUES[2], VALUES[3], "long"];
+        let layouts = [
+            Layout::Flat,
+            Layout::Sharded { depth: 1, width: 4 },
+            Layout::default(),
+            Layout::Sharded { depth: 4, width: 2 },
+        ];
+        for layout in layouts {
+            let _ = std::fs::remove_dir_all(STORE_PATH);
+            let options = StoreOptions {
+                layout,
+                ..StoreOptions::default()
+            };
+            let (tx, rv) = mpsc::channel(1);
+            let _store = Store::with_options(rv, 2, STORE_PATH, options.clone()).unwrap();
+
+            insert_test_data(&tx, &keys, &values).await;
+            delete_keys(&tx, &vec![KEYS[1]]).await;
+            assert_eq!(files_in_store().len(), keys.len() - 1, "{:?}", layout);
+            _store.close().await;
+
+            let (tx, rv) = mpsc::channel(1);
+            let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+            let received_values = get_values_for_keys(&tx, keys.to_vec()).await;
+            for (i, (got, expected)) in received_values.into_iter().zip(values).enumerate() {
+                let expected = (i != 1).then(|| expected.as_bytes().to_vec());
+                assert_eq!(got.unwrap(), expected, "{:?}", layout);
+            }
+            let (resp, recv) = oneshot::channel();
+            let _ = tx.send(Action::Keys { resp }).await;
+            assert_eq!(recv.await.unwrap().unwrap().len(), keys.len() - 1);
+
+            clear_test_data(&tx).await;
+            assert_eq!(len(&tx).await, 0);
+            assert!(files_in_store().is_empty(), "{:?}", layout);
+            _store.close().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn store_refuses_a_directory_of_another_layout() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+        insert_test_data(&tx, &KEYS, &VALUES).await;
+        _store.close().await;
+
+        let others = [
+            (Storage::Files, Layout::Flat),
+            (Storage::Files, Layout::Sharded { depth: 1, width: 2 }),
+            (Storage::Log, Layout::default()),
+        ];
+        for (storage, layout) in others {
+            let options = StoreOptions {
+                storage,
+                layout,
+                ..StoreOptions::default()
+            };
+            let (_, rv) = mpsc::channel(1);
+            let err = Store::with_options(rv, 2, STORE_PATH, options).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+
+        let options = StoreOptions {
+            layout: Layout::Sharded { depth: 3, width: 3 },
+            ..StoreOptions::default()
+        };
+        let (_, rv) = mpsc::channel(1);
+        let err = Store::with_options(rv, 2, "other_db", options).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let _ = std::fs::remove_dir_all("other_db");
+
+        // the layout it was created with still opens it
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+        let received_values = get_values_for_keys(&tx, KEYS.to_vec()).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+        }
+        clear_test_data(&tx).await;
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn compact_reclaims_the_space_deletes_left() {
+        let keys: Vec<String> = (0..50).map(|i| format!("key-{}", i)).collect();
+        let keys: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+        let values = vec!["some value"; keys.len()];
+        for storage in [Storage::Files, Storage::Log] {
+            let _ = std::fs::remove_dir_all(STORE_PATH);
+            let options = StoreOptions {
+                storage,
+                ..StoreOptions::default()
+            };
+            let (tx, rv) = mpsc::channel(1);
+            let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+            insert_test_data(&tx, &keys, &values).await;
+            delete_keys(&tx, &keys[5..].to_vec()).await;
+
+            let stray = match storage {
+                Storage::Log => std::path::PathBuf::from(STORE_PATH).join("entries.log.compacting"),
+                _ => files_in_store()[0].with_extension("tmp.1.1"),
+            };
+            std::fs::write(&stray, "half written").unwrap();
+            let disk_use = || {
+                files_in_store()
+                    .iter()
+                    .map(|f| f.metadata().unwrap().len())
+                    .sum::<u64>()
+            };
+            let before = disk_use();
+
+            let (resp, recv) = oneshot::channel();
+            let _ = tx.send(Action::Compact { resp }).await;
+            let report = recv.await.unwrap().unwrap();
+            assert_eq!(report.tmp_files_removed, 1, "{:?}", storage);
+            assert!(!stray.exists());
+            assert_eq!(report.bytes_reclaimed, before - disk_use(), "{:?}", storage);
+            match storage {
+                Storage::Log => {
+                    assert_eq!(report.dirs_removed, 0);
+                    assert!(report.bytes_reclaimed > "half written".len() as u64);
+                }
+                _ => {
+                    assert!(report.dirs_removed > 0);
+                    assert_eq!(report.bytes_reclaimed, "half written".len() as u64);
+                }
+            }
+
+            let received_values = get_values_for_keys(&tx, keys.clone()).await;
+            for (i, got) in received_values.into_iter().enumerate() {
+                let expected = (i < 5).then(|| values[i].as_bytes().to_vec());
+                assert_eq!(got.unwrap(), expected, "{:?}", storage);
+            }
+
+            // nothing is left to reclaim
+            let (resp, recv) = oneshot::channel();
+            let _ = tx.send(Action::Compact { resp }).await;
+            assert_eq!(recv.await.unwrap().unwrap(), CompactReport::default());
+            clear_test_data(&tx).await;
+            _store.close().await;
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn memory_storage_serves_every_operation_without_touching_disk() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let options = StoreOptions {
+            storage: Storage::Memory,
+            max_entries: Some(2),
+            ..StoreOptions::default()
+        };
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::with_options(rv, 2, STORE_PATH, options.clone()).unwrap();
+
+        insert_test_data(&tx, &KEYS, &VALUES).await;
+        // entries evicted from the store's memory are still in the backend's
+        let received_values = get_values_for_keys(&tx, KEYS.to_vec()).await;
+        for (got, expected) in received_values.into_iter().zip(VALUES) {
+            assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+        }
+        assert_eq!(stats(&tx).await.disk_fallbacks, 2);
+
+        delete_keys(&tx, &vec![KEYS[0]]).await;
+        for (key, expected) in [(KEYS[0], false), (KEYS[1], true)] {
+            let (resp, recv) = oneshot::channel();
+            let key = key.to_string();
+            let _ = tx.send(Action::Exists { key, resp }).await;
+            assert_eq!(recv.await.unwrap().unwrap(), expected);
+        }
+
+        for _ in 0..3 {
+            let (resp, recv) = oneshot::channel();
+            let key = "counter".to_string();
+            let _ = tx
+                .send(Action::Incr {
+                    key,
+                    delta: 2,
+                    resp,
+                })
+                .await;
+            recv.await.unwrap().unwrap();
+        }
+        let (resp, recv) = oneshot::channel();
+        let prefix = "c".to_string();
+        let _ = tx.send(Action::ScanPrefix { prefix, resp }).await;
+        let pairs = recv.await.unwrap().unwrap();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!(&pairs[0].1[..], b"6");
+
+        let (resp, recv) = oneshot::channel();
+        let prefix = "h".to_string();
+        let _ = tx.send(Action::DelPrefix { prefix, resp }).await;
+        assert_eq!(recv.await.unwrap().unwrap(), 1);
+
+        assert!(!std::path::Path::new(STORE_PATH).exists());
+        _store.close().await;
+
+        // nothing outlives the store
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::with_options(rv, 2, STORE_PATH, options).unwrap();
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::Keys { resp }).await;
+        assert!(recv.await.unwrap().unwrap().is_empty());
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn second_store_on_a_path_fails_until_the_first_closes() {
+        let (_tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+
+        let (_, rv) = mpsc::channel(1);
+        let result = Store::with_options(rv, 2, STORE_PATH, StoreOptions::default());
+        let err = result.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::ResourceBusy);
+        assert_eq!(err.to_string(), "store at \"db\" is already in use");
+
+        // clearing the store keeps it locked
+        clear_test_data(&_tx).await;
+        let (_, rv) = mpsc::channel(1);
+        let result = Store::with_options(rv, 2, STORE_PATH, StoreOptions::default());
+        assert!(result.is_err());
+
+        _store.close().await;
+        let (_tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 2, STORE_PATH);
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn store_is_the_same_however_its_path_is_written() {
+        let paths = ["db", "db/", "./db"];
+        let options = StoreOptions {
+            durability: Durability::Full,
+            ..StoreOptions::default()
+        };
+
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::with_options(rv, 2, paths[0], options.clone()).unwrap();
+        clear_test_data(&tx).await;
+        insert_test_data(&tx, &KEYS[..2], &VALUES[..2]).await;
+
+        // every form of the path takes the same lock
+        for path in &paths[1..] {
+            let (_, rv) = mpsc::channel(1);
+            let result = Store::with_options(rv, 2, path, options.clone());
+            assert_eq!(result.err().unwrap().kind(), io::ErrorKind::ResourceBusy);
+        }
+        _store.close().await;
+
+        for (i, path) in paths.iter().enumerate().skip(1) {
+            let (tx, rv) = mpsc::channel(1);
+            let _store = Store::with_options(rv, 2, path, options.clone()).unwrap();
+            let received = get_values_for_keys(&tx, KEYS[..i + 1].to_vec()).await;
+            for (got, expected) in received.into_iter().zip(VALUES) {
+                assert_eq!(got.unwrap(), Some(expected.as_bytes().to_vec()));
+            }
+
+            let (resp, recv) = oneshot::channel();
+            let _ = tx.send(Action::Keys { resp }).await;
+            assert_eq!(recv.await.unwrap().unwrap().len(), i + 1);
+
+            // a key set under this form is seen under the others
+            insert_test_data(&tx, &KEYS[i + 1..i + 2], &VALUES[i + 1..i + 2]).await;
+            assert!(key_file(KEYS[i + 1]).exists());
+            _store.close().await;
+        }
+
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::with_options(rv, 2, paths[0], options).unwrap();
+        clear_test_data(&tx).await;
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn store_without_workers_fails() {
+        let (_, rv) = mpsc::channel(1);
+        let result = Store::with_options(rv, 0, STORE_PATH, StoreOptions::default());
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+
+        // the path was left alone, so a store with one worker opens it
+        let (tx, rv) = mpsc::channel(1);
+        let _store = Store::new(rv, 1, STORE_PATH);
+        insert_test_data(&tx, &KEYS[..1], &VALUES[..1]).await;
+        let received = get_values_for_keys(&tx, KEYS[..1].to_vec()).await;
+        assert_eq!(
+            received[0].as_ref().unwrap().as_deref(),
+            Some(VALUES[0].as_bytes())
+        );
+        clear_test_data(&tx).await;
+        _store.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn store_fails_when_its_path_cannot_be_created() {
+        let parent = "db_file";
+        std::fs::write(parent, b"not a directory").unwrap();
 
-impl Store {
-    pub(crate) fn new(
-        receiver: mpsc::Receiver<Action>,
-        num_of_handlers: usize,
-        store_path: &str,
-    ) -> Store {
-        assert!(num_of_handlers > 1);
+        for storage in [Storage::Files, Storage::Log] {
+            let (_, rv) = mpsc::channel(1);
+            let options = StoreOptions {
+                storage,
+                ..StoreOptions::default()
+            };
+            let result = Store::with_options(rv, 2, "db_file/sub", options);
+            let err = result.err().unwrap();
+            assert!(err
+                .to_string()
+                .starts_with("cannot create store directory \"db_file/sub\": "));
+        }
 
-        let mut store = Store {
-            db: Arc::new(Mutex::new(HashMap::new())),
-            handlers: Vec::with_capacity(num_of_handlers),
-            store_path: store_path.to_string(),
-            receiver_mutex_arc: Arc::new(Mutex::new(receiver)),
+        std::fs::remove_file(parent).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn worker_outlives_callers_that_stopped_waiting() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(1);
+        let mut _store = Store::new(rv, 1, STORE_PATH);
+
+        // keeps the set waiting until its caller has given up on it
+        let flushing = _store.shared.write_gate.write().await;
+        let (resp, recv) = oneshot::channel();
+        let action = Action::Set {
+            key: KEYS[0].to_string(),
+            value: VALUES[0].as_bytes().to_vec(),
+            expires_at: None,
+            idle_ttl: None,
+            resp,
         };
+        assert!(tx.send(action).await.is_ok());
+        drop(recv);
+        drop(flushing);
 
-        crate::fs::initialize_file_db(&store.store_path);
-        store.generate_handlers(num_of_handlers);
-        store
+        let received_values = get_values_for_keys(&tx, vec![KEYS[0]]).await;
+        assert_eq!(
+            received_values[0].as_ref().unwrap().as_deref(),
+            Some(VALUES[0].as_bytes())
+        );
+        assert_eq!(_store.workers_alive(), 1);
+
+        _store.close().await;
+        // a worker that panicked would hand its panic back here
+        let worker = _store.handlers.pop().unwrap();
+        assert!(worker.await.is_ok());
     }
 
-    pub async fn close(&self) {
-        for handler in &self.handlers {
-            handler.abort()
+    /// Counts the entries saved to it
+    struct CountingBackend {
+        inner: MemoryBackend,
+        saves: Arc<AtomicUsize>,
+    }
+
+    #[async_trait::async_trait]
+    impl StorageBackend for CountingBackend {
+        async fn save(&self, key: &str, entry: &Entry) -> io::Result<()> {
+            self.saves.fetch_add(1, Ordering::SeqCst);
+            self.inner.save(key, entry).await
         }
 
-        'outer: loop {
-            sleep(Duration::from_millis(200)).await;
+        async fn load(&self, key: &str) -> io::Result<Option<Entry>> {
+            self.inner.load(key).await
+        }
 
-            for handler in &self.handlers {
-                if !handler.is_finished() {
-                    continue 'outer;
-                }
-            }
+        async fn remove(&self, key: &str) -> io::Result<()> {
+            self.inner.remove(key).await
+        }
 
-            break;
+        async fn clear(&self) -> io::Result<()> {
+            self.inner.clear().await
         }
 
-        let receiver_mutex = Arc::clone(&self.receiver_mutex_arc);
-        receiver_mutex.lock().await.close();
-    }
+        async fn keys(&self) -> Vec<String> {
+            self.inner.keys().await
+        }
 
-    fn generate_handlers(&mut self, num_of_handlers: usize) {
-        for _ in 0..num_of_handlers {
-            let db_mutex = Arc::clone(&self.db);
-            let receiver_mutex = Arc::clone(&self.receiver_mutex_arc);
-            let store_path = self.store_path.clone();
+        fn load_all(&self) -> Vec<(String, Entry)> {
+            Vec::new()
+        }
 
-            let handler = tokio::spawn(async move {
-                loop {
-                    let mut rv = receiver_mutex.lock().await;
-                    let mut db = db_mutex.lock().await;
-                    let action = rv.recv().await.unwrap();
-
-                    match action {
-                        Action::Set { key, value, resp } => {
-                            let file_io = crate::fs::save_to_file(&store_path, &key, &value).await;
-                            match file_io {
-                                Err(v) => resp.send(Err(v)).unwrap(),
-                                Ok(()) => {
-                                    resp.send(Ok(db.insert(key, value))).unwrap();
-                                }
-                            }
-                        }
-                        Action::Get { key, resp } => {
-                            let value = match db.get(&key[..]) {
-                                Some(v) => Ok(Some(v.to_string())),
-                                None => crate::fs::get_from_file(&store_path, &key).await,
-                            };
+        async fn sync(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
 
-                            resp.send(value).unwrap()
-                        }
-                        Action::Del { key, resp } => {
-                            let file_io = crate::fs::remove_from_file(&store_path, &key).await;
-                            match file_io {
-                                Err(v) => resp.send(Err(v)).unwrap(),
-                                Ok(()) => {
-                                    let value = db.remove(&key[..]);
-                                    resp.send(Ok(value)).unwrap();
-                                }
-                            }
-                        }
-                        Action::Clear { resp } => {
-                            let file_io = crate::fs::clear_from_file(&store_path).await;
-                            let value = match file_io {
-                                Ok(()) => Ok(db.clear()),
-                                Err(err) => Err(err),
-                            };
-                            resp.send(value).unwrap()
-                        }
-                    };
-                }
-            });
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn queued_sets_to_one_key_are_written_once() {
+        let saves = Arc::new(AtomicUsize::new(0));
+        let backend = Box::new(CountingBackend {
+            inner: MemoryBackend::default(),
+            saves: Arc::clone(&saves),
+        });
+        let options = StoreOptions {
+            coalesce_sets: true,
+            ..StoreOptions::default()
+        };
+        let (tx, rv) = mpsc::channel(128);
+        let _store = Store::start(rv, 1, options, backend, None);
 
-            self.handlers.push(handler);
+        // holds the worker up on the first set while the others queue behind it
+        let flushing = _store.shared.write_gate.write().await;
+        let mut responses = Vec::new();
+        for i in 0..100 {
+            let (resp, recv) = oneshot::channel();
+            let action = Action::Set {
+                key: "hot".to_string(),
+                value: format!("v{}", i).into_bytes(),
+                expires_at: None,
+                idle_ttl: None,
+                resp,
+            };
+            assert!(tx.send(action).await.is_ok());
+            responses.push(recv);
         }
+        let (resp, read) = oneshot::channel();
+        let action = Action::Get {
+            key: "hot".to_string(),
+            resp,
+        };
+        assert!(tx.send(action).await.is_ok());
+        drop(flushing);
+
+        for (i, recv) in responses.into_iter().enumerate() {
+            let prev = recv.await.unwrap().unwrap();
+            let expected = (i > 0).then(|| format!("v{}", i - 1));
+            assert_eq!(prev.as_deref(), expected.as_ref().map(String::as_bytes));
+        }
+        let value = read.await.unwrap().unwrap();
+        assert_eq!(value.as_deref(), Some(&b"v99"[..]));
+        assert!(saves.load(Ordering::SeqCst) <= 2);
+
+        _store.close().await;
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serial_test::serial;
-    use tokio::sync::mpsc::Sender;
+    /// Refuses every save as a key whose file would lie outside the store
+    #[derive(Default)]
+    struct RefusingBackend(MemoryBackend);
 
-    const STORE_PATH: &str = "db";
-    const KEYS: [&str; 4] = ["hey", "hi", "yoo-hoo", "bonjour"];
-    const VALUES: [&str; 4] = ["English", "English", "Slang", "French"];
+    #[async_trait::async_trait]
+    impl StorageBackend for RefusingBackend {
+        async fn save(&self, key: &str, _entry: &Entry) -> io::Result<()> {
+            Err(crate::error::InvalidKey::error(key))
+        }
 
-    #[tokio::test(flavor = "multi_thread")]
-    #[serial]
-    async fn set_and_read_multiple_key_value_pairs() {
-        let (tx, rv) = mpsc::channel(1);
-        let _store = Store::new(rv, 2, STORE_PATH);
+        async fn load(&self, key: &str) -> io::Result<Option<Entry>> {
+            self.0.load(key).await
+        }
 
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
+        async fn remove(&self, key: &str) -> io::Result<()> {
+            self.0.remove(key).await
+        }
 
-        insert_test_data(&tx, &keys, &values).await;
-        let received_values = get_values_for_keys(&tx, keys).await;
+        async fn clear(&self) -> io::Result<()> {
+            self.0.clear().await
+        }
 
-        let expected_values: Vec<io::Result<Option<String>>> = values
-            .into_iter()
-            .map(|v| Ok(Some(v.to_string())))
-            .collect();
+        async fn keys(&self) -> Vec<String> {
+            self.0.keys().await
+        }
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+        fn load_all(&self) -> Vec<(String, Entry)> {
+            Vec::new()
         }
 
-        _store.close().await;
+        async fn sync(&self) -> io::Result<()> {
+            Ok(())
+        }
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn set_and_delete_multiple_key_value_pairs() {
-        let (tx, rv) = mpsc::channel(1);
-        let _store = Store::new(rv, 2, STORE_PATH);
-
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
-        let keys_to_delete = keys[2..].to_vec();
+    async fn coalesced_sets_fail_as_a_single_set_does() {
+        let options = StoreOptions {
+            coalesce_sets: true,
+            ..StoreOptions::default()
+        };
+        let (tx, rv) = mpsc::channel(128);
+        let backend = Box::<RefusingBackend>::default();
+        let _store = Store::start(rv, 1, options, backend, None);
+        let set = |value: &str| {
+            let (resp, recv) = oneshot::channel();
+            let action = Action::Set {
+                key: "hot".to_string(),
+                value: value.as_bytes().to_vec(),
+                expires_at: None,
+                idle_ttl: None,
+                resp,
+            };
+            (action, recv)
+        };
+        let is_invalid_key = |e: io::Error| matches!(crate::CacheError::from(e), crate::CacheError::InvalidKey { key } if key == "hot");
 
-        insert_test_data(&tx, &keys, &values).await;
-        delete_keys(&tx, &keys_to_delete).await;
+        let (action, recv) = set("alone");
+        assert!(tx.send(action).await.is_ok());
+        assert!(is_invalid_key(recv.await.unwrap().unwrap_err()));
 
-        let received_values = get_values_for_keys(&tx, keys.clone()).await;
-        let mut expected_values: Vec<io::Result<Option<String>>> = values[..2]
-            .into_iter()
-            .map(|v| Ok(Some(v.to_string())))
-            .collect();
-        for _ in 0..keys_to_delete.len() {
-            expected_values.push(Ok(None));
+        // holds the worker up on the first set while the others queue behind it
+        let flushing = _store.shared.write_gate.write().await;
+        let mut responses = Vec::new();
+        for i in 0..10 {
+            let (action, recv) = set(&format!("v{}", i));
+            assert!(tx.send(action).await.is_ok());
+            responses.push(recv);
         }
+        drop(flushing);
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+        for recv in responses {
+            assert!(is_invalid_key(recv.await.unwrap().unwrap_err()));
         }
-
         _store.close().await;
     }
 
-    #[tokio::test(flavor = "multi_thread")]
-    #[serial]
-    async fn set_and_clear() {
-        let (tx, rv) = mpsc::channel(1);
-        let _store = Store::new(rv, 2, STORE_PATH);
+    /// Takes its time over every read and write, noting how many reads ran at once
+    /// and whether a write ever ran alongside a read
+    #[derive(Default)]
+    struct SlowBackend {
+        inner: MemoryBackend,
+        reading: AtomicUsize,
+        most_reading: Arc<AtomicUsize>,
+        overlaps: Arc<AtomicUsize>,
+    }
 
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
+    #[async_trait::async_trait]
+    impl StorageBackend for SlowBackend {
+        async fn save(&self, key: &str, entry: &Entry) -> io::Result<()> {
+            if self.reading.load(Ordering::SeqCst) > 0 {
+                self.overlaps.fetch_add(1, Ordering::SeqCst);
+            }
+            sleep(Duration::from_millis(100)).await;
+            if self.reading.load(Ordering::SeqCst) > 0 {
+                self.overlaps.fetch_add(1, Ordering::SeqCst);
+            }
+            self.inner.save(key, entry).await
+        }
 
-        insert_test_data(&tx, &keys, &values).await;
-        clear_test_data(&tx).await;
+        async fn load(&self, key: &str) -> io::Result<Option<Entry>> {
+            let reading = self.reading.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most_reading.fetch_max(reading, Ordering::SeqCst);
+            sleep(Duration::from_millis(100)).await;
+            self.reading.fetch_sub(1, Ordering::SeqCst);
+            self.inner.load(key).await
+        }
 
-        let received_values = get_values_for_keys(&tx, keys.clone()).await;
-        let expected_values: Vec<io::Result<Option<String>>> =
-            keys.into_iter().map(|_| Ok(None)).collect();
+        async fn remove(&self, key: &str) -> io::Result<()> {
+            if self.reading.load(Ordering::SeqCst) > 0 {
+                self.overlaps.fetch_add(1, Ordering::SeqCst);
+            }
+            self.inner.remove(key).await
+        }
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+        async fn clear(&self) -> io::Result<()> {
+            self.inner.clear().await
         }
 
-        _store.close().await;
+        async fn keys(&self) -> Vec<String> {
+            self.inner.keys().await
+        }
+
+        fn load_all(&self) -> Vec<(String, Entry)> {
+            Vec::new()
+        }
+
+        async fn sync(&self) -> io::Result<()> {
+            Ok(())
+        }
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn persist_to_file() {
-        let (tx, rv) = mpsc::channel(1);
-        let _store = Store::new(rv, 2, STORE_PATH);
-
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
+    async fn reads_of_a_key_run_together_and_writes_alone() {
+        let backend = SlowBackend::default();
+        let (most_reading, overlaps) = (
+            Arc::clone(&backend.most_reading),
+            Arc::clone(&backend.overlaps),
+        );
+        // on disk alone, so that every read waits on the backend
+        let entry = Entry {
+            value: b"old".to_vec().into(),
+            expires_at: None,
+            idle_ttl: None,
+        };
+        backend.inner.save("hot", &entry).await.unwrap();
+        let (tx, rv) = mpsc::channel(64);
+        let _store = Store::start(rv, 8, StoreOptions::default(), Box::new(backend), None);
 
-        insert_test_data(&tx, &keys, &values).await;
-        // Close the store
+        let mut gets = Vec::new();
+        for _ in 0..8 {
+            let (resp, recv) = oneshot::channel();
+            let key = "hot".to_string();
+            let _ = tx.send(Action::Get { key, resp }).await;
+            gets.push(recv);
+        }
+        let (resp, set) = oneshot::channel();
+        let action = Action::Set {
+            key: "hot".to_string(),
+            value: b"new".to_vec(),
+            expires_at: None,
+            idle_ttl: None,
+            resp,
+        };
+        let _ = tx.send(action).await;
+        let (resp, exists) = oneshot::channel();
+        let key = "hot".to_string();
+        let _ = tx.send(Action::Exists { key, resp }).await;
 
+        for recv in gets {
+            assert_eq!(recv.await.unwrap().unwrap().as_deref(), Some(&b"old"[..]));
+        }
+        set.await.unwrap().unwrap();
+        assert!(exists.await.unwrap().unwrap());
+        assert!(most_reading.load(Ordering::SeqCst) > 1);
+        assert_eq!(overlaps.load(Ordering::SeqCst), 0);
         _store.close().await;
+    }
 
-        // Open new store instance
-        let (tx, rv) = mpsc::channel(1);
-        let _store = Store::new(rv, 2, STORE_PATH);
-
-        let received_values = get_values_for_keys(&tx, keys.clone()).await;
-        let expected_values: Vec<io::Result<Option<String>>> = values
-            .into_iter()
-            .map(|v| Ok(Some(v.to_string())))
-            .collect();
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn reads_that_write_wait_for_the_other_reads() {
+        let backend = SlowBackend::default();
+        let overlaps = Arc::clone(&backend.overlaps);
+        // each get of the first slides its expiry, and the second has a file to delete
+        let idle = Entry {
+            value: b"idle".to_vec().into(),
+            expires_at: Some(SystemTime::now() + Duration::from_secs(60)),
+            idle_ttl: Some(Duration::from_secs(60)),
+        };
+        let expired = Entry {
+            value: b"gone".to_vec().into(),
+            expires_at: Some(SystemTime::UNIX_EPOCH),
+            idle_ttl: None,
+        };
+        backend.inner.save("idle", &idle).await.unwrap();
+        backend.inner.save("expired", &expired).await.unwrap();
+        let (tx, rv) = mpsc::channel(64);
+        let _store = Store::start(rv, 8, StoreOptions::default(), Box::new(backend), None);
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+        // one key at a time, as the backend counts the reads of every key
+        for (key, expected) in [("idle", Some(&b"idle"[..])), ("expired", None)] {
+            let mut gets = Vec::new();
+            for _ in 0..4 {
+                let (resp, recv) = oneshot::channel();
+                let _ = tx
+                    .send(Action::Get {
+                        key: key.to_string(),
+                        resp,
+                    })
+                    .await;
+                gets.push(recv);
+            }
+            for recv in gets {
+                assert_eq!(recv.await.unwrap().unwrap().as_deref(), expected);
+            }
         }
-
+        assert_eq!(overlaps.load(Ordering::SeqCst), 0);
+        assert_eq!(_store.shared.backend.keys().await, ["idle"]);
         _store.close().await;
     }
 
     #[tokio::test(flavor = "multi_thread")]
     #[serial]
-    async fn persist_to_file_after_delete() {
-        let (tx, rv) = mpsc::channel(1);
+    async fn close_finishes_queued_actions() {
+        let _ = std::fs::remove_dir_all(STORE_PATH);
+        let (tx, rv) = mpsc::channel(16);
         let _store = Store::new(rv, 2, STORE_PATH);
 
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
-        let keys_to_delete = keys[2..].to_vec();
-
-        insert_test_data(&tx, &keys, &values).await;
-        delete_keys(&tx, &keys_to_delete).await;
+        let keys: Vec<String> = (0..16).map(|i| format!("queued{}", i)).collect();
+        let mut responses = Vec::new();
+        for key in &keys {
+            let (resp, recv) = oneshot::channel();
+            let action = Action::Set {
+                key: key.clone(),
+                value: key.as_bytes().to_vec(),
+                expires_at: None,
+                idle_ttl: None,
+                resp,
+            };
+            assert!(tx.send(action).await.is_ok());
+            responses.push(recv);
+        }
 
         // Close the store
-
         _store.close().await;
+        for recv in responses {
+            assert!(recv.await.unwrap().is_ok());
+        }
+        let (resp, _) = oneshot::channel();
+        assert!(tx.send(Action::Keys { resp }).await.is_err());
 
         // Open new store instance
         let (tx, rv) = mpsc::channel(1);
         let _store = Store::new(rv, 2, STORE_PATH);
 
+        let keys: Vec<&str> = keys.iter().map(String::as_str).collect();
         let received_values = get_values_for_keys(&tx, keys.clone()).await;
-        let mut expected_values: Vec<io::Result<Option<String>>> = values[..2]
-            .into_iter()
-            .map(|v| Ok(Some(v.to_string())))
-            .collect();
-        for _ in 0..keys_to_delete.len() {
-            expected_values.push(Ok(None));
-        }
-
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+        for (got, k) in received_values.into_iter().zip(keys) {
+            assert_eq!(got.unwrap(), Some(k.as_bytes().to_vec()));
         }
 
         _store.close().await;
     }
 
-    #[tokio::test(flavor = "multi_thread")]
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
     #[serial]
-    async fn persist_to_file_after_clear() {
+    async fn each_action_runs_in_its_own_span() {
+        let spans = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let _guard = tracing::subscriber::set_default(SpanRecorder {
+            spans: spans.clone(),
+        });
+
         let (tx, rv) = mpsc::channel(1);
         let _store = Store::new(rv, 2, STORE_PATH);
 
-        let keys = KEYS.to_vec();
-        let values = VALUES.to_vec();
+        insert_test_data(&tx, &KEYS[..1], &VALUES[..1]).await;
+        _store.close().await;
 
-        insert_test_data(&tx, &keys, &values).await;
-        clear_test_data(&tx).await;
+        let spans = spans.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert!(spans[0].starts_with("action op=\"set\" key=\"hey\" worker="));
+    }
 
-        // Close the store
+    /// Records the name and fields of every span opened while it is the default subscriber
+    #[cfg(feature = "tracing")]
+    struct SpanRecorder {
+        spans: Arc<std::sync::Mutex<Vec<String>>>,
+    }
 
-        _store.close().await;
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
 
-        // Open new store instance
-        let (tx, rv) = mpsc::channel(1);
-        let _store = Store::new(rv, 2, STORE_PATH);
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            struct Fields(String);
 
-        let received_values = get_values_for_keys(&tx, keys.clone()).await;
-        let expected_values: Vec<io::Result<Option<String>>> =
-            keys.into_iter().map(|_| Ok(None)).collect();
+            impl tracing::field::Visit for Fields {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    self.0.push_str(&format!(" {}={:?}", field.name(), value));
+                }
+            }
 
-        for (got, expected) in received_values.into_iter().zip(expected_values) {
-            assert_eq!(got.unwrap(), expected.unwrap());
+            let mut fields = Fields(span.metadata().name().to_string());
+            span.record(&mut fields);
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(fields.0);
+            tracing::span::Id::from_u64(spans.len() as u64)
         }
 
-        _store.close().await;
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, _: &tracing::Event<'_>) {}
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
     }
 
-    #[tokio::test(flavor = "multi_thread")]
-    #[serial]
-    async fn close_aborts_tasks() {
-        let (_, rv) = mpsc::channel(1);
-        let _store = Store::new(rv, 2, STORE_PATH);
+    async fn len(tx: &Sender<Action>) -> usize {
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::Len { resp }).await;
+        recv.await.unwrap().unwrap()
+    }
 
-        for handler in &_store.handlers {
-            assert!(!handler.is_finished())
-        }
+    async fn exists_many(tx: &Sender<Action>, keys: Vec<String>) -> Vec<bool> {
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::ExistsMany { keys, resp }).await;
+        recv.await.unwrap().unwrap()
+    }
 
-        // Close the store
-        _store.close().await;
+    async fn stats(tx: &Sender<Action>) -> CacheStats {
+        let (resp, recv) = oneshot::channel();
+        let _ = tx.send(Action::Stats { resp }).await;
+        recv.await.unwrap().unwrap()
+    }
 
-        for handler in &_store.handlers {
-            assert!(handler.is_finished())
+    fn key_file(key: &str) -> std::path::PathBuf {
+        crate::fs::key_to_path(STORE_PATH, key, &StoreOptions::default())
+    }
+
+    fn write_key_file(key: &str, content: &str) {
+        let path = key_file(key);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn files_in_store() -> Vec<std::path::PathBuf> {
+        let mut files = Vec::new();
+        let mut dirs = vec![std::path::PathBuf::from(STORE_PATH)];
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if matches!(
+                    path.file_name().unwrap().to_str(),
+                    Some(".lock" | ".layout")
+                ) {
+                    continue;
+                }
+                if path.is_dir() {
+                    dirs.push(path);
+                } else {
+                    files.push(path);
+                }
+            }
         }
+
+        files
     }
 
     async fn clear_test_data(tx: &Sender<Action>) {
@@ -351,25 +4807,34 @@ mod tests {
     async fn get_values_for_keys(
         tx: &Sender<Action>,
         keys: Vec<&str>,
-    ) -> Vec<io::Result<Option<String>>> {
+    ) -> Vec<io::Result<Option<Vec<u8>>>> {
         let mut received_values = Vec::with_capacity(keys.len());
 
         for k in keys {
             let key = k.to_string();
             let (resp, recv) = oneshot::channel();
             let _ = tx.send(Action::Get { key, resp }).await;
-            let _ = &received_values.push(recv.await.unwrap());
+            let value = recv.await.unwrap().map(|v| v.map(|v| v.to_vec()));
+            let _ = &received_values.push(value);
         }
 
         received_values
     }
 
-    async fn insert_test_data(tx: &Sender<Action>, keys: &Vec<&str>, values: &Vec<&str>) {
-        for (k, v) in keys.clone().into_iter().zip(values.clone()) {
+    async fn insert_test_data(tx: &Sender<Action>, keys: &[&str], values: &[&str]) {
+        for (k, v) in keys.iter().zip(values) {
             let key = k.to_string();
-            let value = v.to_string();
+            let value = v.as_bytes().to_vec();
             let (resp, recv) = oneshot::channel();
-            let _ = tx.send(Action::Set { value, key, resp }).await;
+            let _ = tx
+                .send(Action::Set {
+                    value,
+                    key,
+                    expires_at: None,
+                    idle_ttl: None,
+                    resp,
+                })
+                .await;
             let _ = recv.await;
         }
     }
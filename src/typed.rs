@@ -0,0 +1,374 @@
+use crate::client::ClientHandle;
+use crate::error::CacheError;
+use crate::store::Format;
+use serde::de::value::StrDeserializer;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::error::Error;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+/// A view of a [`ClientHandle`] that stores values of type `V`, encoded in the
+/// client's [`Format`].
+///
+/// Got from [`ClientHandle::typed`]. Setting a value that the format cannot
+/// encode fails with [`CacheError::Serialization`], and reading a key that holds
+/// something other than a `V` in that format with [`CacheError::Deserialization`],
+/// both naming the key
+pub struct TypedClient<V> {
+    handle: ClientHandle,
+    format: Format,
+    value_type: PhantomData<fn() -> V>,
+}
+
+impl<V> Clone for TypedClient<V> {
+    fn clone(&self) -> Self {
+        TypedClient::new(self.handle.clone(), self.format)
+    }
+}
+
+impl<V> TypedClient<V> {
+    pub(crate) fn new(handle: ClientHandle, format: Format) -> TypedClient<V> {
+        TypedClient {
+            handle,
+            format,
+            value_type: PhantomData,
+        }
+    }
+}
+
+impl<V: Serialize + DeserializeOwned> TypedClient<V> {
+    pub async fn set(&self, key: impl AsRef<str>, value: &V) -> Result<(), CacheError> {
+        let key = key.as_ref();
+        let value = self.encode(key, value)?;
+        self.handle.set(key.to_string(), value).await?;
+        Ok(())
+    }
+
+    /// Sets a value that is treated as missing once `ttl` has elapsed
+    pub async fn set_with_ttl(
+        &self,
+        key: impl AsRef<str>,
+        value: &V,
+        ttl: Duration,
+    ) -> Result<(), CacheError> {
+        let key = key.as_ref();
+        let value = self.encode(key, value)?;
+        self.handle
+            .set_with_ttl(key.to_string(), value, ttl)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get(&self, key: impl AsRef<str>) -> Result<Option<V>, CacheError> {
+        let key = key.as_ref();
+        match self.handle.get(key).await? {
+            Some(v) => self.decode(key, &v).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the value at `key`, or loads it with `load`, stores it and returns it
+    /// if the key is absent. The loaded value is serialized once, to be stored, and
+    /// handed back as it is rather than read back.
+    ///
+    /// An error from `load` is returned as [`CacheError::Loader`], and nothing is
+    /// stored. Unlike [`ClientHandle::get_or_insert_with`], concurrent callers
+    /// missing the same key each run their own `load`
+    pub async fn get_or_load<F, Fut, E>(
+        &self,
+        key: impl AsRef<str>,
+        load: F,
+    ) -> Result<V, CacheError>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+        E: Into<Box<dyn Error + Send + Sync>>,
+    {
+        let key = key.as_ref();
+        if let Some(value) = self.get(key).await? {
+            return Ok(value);
+        }
+
+        let value = load().await.map_err(|e| CacheError::Loader(e.into()))?;
+        self.set(key, &value).await?;
+        Ok(value)
+    }
+
+    /// Deletes `key`, returning the value it held
+    pub async fn delete(&self, key: impl AsRef<str>) -> Result<Option<V>, CacheError> {
+        let key = key.as_ref();
+        match self.handle.delete(key).await? {
+            Some(v) => self.decode(key, &v).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn encode(&self, key: &str, value: &V) -> Result<Vec<u8>, CacheError> {
+        encode(self.format, value).map_err(|source| CacheError::Serialization {
+            key: Some(key.to_string()),
+            source,
+        })
+    }
+
+    fn decode(&self, key: &str, value: &[u8]) -> Result<V, CacheError> {
+        decode(self.format, value).map_err(|source| CacheError::Deserialization {
+            key: key.to_string(),
+            source,
+        })
+    }
+}
+
+/// An error of one of the formats, before the typed client names the key it hit
+type FormatError = Box<dyn Error + Send + Sync>;
+
+fn encode<V: Serialize>(format: Format, value: &V) -> Result<Vec<u8>, FormatError> {
+    match format {
+        Format::Raw => match serde_json::to_value(value)? {
+            serde_json::Value::String(s) => Ok(s.into_bytes()),
+            _ => Err("raw values must serialize to a string".into()),
+        },
+        Format::Json => Ok(serde_json::to_vec(value)?),
+        #[cfg(feature = "bincode")]
+        Format::Bincode => Ok(bincode::serialize(value)?),
+    }
+}
+
+fn decode<V: DeserializeOwned>(format: Format, value: &[u8]) -> Result<V, FormatError> {
+    match format {
+        Format::Raw => {
+            let value = std::str::from_utf8(value)?;
+            let deserializer: StrDeserializer<'_, serde::de::value::Error> =
+                StrDeserializer::new(value);
+            Ok(V::deserialize(deserializer)?)
+        }
+        Format::Json => Ok(serde_json::from_slice(value)?),
+        #[cfg(feature = "bincode")]
+        Format::Bincode => Ok(bincode::deserialize(value)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Client;
+    use serde::Deserialize;
+    use serial_test::serial;
+
+    const STORE_PATH: &str = "client_db";
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Address {
+        city: String,
+        zip: Option<String>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u8,
+        tags: Vec<String>,
+        addresses: Vec<Address>,
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn nested_values_round_trip() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        let users = client.typed::<User>();
+
+        let user = User {
+            name: "Ada".to_string(),
+            age: 36,
+            tags: vec!["admin".to_string()],
+            addresses: vec![Address {
+                city: "London".to_string(),
+                zip: None,
+            }],
+        };
+        users.set("user:1", &user).await.unwrap();
+
+        assert_eq!(users.get("user:1").await.unwrap(), Some(user));
+        assert_eq!(users.get("user:2").await.unwrap(), None);
+
+        client
+            .set_str("user:2".to_string(), "not json".to_string())
+            .await
+            .unwrap();
+        assert!(matches!(
+            users.get("user:2").await,
+            Err(CacheError::Deserialization { .. })
+        ));
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn failures_name_the_key_and_the_direction() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+
+        // a user stored before its type lost and renamed fields
+        #[derive(Debug, Serialize, Deserialize)]
+        struct UserV2 {
+            full_name: String,
+        }
+        let user = User {
+            name: "Ada".to_string(),
+            age: 36,
+            tags: Vec::new(),
+            addresses: Vec::new(),
+        };
+        client.typed::<User>().set("user:1", &user).await.unwrap();
+        let err = client.typed::<UserV2>().get("user:1").await.unwrap_err();
+        match &err {
+            CacheError::Deserialization { key, source } => {
+                assert_eq!(key, "user:1");
+                assert!(source.to_string().contains("full_name"));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+        assert!(err.to_string().contains("\"user:1\""));
+        // the value is left as it was
+        assert!(client.contains_key("user:1").await.unwrap());
+
+        // JSON object keys must be strings
+        let by_pair = client.typed::<std::collections::BTreeMap<(u8, u8), u8>>();
+        let value = [((1, 2), 3)].into_iter().collect();
+        let err = by_pair.set("pairs", &value).await.unwrap_err();
+        assert!(matches!(
+            &err,
+            CacheError::Serialization { key: Some(key), .. } if key == "pairs"
+        ));
+        assert!(err.source().is_some());
+        assert!(!client.contains_key("pairs").await.unwrap());
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn get_or_load_loads_each_missing_key_once() {
+        let mut client = Client::new(STORE_PATH, 2).unwrap();
+        client.clear().await.unwrap();
+        let users = client.typed::<User>();
+        let loaded = std::sync::atomic::AtomicUsize::new(0);
+        let load = |name: &str| {
+            loaded.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let user = User {
+                name: name.to_string(),
+                age: 36,
+                tags: Vec::new(),
+                addresses: Vec::new(),
+            };
+            async move { Ok::<_, std::io::Error>(user) }
+        };
+
+        for _ in 0..3 {
+            let user = users.get_or_load("user:1", || load("Ada")).await.unwrap();
+            assert_eq!(user.name, "Ada");
+        }
+        assert_eq!(loaded.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        let failed = users
+            .get_or_load("user:2", || async {
+                Err(std::io::Error::other("database is down"))
+            })
+            .await;
+        assert!(matches!(failed, Err(CacheError::Loader(_))));
+        assert_eq!(users.get("user:2").await.unwrap(), None);
+
+        // a stored value that does not deserialize is not reloaded
+        client
+            .set_str("user:3".to_string(), "not json".to_string())
+            .await
+            .unwrap();
+        let result = users.get_or_load("user:3", || load("Bob")).await;
+        assert!(matches!(result, Err(CacheError::Deserialization { .. })));
+        assert_eq!(loaded.load(std::sync::atomic::Ordering::SeqCst), 1);
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn each_format_round_trips_its_own_bytes() {
+        let user = User {
+            name: "Ada".to_string(),
+            age: 36,
+            tags: vec!["admin".to_string()],
+            addresses: Vec::new(),
+        };
+        let formats = [
+            Format::Json,
+            #[cfg(feature = "bincode")]
+            Format::Bincode,
+        ];
+
+        let mut stored = Vec::new();
+        for format in formats {
+            let mut client = Client::builder()
+                .path(STORE_PATH)
+                .workers(2)
+                .format(format)
+                .build()
+                .unwrap();
+            client.clear().await.unwrap();
+            let users = client.typed::<User>();
+
+            users.set("user:1", &user).await.unwrap();
+            assert_eq!(users.get("user:1").await.unwrap().as_ref(), Some(&user));
+            stored.push(client.get("user:1").await.unwrap().unwrap());
+
+            client.clear().await.unwrap();
+            client.close().await;
+        }
+
+        assert_eq!(
+            &*stored[0],
+            br#"{"name":"Ada","age":36,"tags":["admin"],"addresses":[]}"#
+        );
+        #[cfg(feature = "bincode")]
+        {
+            assert_eq!(stored[1].as_ref(), bincode::serialize(&user).unwrap());
+            assert!(stored[1].len() < stored[0].len());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[serial]
+    async fn raw_format_stores_strings_as_they_are() {
+        let mut client = Client::builder()
+            .path(STORE_PATH)
+            .workers(2)
+            .format(Format::Raw)
+            .build()
+            .unwrap();
+        client.clear().await.unwrap();
+        let names = client.typed::<String>();
+
+        names.set("name", &"Ada".to_string()).await.unwrap();
+        assert_eq!(names.get("name").await.unwrap(), Some("Ada".to_string()));
+        assert_eq!(&*client.get("name").await.unwrap().unwrap(), b"Ada");
+
+        let users = client.typed::<User>();
+        let user = User {
+            name: "Ada".to_string(),
+            age: 36,
+            tags: Vec::new(),
+            addresses: Vec::new(),
+        };
+        assert!(matches!(
+            users.set("user:1", &user).await,
+            Err(CacheError::Serialization { .. })
+        ));
+
+        client.clear().await.unwrap();
+        client.close().await;
+    }
+}